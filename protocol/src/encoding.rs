@@ -0,0 +1,257 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// How frames are written on the wire.
+///
+/// JSON text is what every client and server speaks. The binary encodings
+/// are smaller and cheaper to parse, and are only used once both ends have
+/// agreed on one while registering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+#[derive(Debug)]
+pub struct EncodingError(String);
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+impl Encoding {
+    /// Binary encodings, most preferred first.
+    pub const BINARY: [Encoding; 2] = [Encoding::MsgPack, Encoding::Cbor];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MsgPack => "msgpack",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Encoding::Json, Encoding::MsgPack, Encoding::Cbor]
+            .into_iter()
+            .find(|e| e.name() == name)
+    }
+
+    /// The first of the `offered` encoding names we know, JSON if none.
+    pub fn negotiate(offered: &[String]) -> Self {
+        offered
+            .iter()
+            .find_map(|name| Self::from_name(name))
+            .unwrap_or(Encoding::Json)
+    }
+
+    pub fn is_binary(self) -> bool {
+        self != Encoding::Json
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, EncodingError> {
+        let error =
+            |e: &dyn fmt::Display| EncodingError(format!("can't encode {}: {}", self.name(), e));
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|e| error(&e)),
+            // Named fields rather than arrays, so optional fields can be
+            // added or left out like they can in JSON.
+            Encoding::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| error(&e)),
+            Encoding::Cbor => serde_cbor::to_vec(value).map_err(|e| error(&e)),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, EncodingError> {
+        let error =
+            |e: &dyn fmt::Display| EncodingError(format!("malformed {}: {}", self.name(), e));
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|e| error(&e)),
+            Encoding::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| error(&e)),
+            Encoding::Cbor => serde_cbor::from_slice(bytes).map_err(|e| error(&e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Attachment, AttachmentField, BotIdentity, Button, ButtonStyle, MessageData, MsgTypes,
+        ReadData, Sealed, SearchResultsData, WebSocketMessage,
+    };
+    use proptest::prelude::*;
+
+    const ALL: [Encoding; 3] = [Encoding::Json, Encoding::MsgPack, Encoding::Cbor];
+
+    fn msg_type() -> impl Strategy<Value = MsgTypes> {
+        prop_oneof![
+            Just(MsgTypes::Users),
+            Just(MsgTypes::Register),
+            Just(MsgTypes::Message),
+            Just(MsgTypes::Read),
+            Just(MsgTypes::Search),
+            Just(MsgTypes::SearchResults),
+            Just(MsgTypes::Ping),
+            Just(MsgTypes::Pong),
+            Just(MsgTypes::Encoding),
+            Just(MsgTypes::Typing),
+            Just(MsgTypes::SlowDown),
+            Just(MsgTypes::Topic),
+            Just(MsgTypes::RegisterBot),
+            Just(MsgTypes::BotMessage),
+            Just(MsgTypes::Interaction),
+            Just(MsgTypes::CreateWebhook),
+            Just(MsgTypes::RevokeWebhook),
+            Just(MsgTypes::ListWebhooks),
+            Just(MsgTypes::Webhooks),
+            Just(MsgTypes::Moderate),
+            Just(MsgTypes::Roles),
+            Just(MsgTypes::Removed),
+            Just(MsgTypes::Muted),
+            Just(MsgTypes::Unmuted),
+            Just(MsgTypes::Kicked),
+            Just(MsgTypes::ModerationLog),
+            Just(MsgTypes::Report),
+            Just(MsgTypes::Reports),
+            Just(MsgTypes::PublicKey),
+            Just(MsgTypes::Sealed),
+            Just(MsgTypes::Profile),
+            Just(MsgTypes::Profiles),
+            Just(MsgTypes::Session),
+            Just(MsgTypes::Refused),
+        ]
+    }
+
+    prop_compose! {
+        fn frame()(
+            message_type in msg_type(),
+            data in proptest::option::of(".*"),
+            data_array in proptest::option::of(proptest::collection::vec(".*", 0..4)),
+        ) -> WebSocketMessage {
+            WebSocketMessage { message_type, data_array, data }
+        }
+    }
+
+    prop_compose! {
+        fn attachment()(
+            title in ".*",
+            text in ".*",
+            color in proptest::option::of("#[0-9a-f]{6}"),
+            fields in proptest::collection::vec((".*", ".*", any::<bool>()), 0..3),
+            buttons in proptest::collection::vec(("[a-z]{1,8}", ".*", any::<bool>()), 0..3),
+        ) -> Attachment {
+            Attachment {
+                title,
+                text,
+                color,
+                fields: fields
+                    .into_iter()
+                    .map(|(name, value, short)| AttachmentField { name, value, short })
+                    .collect(),
+                buttons: buttons
+                    .into_iter()
+                    .map(|(id, label, primary)| Button {
+                        id,
+                        label,
+                        style: if primary { ButtonStyle::Primary } else { ButtonStyle::Default },
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    prop_compose! {
+        fn message()(
+            id in any::<u64>(),
+            room in "[a-z]{1,8}",
+            from in ".*",
+            to in proptest::option::of(".*"),
+            message in ".*",
+            timestamp in 0.0..1e13f64,
+            bot in proptest::option::of((".*", ".*")),
+            attachments in proptest::collection::vec(attachment(), 0..2),
+            sealed in proptest::option::of(("[0-9a-f]*", "[0-9a-f]*", "[0-9a-f]*", "[0-9a-f]*")),
+        ) -> MessageData {
+            let bot = bot.map(|(name, description)| BotIdentity { name, description });
+            let sealed = sealed.map(|(sender_key, recipient_key, iv, ciphertext)| Box::new(Sealed {
+                sender_key,
+                recipient_key,
+                iv,
+                ciphertext,
+            }));
+            MessageData { id, room, from, to, message, timestamp, bot, attachments, sealed }
+        }
+    }
+
+    fn round_trips<T>(value: &T) -> Result<(), TestCaseError>
+    where
+        T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        for encoding in ALL {
+            let bytes = encoding.encode(value).unwrap();
+            let decoded: T = encoding.decode(&bytes).unwrap();
+            prop_assert_eq!(&decoded, value, "{}", encoding.name());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn frames_round_trip(frame in frame()) {
+            round_trips(&frame)?;
+        }
+
+        #[test]
+        fn messages_round_trip(m in message()) {
+            round_trips(&m)?;
+        }
+
+        #[test]
+        fn reads_round_trip(user in ".*", room in ".*", message_id in any::<u64>()) {
+            round_trips(&ReadData { user, room, message_id })?;
+        }
+
+        #[test]
+        fn search_results_round_trip(
+            query in ".*",
+            messages in proptest::collection::vec(message(), 0..4),
+        ) {
+            round_trips(&SearchResultsData { query, messages })?;
+        }
+    }
+
+    #[test]
+    fn negotiates_the_first_known_encoding() {
+        let offered = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Encoding::negotiate(&offered(&["bson", "cbor", "msgpack"])),
+            Encoding::Cbor
+        );
+        assert_eq!(Encoding::negotiate(&offered(&["bson"])), Encoding::Json);
+        assert_eq!(Encoding::negotiate(&[]), Encoding::Json);
+    }
+
+    #[test]
+    fn binary_is_smaller_than_json() {
+        let frame = WebSocketMessage {
+            message_type: MsgTypes::Users,
+            data_array: Some(vec!["alice".into(), "bob".into()]),
+            data: None,
+        };
+        let json = Encoding::Json.encode(&frame).unwrap().len();
+        for encoding in Encoding::BINARY {
+            assert!(
+                encoding.encode(&frame).unwrap().len() < json,
+                "{}",
+                encoding.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,875 @@
+//! Wire format shared by the Yewchat client and server.
+
+mod encoding;
+mod rate_limit;
+mod search;
+
+pub use encoding::{Encoding, EncodingError};
+pub use rate_limit::{TokenBucket, MESSAGES_PER_SECOND, MESSAGE_BURST, TYPING_INTERVAL_MS};
+pub use search::{is_image, SearchQuery, MAX_SEARCH_RESULTS};
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Room every message belongs to unless the server says otherwise.
+pub const DEFAULT_ROOM: &str = "general";
+/// Where on the server webhooks are posted, followed by the token.
+pub const WEBHOOK_PATH: &str = "/hooks/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MsgTypes {
+    /// Everyone online, by username in `dataArray`; `data` is their
+    /// [`PublicKeys`].
+    Users,
+    /// Signs in; `data` is the client's [`Credentials`], see
+    /// [`WebSocketMessage::register`]. Answered with `Refused` if the name
+    /// belongs to someone else.
+    Register,
+    /// Like `Register`, for a bot; `data` is its [`BotIdentity`]. Bots can't
+    /// take a name someone has registered, or one that's online.
+    RegisterBot,
+    /// A chat message. From the server `data` is a [`MessageData`]; from a
+    /// client it's just the text, see [`WebSocketMessage::chat`].
+    Message,
+    Read,
+    Search,
+    SearchResults,
+    /// Heartbeat; `data` is the send time, which the server echoes back in
+    /// a `Pong`.
+    Ping,
+    Pong,
+    /// Sent by the server in reply to a `Register` whose `dataArray` offered
+    /// binary encodings; `data` names the one it picked. Every frame after it
+    /// goes both ways in that encoding, as binary.
+    Encoding,
+    /// The sender is writing a message; `data` is a [`TypingData`]. Relayed
+    /// to everyone else, at most once per [`TYPING_INTERVAL_MS`] per user.
+    Typing,
+    /// Sent by the server when a client goes over its message rate; `data`
+    /// is how many milliseconds to wait before sending again. The message
+    /// that went over was dropped.
+    SlowDown,
+    /// A room's topic changed; `data` is a [`TopicData`]. The server sends
+    /// every topic that's set when a client registers.
+    Topic,
+    /// A message from a bot, which unlike people can attach cards; `data`
+    /// is a [`BotMessage`]. It reaches everyone as an ordinary `Message`.
+    BotMessage,
+    /// Someone pressed a button on a bot's message; `data` is an
+    /// [`InteractionData`]. Only the bot that posted the message gets it.
+    Interaction,
+    /// Asks for a webhook that posts into a room; `data` is a
+    /// [`WebhookRequest`]. Answered with `Webhooks`.
+    CreateWebhook,
+    /// Revokes a webhook; `data` is its id. Answered with `Webhooks`.
+    RevokeWebhook,
+    /// Asks for a room's webhooks; `data` is the room.
+    ListWebhooks,
+    /// A room's webhooks; `data` is a [`WebhookList`].
+    Webhooks,
+    /// Asks the server to act on a user or message; `data` is a
+    /// [`Moderation`]. Refused unless the sender's [`Role`] allows it.
+    Moderate,
+    /// Everyone whose role isn't [`Role::Member`]; `data` is a JSON object of
+    /// usernames to roles. Sent on register and whenever a role changes.
+    Roles,
+    /// A message was taken down; `data` is its id.
+    Removed,
+    /// Sent to someone who was muted; `data` is a [`Sanction`]. The server
+    /// drops their messages until it runs out.
+    Muted,
+    /// Sent to someone whose mute was lifted early.
+    Unmuted,
+    /// Sent to someone kicked or banned, just before the server closes their
+    /// connection; `data` is a [`Sanction`].
+    Kicked,
+    /// Entries of the moderation log; `data` is a list of
+    /// [`ModerationEntry`]. Moderators get the recent ones when they
+    /// register, and each new one as it happens.
+    ModerationLog,
+    /// Flags a message for the moderators; `data` is a [`ReportRequest`].
+    Report,
+    /// Every open report, as a list of [`Report`]; sent to moderators when
+    /// they register and whenever one is filed or closed. Reports are closed
+    /// with a [`ModAction::CloseReport`].
+    Reports,
+    /// Publishes the sender's key for encrypted direct messages; `data` is
+    /// the raw P-256 public key, in hex. Everyone gets it in the next `Users`.
+    PublicKey,
+    /// An encrypted direct message; `data` is a [`Sealed`], and `dataArray`
+    /// says where it goes like a `Message`'s, but must name a recipient. It
+    /// reaches them as a `Message` whose `sealed` is set and text is empty.
+    Sealed,
+    /// Replaces the sender's [`Profile`]; `data` is the new one. Refused if
+    /// it isn't [`Profile::validate`].
+    Profile,
+    /// Everyone's profiles; `data` is a JSON object of usernames to
+    /// [`Profile`]s. Sent on register and whenever one changes.
+    Profiles,
+    /// Sent to a client that registered a name nobody had used; `data` is
+    /// the token that proves it's theirs from now on, for the
+    /// [`Credentials`] of every later `Register`.
+    Session,
+    /// The server turned down a `Register`; `data` says why. It closes the
+    /// connection straight after.
+    Refused,
+}
+
+/// Public keys for encrypted direct messages, by username, in hex.
+pub type PublicKeys = BTreeMap<String, String>;
+
+/// Whether `key` looks like a raw, uncompressed P-256 public key in hex.
+pub fn is_public_key(key: &str) -> bool {
+    key.len() == 130 && key.starts_with("04") && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Envelope of every frame exchanged with the chat server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketMessage {
+    pub message_type: MsgTypes,
+    pub data_array: Option<Vec<String>>,
+    pub data: Option<String>,
+}
+
+impl WebSocketMessage {
+    /// A chat message from a client to `room`, or only to `to` (and back to
+    /// the sender) if that's set. Where it goes travels in `dataArray`, as
+    /// `[room]` or `[room, to]`.
+    pub fn chat(text: &str, room: &str, to: Option<&str>) -> Self {
+        let mut target = vec![room.to_string()];
+        target.extend(to.map(str::to_string));
+        Self {
+            message_type: MsgTypes::Message,
+            data: Some(text.to_string()),
+            data_array: Some(target),
+        }
+    }
+
+    /// Signs in as `name`, with the token the server gave out for it if
+    /// there is one.
+    pub fn register(name: &str, token: Option<&str>) -> Self {
+        let credentials = Credentials {
+            name: name.to_string(),
+            token: token.map(str::to_string),
+        };
+        Self {
+            message_type: MsgTypes::Register,
+            data: Some(serde_json::to_string(&credentials).unwrap()),
+            data_array: None,
+        }
+    }
+
+    /// The room and recipient of a chat message from a client. Clients that
+    /// don't say are talking to everyone in [`DEFAULT_ROOM`].
+    pub fn chat_target(&self) -> (String, Option<String>) {
+        let target = self.data_array.as_deref().unwrap_or_default();
+        let room = target
+            .first()
+            .filter(|room| !room.is_empty())
+            .cloned()
+            .unwrap_or_else(default_room);
+        (room, target.get(1).cloned())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageData {
+    #[serde(default)]
+    pub id: u64,
+    #[serde(default = "default_room")]
+    pub room: String,
+    pub from: String,
+    /// Recipient of a direct message; `None` for messages to the whole room.
+    #[serde(default)]
+    pub to: Option<String>,
+    pub message: String,
+    /// Milliseconds since the epoch; stamped on arrival if the server doesn't.
+    #[serde(default)]
+    pub timestamp: f64,
+    /// Set if a bot posted it.
+    #[serde(default)]
+    pub bot: Option<BotIdentity>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Set for an encrypted direct message, which has no plain text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Box<Sealed>>,
+}
+
+/// A direct message only its sender and recipient can read: AES-GCM under a
+/// key they agree on with ECDH, authenticating who it's from and to. Both
+/// public keys travel with it so either side can derive the key again. All
+/// fields are hex.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sealed {
+    pub sender_key: String,
+    pub recipient_key: String,
+    pub iv: String,
+    pub ciphertext: String,
+}
+
+/// Who someone registers as. Names go to whoever registers them first,
+/// who gets a token in a `Session` frame; after that only the token gets
+/// the name, and whatever role or sanction goes with it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Who a bot is. Bots register with this in place of a username, and their
+/// messages carry it so clients can tell them from people.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BotIdentity {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A card a bot attaches to a message, like a build result.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Attachment {
+    pub title: String,
+    #[serde(default)]
+    pub text: String,
+    /// Accent colour, as `#rgb` or `#rrggbb`.
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<AttachmentField>,
+    #[serde(default)]
+    pub buttons: Vec<Button>,
+}
+
+impl Attachment {
+    /// The accent colour, if it's one clients can safely put in a style.
+    pub fn accent(&self) -> Option<&str> {
+        let color = self.color.as_deref()?;
+        let hex = color.strip_prefix('#')?;
+        let valid = matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit());
+        valid.then_some(color)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentField {
+    pub name: String,
+    pub value: String,
+    /// Narrow enough to sit beside another short field.
+    #[serde(default)]
+    pub short: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Button {
+    /// Handed back to the bot when the button is pressed.
+    pub id: String,
+    pub label: String,
+    #[serde(default)]
+    pub style: ButtonStyle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ButtonStyle {
+    #[default]
+    Default,
+    Primary,
+    Danger,
+}
+
+/// What a bot sends to post a message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BotMessage {
+    #[serde(default = "default_room")]
+    pub room: String,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A button press on a bot's message. Clients send the message id and
+/// button; the server fills in who pressed it and where.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InteractionData {
+    pub message_id: u64,
+    pub button: String,
+    #[serde(default)]
+    pub user: String,
+    #[serde(default = "default_room")]
+    pub room: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadData {
+    /// Who read it; filled in by the server.
+    #[serde(default)]
+    pub user: String,
+    pub room: String,
+    pub message_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypingData {
+    pub user: String,
+    pub room: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicData {
+    pub room: String,
+    pub topic: String,
+    /// Who set it; filled in by the server.
+    #[serde(default)]
+    pub set_by: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResultsData {
+    pub query: String,
+    pub messages: Vec<MessageData>,
+}
+
+/// A secret URL that lets scripts post into a room without connecting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub id: String,
+    pub room: String,
+    /// Shown as the sender unless a post names one.
+    pub name: String,
+    /// Posting to [`WEBHOOK_PATH`] followed by this is all it takes.
+    pub token: String,
+    #[serde(default)]
+    pub created_by: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookRequest {
+    pub room: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookList {
+    pub room: String,
+    pub hooks: Vec<Webhook>,
+}
+
+/// The JSON body a script posts to a webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    #[serde(default)]
+    pub text: String,
+    /// Who to show as the sender, instead of the webhook's name.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// How someone's avatar is drawn from their name when they haven't
+/// uploaded one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarStyle {
+    #[default]
+    Identicon,
+    Initials,
+}
+
+impl AvatarStyle {
+    pub const ALL: [AvatarStyle; 2] = [AvatarStyle::Identicon, AvatarStyle::Initials];
+}
+
+/// What people tell others about themselves. Every field may be left empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Profile {
+    /// Shown in place of the username.
+    pub display_name: String,
+    pub avatar: AvatarStyle,
+    /// An uploaded avatar as a base64 `data:` URL of a PNG, JPEG or WebP
+    /// image, shown instead of the drawn one.
+    pub image: String,
+    pub bio: String,
+    pub pronouns: String,
+    /// An IANA time zone like `Asia/Jakarta`, to show their local time by.
+    pub time_zone: String,
+}
+
+impl Profile {
+    pub const DISPLAY_NAME_MAX: usize = 32;
+    pub const BIO_MAX: usize = 280;
+    pub const PRONOUNS_MAX: usize = 24;
+    pub const TIME_ZONE_MAX: usize = 64;
+    /// Everyone gets everyone's profile, so uploads are kept small.
+    pub const IMAGE_MAX: usize = 24 * 1024;
+    const IMAGE_TYPES: [&'static str; 3] = ["image/png", "image/jpeg", "image/webp"];
+
+    /// Checks every field fits, and that names and time zones are one line.
+    pub fn validate(&self) -> Result<(), String> {
+        let fields = [
+            ("display name", &self.display_name, Self::DISPLAY_NAME_MAX),
+            ("bio", &self.bio, Self::BIO_MAX),
+            ("pronouns", &self.pronouns, Self::PRONOUNS_MAX),
+            ("time zone", &self.time_zone, Self::TIME_ZONE_MAX),
+            ("avatar image", &self.image, Self::IMAGE_MAX),
+        ];
+        for (name, value, max) in fields {
+            if value.chars().count() > max {
+                return Err(format!("{} longer than {} characters", name, max));
+            }
+        }
+        if [&self.display_name, &self.pronouns]
+            .iter()
+            .any(|v| v.chars().any(char::is_control))
+        {
+            return Err("names and pronouns go on one line".to_string());
+        }
+        let zone_char = |c: char| c.is_ascii_alphanumeric() || "/_+-".contains(c);
+        if !self.time_zone.chars().all(zone_char) {
+            return Err(format!("{:?} isn't a time zone", self.time_zone));
+        }
+        if !self.image.is_empty() && !Self::is_image(&self.image) {
+            return Err("avatar images must be PNG, JPEG or WebP data URLs".to_string());
+        }
+        Ok(())
+    }
+
+    fn is_image(url: &str) -> bool {
+        let data = url
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(";base64,"));
+        let base64 = |c: char| c.is_ascii_alphanumeric() || "+/=".contains(c);
+        matches!(data, Some((kind, encoded))
+            if Self::IMAGE_TYPES.contains(&kind) && encoded.chars().all(base64))
+    }
+}
+
+/// What someone may do. Ordered, so an owner outranks a moderator, who
+/// outranks a member.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Member,
+    Moderator,
+    /// The first person to register. Hands out the moderator role.
+    Owner,
+}
+
+impl Role {
+    /// Whether someone with this role can kick, ban, mute or remove
+    /// messages of someone with `target`'s.
+    pub fn outranks(self, target: Role) -> bool {
+        self >= Role::Moderator && self > target
+    }
+}
+
+/// Something a moderator does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ModAction {
+    Kick {
+        user: String,
+    },
+    /// Kicks the user and refuses them when they come back.
+    Ban {
+        user: String,
+    },
+    Unban {
+        user: String,
+    },
+    /// Drops the user's messages for a while, or until lifted if `minutes`
+    /// isn't set.
+    Mute {
+        user: String,
+        #[serde(default)]
+        minutes: Option<u32>,
+    },
+    Unmute {
+        user: String,
+    },
+    /// Takes a message down. Anyone can remove their own; `user`, who wrote
+    /// it, is filled in by the server.
+    Remove {
+        #[serde(rename = "messageId")]
+        message_id: u64,
+        #[serde(default)]
+        user: String,
+    },
+    /// Only the owner can hand out roles, and there's only one owner.
+    SetRole {
+        user: String,
+        role: Role,
+    },
+    /// Closes a report, having dealt with it or decided there was nothing
+    /// to deal with. `user`, who was reported, is filled in by the server.
+    CloseReport {
+        #[serde(rename = "reportId")]
+        report_id: u64,
+        #[serde(default)]
+        user: String,
+        #[serde(default)]
+        dismissed: bool,
+    },
+}
+
+impl ModAction {
+    /// Who it's done to.
+    pub fn user(&self) -> &str {
+        match self {
+            ModAction::Kick { user }
+            | ModAction::Ban { user }
+            | ModAction::Unban { user }
+            | ModAction::Mute { user, .. }
+            | ModAction::Unmute { user }
+            | ModAction::Remove { user, .. }
+            | ModAction::SetRole { user, .. }
+            | ModAction::CloseReport { user, .. } => user,
+        }
+    }
+
+    /// What happened, to follow the moderator's name, like "muted bob for
+    /// 10 minutes".
+    pub fn describe(&self) -> String {
+        match self {
+            ModAction::Kick { user } => format!("kicked {}", user),
+            ModAction::Ban { user } => format!("banned {}", user),
+            ModAction::Unban { user } => format!("lifted the ban on {}", user),
+            ModAction::Mute {
+                user,
+                minutes: None,
+            } => format!("muted {}", user),
+            ModAction::Mute {
+                user,
+                minutes: Some(1),
+            } => format!("muted {} for a minute", user),
+            ModAction::Mute {
+                user,
+                minutes: Some(minutes),
+            } => {
+                format!("muted {} for {} minutes", user, minutes)
+            }
+            ModAction::Unmute { user } => format!("unmuted {}", user),
+            ModAction::Remove { user, .. } => format!("removed a message by {}", user),
+            ModAction::SetRole {
+                user,
+                role: Role::Member,
+            } => {
+                format!("made {} a member again", user)
+            }
+            ModAction::SetRole {
+                user,
+                role: Role::Moderator,
+            } => {
+                format!("made {} a moderator", user)
+            }
+            ModAction::SetRole {
+                user,
+                role: Role::Owner,
+            } => format!("made {} the owner", user),
+            ModAction::CloseReport {
+                user,
+                dismissed: false,
+                ..
+            } => {
+                format!("resolved a report about {}", user)
+            }
+            ModAction::CloseReport {
+                user,
+                dismissed: true,
+                ..
+            } => {
+                format!("dismissed a report about {}", user)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Moderation {
+    #[serde(flatten)]
+    pub action: ModAction,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// A line of the moderation log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModerationEntry {
+    pub id: u64,
+    pub timestamp: f64,
+    /// The moderator.
+    pub by: String,
+    #[serde(flatten)]
+    pub moderation: Moderation,
+}
+
+/// What a muted, kicked or banned user is told.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sanction {
+    pub by: String,
+    #[serde(default)]
+    pub reason: String,
+    /// When it runs out, in milliseconds since the epoch; `None` if it
+    /// doesn't.
+    #[serde(default)]
+    pub until: Option<f64>,
+    /// For `Kicked`: whether they're banned too.
+    #[serde(default)]
+    pub banned: bool,
+}
+
+/// Why a message was reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportCategory {
+    Spam,
+    Harassment,
+    /// Not suitable for the room, like explicit content.
+    Inappropriate,
+    #[default]
+    Other,
+}
+
+impl ReportCategory {
+    pub const ALL: [ReportCategory; 4] = [
+        ReportCategory::Spam,
+        ReportCategory::Harassment,
+        ReportCategory::Inappropriate,
+        ReportCategory::Other,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReportCategory::Spam => "Spam",
+            ReportCategory::Harassment => "Harassment",
+            ReportCategory::Inappropriate => "Inappropriate",
+            ReportCategory::Other => "Something else",
+        }
+    }
+}
+
+/// What a client sends to report a message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportRequest {
+    pub message_id: u64,
+    #[serde(default)]
+    pub category: ReportCategory,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// A report waiting for a moderator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub id: u64,
+    pub timestamp: f64,
+    pub reporter: String,
+    pub category: ReportCategory,
+    #[serde(default)]
+    pub note: String,
+    pub message: MessageData,
+    /// The messages before it in its room, oldest first, so moderators can
+    /// see what it was answering.
+    #[serde(default)]
+    pub context: Vec<MessageData>,
+}
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_use_the_wire_names() {
+        let frame = WebSocketMessage {
+            message_type: MsgTypes::SearchResults,
+            data_array: Some(vec!["alice".into()]),
+            data: None,
+        };
+        let json = serde_json::to_string(&frame).unwrap();
+        assert_eq!(
+            json,
+            r#"{"messageType":"searchresults","dataArray":["alice"],"data":null}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<WebSocketMessage>(&json).unwrap(),
+            frame
+        );
+
+        let read = ReadData {
+            user: "bob".into(),
+            room: "general".into(),
+            message_id: 7,
+        };
+        assert!(serde_json::to_string(&read)
+            .unwrap()
+            .contains(r#""messageId":7"#));
+    }
+
+    #[test]
+    fn optional_fields_can_be_left_out() {
+        let frame: WebSocketMessage =
+            serde_json::from_str(r#"{"messageType":"register"}"#).unwrap();
+        assert_eq!(frame.message_type, MsgTypes::Register);
+        assert_eq!((frame.data, frame.data_array), (None, None));
+
+        // What servers that predate ids, rooms and timestamps send.
+        let m: MessageData = serde_json::from_str(r#"{"from":"bob","message":"hi"}"#).unwrap();
+        assert_eq!(
+            (m.id, m.room.as_str(), m.to, m.timestamp),
+            (0, DEFAULT_ROOM, None, 0.0)
+        );
+    }
+
+    #[test]
+    fn chat_messages_carry_their_target() {
+        let dm = WebSocketMessage::chat("hi", "random", Some("bob"));
+        assert_eq!(dm.data.as_deref(), Some("hi"));
+        assert_eq!(
+            dm.chat_target(),
+            ("random".to_string(), Some("bob".to_string()))
+        );
+
+        let legacy = WebSocketMessage {
+            message_type: MsgTypes::Message,
+            data: Some("hi".into()),
+            data_array: None,
+        };
+        assert_eq!(legacy.chat_target(), (DEFAULT_ROOM.to_string(), None));
+    }
+
+    #[test]
+    fn registering_sends_the_token_only_if_there_is_one() {
+        let first = WebSocketMessage::register("alice", None);
+        assert_eq!(first.data.as_deref(), Some(r#"{"name":"alice"}"#));
+
+        let again = WebSocketMessage::register("alice", Some("9f2c"));
+        let credentials: Credentials = serde_json::from_str(&again.data.unwrap()).unwrap();
+        assert_eq!(credentials.token.as_deref(), Some("9f2c"));
+    }
+
+    #[test]
+    fn only_hex_colours_are_accents() {
+        let card = |color: &str| Attachment {
+            title: "build".into(),
+            color: Some(color.into()),
+            ..Attachment::default()
+        };
+        assert_eq!(card("#36a64f").accent(), Some("#36a64f"));
+        assert_eq!(card("#f00").accent(), Some("#f00"));
+        assert_eq!(card("red").accent(), None);
+        assert_eq!(card("#f00; background: url(x)").accent(), None);
+    }
+
+    #[test]
+    fn moderators_only_act_on_those_below_them() {
+        assert!(Role::Owner.outranks(Role::Moderator));
+        assert!(Role::Moderator.outranks(Role::Member));
+        assert!(!Role::Moderator.outranks(Role::Moderator));
+        assert!(!Role::Member.outranks(Role::Member));
+        assert!(!Role::Moderator.outranks(Role::Owner));
+    }
+
+    #[test]
+    fn moderation_is_tagged_by_action() {
+        let json = r#"{"action":"mute","user":"bob","minutes":10,"reason":"spam"}"#;
+        let moderation: Moderation = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            moderation.action,
+            ModAction::Mute {
+                user: "bob".into(),
+                minutes: Some(10)
+            }
+        );
+        assert_eq!(moderation.reason, "spam");
+        assert_eq!(moderation.action.describe(), "muted bob for 10 minutes");
+
+        let json = r#"{"action":"remove","messageId":7}"#;
+        let moderation: Moderation = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            moderation.action,
+            ModAction::Remove { message_id: 7, .. }
+        ));
+        let json = r#"{"action":"setrole","user":"carol","role":"moderator"}"#;
+        let moderation: Moderation = serde_json::from_str(json).unwrap();
+        assert_eq!(moderation.action.describe(), "made carol a moderator");
+    }
+
+    #[test]
+    fn profiles_must_fit() {
+        let profile = Profile {
+            display_name: "Alice L.".into(),
+            bio: "Likes \n new lines".into(),
+            time_zone: "America/Port-au-Prince".into(),
+            ..Profile::default()
+        };
+        assert_eq!(profile.validate(), Ok(()));
+        let long = Profile {
+            bio: "x".repeat(Profile::BIO_MAX + 1),
+            ..profile.clone()
+        };
+        assert!(long.validate().is_err());
+        let two_lines = Profile {
+            display_name: "Alice\nAdmin".into(),
+            ..profile.clone()
+        };
+        assert!(two_lines.validate().is_err());
+        let zone = Profile {
+            time_zone: "<script>".into(),
+            ..profile
+        };
+        assert!(zone.validate().is_err());
+        let image = Profile {
+            image: "data:image/png;base64,iVBORw0KGgo=".into(),
+            ..Profile::default()
+        };
+        assert_eq!(image.validate(), Ok(()));
+        for bad in [
+            "https://example.com/me.png",
+            "data:image/svg+xml;base64,PHN2Zz4=",
+            "data:image/png;base64,<b>",
+        ] {
+            let image = Profile {
+                image: bad.into(),
+                ..Profile::default()
+            };
+            assert!(image.validate().is_err(), "{}", bad);
+        }
+        // Clients leave out what they don't know about.
+        let parsed: Profile = serde_json::from_str(r#"{"pronouns":"she/her"}"#).unwrap();
+        assert_eq!(
+            (parsed.pronouns.as_str(), parsed.avatar),
+            ("she/her", AvatarStyle::Identicon)
+        );
+    }
+
+    #[test]
+    fn public_keys_are_raw_p256_points() {
+        let key = format!("04{}", "ab".repeat(64));
+        assert!(is_public_key(&key));
+        assert!(!is_public_key(&key[2..]));
+        assert!(!is_public_key(&format!("02{}", "ab".repeat(64))));
+        assert!(!is_public_key(&format!("04{}", "zz".repeat(64))));
+    }
+}
@@ -0,0 +1,102 @@
+/// Most chat messages a client may send in one go.
+pub const MESSAGE_BURST: u32 = 5;
+/// How fast a client earns back the right to send after a burst.
+pub const MESSAGES_PER_SECOND: f64 = 1.0;
+/// Shortest gap between two typing frames from the same user, in
+/// milliseconds. Clients hold off for this long; the server drops anything
+/// sent sooner.
+pub const TYPING_INTERVAL_MS: f64 = 3000.0;
+
+/// A token bucket: sending takes a token, and tokens come back at a steady
+/// rate up to the bucket's capacity.
+///
+/// The server keeps one per client to enforce [`MESSAGE_BURST`] and
+/// [`MESSAGES_PER_SECOND`]; clients keep the same one so they can tell their
+/// user to wait before the server has to drop anything. Times are in
+/// milliseconds, from whatever clock the caller uses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_ms: f64,
+    tokens: f64,
+    updated: Option<f64>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens, refilling at `per_second`.
+    pub fn new(capacity: u32, per_second: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            per_ms: per_second / 1000.0,
+            tokens: capacity as f64,
+            updated: None,
+        }
+    }
+
+    /// The limit on chat messages both ends agree on.
+    pub fn for_messages() -> Self {
+        Self::new(MESSAGE_BURST, MESSAGES_PER_SECOND)
+    }
+
+    /// Takes a token at time `now`, or says how many milliseconds until
+    /// there is one.
+    pub fn try_take(&mut self, now: f64) -> Result<(), f64> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err((1.0 - self.tokens) / self.per_ms)
+        }
+    }
+
+    /// Holds off the next token until `ms` after `now`, however full the
+    /// bucket was.
+    pub fn pause(&mut self, now: f64, ms: f64) {
+        self.refill(now);
+        self.tokens = self.tokens.min(1.0 - ms * self.per_ms);
+    }
+
+    fn refill(&mut self, now: f64) {
+        if let Some(updated) = self.updated {
+            // A clock that steps backwards earns nothing.
+            let earned = (now - updated).max(0.0) * self.per_ms;
+            self.tokens = (self.tokens + earned).min(self.capacity);
+        }
+        self.updated = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_a_burst_then_one_per_interval() {
+        let mut bucket = TokenBucket::new(3, 2.0);
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take(0.0), Ok(()));
+        }
+        assert_eq!(bucket.try_take(0.0), Err(500.0));
+        assert_eq!(bucket.try_take(250.0), Err(250.0));
+        assert_eq!(bucket.try_take(500.0), Ok(()));
+        assert!(bucket.try_take(500.0).is_err());
+    }
+
+    #[test]
+    fn refills_no_further_than_capacity() {
+        let mut bucket = TokenBucket::new(2, 1.0);
+        bucket.try_take(0.0).unwrap();
+        bucket.try_take(60_000.0).unwrap();
+        bucket.try_take(60_000.0).unwrap();
+        assert!(bucket.try_take(60_000.0).is_err());
+    }
+
+    #[test]
+    fn pausing_empties_the_bucket() {
+        let mut bucket = TokenBucket::for_messages();
+        bucket.pause(0.0, 2000.0);
+        assert_eq!(bucket.try_take(0.0), Err(2000.0));
+        assert_eq!(bucket.try_take(2000.0), Ok(()));
+    }
+}
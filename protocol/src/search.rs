@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+/// Most messages a search returns.
+pub const MAX_SEARCH_RESULTS: usize = 50;
+
+const DAY_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// A parsed search box query, sent to the server as the data of a
+/// [`Search`](crate::MsgTypes::Search) frame.
+///
+/// Free text is matched case-insensitively against the message body; the
+/// `from:`, `in:#room`, `has:image`, `after:YYYY-MM-DD` and
+/// `before:YYYY-MM-DD` filters narrow results down. Dates are stored as
+/// milliseconds since the epoch (UTC), `before` being exclusive.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchQuery {
+    pub raw: String,
+    pub terms: Vec<String>,
+    pub from: Option<String>,
+    pub room: Option<String>,
+    pub has_image: bool,
+    pub after: Option<f64>,
+    pub before: Option<f64>,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let mut query = SearchQuery {
+            raw: input.trim().to_string(),
+            ..Default::default()
+        };
+        for word in input.split_whitespace() {
+            match word.split_once(':') {
+                Some(("from", user)) if !user.is_empty() => {
+                    query.from = Some(user.trim_start_matches('@').to_string())
+                }
+                Some(("in", room)) if !room.is_empty() => {
+                    query.room = Some(room.trim_start_matches('#').to_string())
+                }
+                Some(("has", "image")) => query.has_image = true,
+                Some(("after", date)) if parse_date(date).is_some() => {
+                    // `after:` a day means from the start of the next one.
+                    query.after = parse_date(date).map(|d| d + DAY_MS)
+                }
+                Some(("before", date)) if parse_date(date).is_some() => {
+                    query.before = parse_date(date)
+                }
+                _ => query.terms.push(word.to_lowercase()),
+            }
+        }
+        query
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+            && self.from.is_none()
+            && self.room.is_none()
+            && !self.has_image
+            && self.after.is_none()
+            && self.before.is_none()
+    }
+
+    pub fn matches(&self, from: &str, room: &str, text: &str, timestamp: f64) -> bool {
+        let text_lower = text.to_lowercase();
+        self.terms.iter().all(|t| text_lower.contains(t.as_str()))
+            && self
+                .from
+                .as_ref()
+                .is_none_or(|f| f.eq_ignore_ascii_case(from))
+            && self
+                .room
+                .as_ref()
+                .is_none_or(|r| r.eq_ignore_ascii_case(room))
+            && (!self.has_image || is_image(text))
+            && self.after.is_none_or(|after| timestamp >= after)
+            && self.before.is_none_or(|before| timestamp < before)
+    }
+}
+
+/// Whether a message body is an image link that gets embedded inline.
+pub fn is_image(text: &str) -> bool {
+    text.ends_with(".gif")
+}
+
+/// Parses `YYYY-MM-DD` into milliseconds since the epoch at UTC midnight.
+fn parse_date(s: &str) -> Option<f64> {
+    let mut parts = s.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days as f64 * DAY_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_filters_and_terms() {
+        let q = SearchQuery::parse("deploy from:@alice in:#ops has:image Friday");
+        assert_eq!(q.terms, vec!["deploy", "friday"]);
+        assert_eq!(q.from.as_deref(), Some("alice"));
+        assert_eq!(q.room.as_deref(), Some("ops"));
+        assert!(q.has_image);
+        assert!(SearchQuery::parse("  ").is_empty());
+        // Unknown or malformed filters are searched for literally.
+        assert_eq!(SearchQuery::parse("after:soon").terms, vec!["after:soon"]);
+    }
+
+    #[test]
+    fn after_excludes_the_named_day_and_before_is_exclusive() {
+        assert_eq!(parse_date("1970-01-02"), Some(DAY_MS));
+        assert_eq!(parse_date("2024-03-01"), Some(19_783.0 * DAY_MS));
+
+        let q = SearchQuery::parse("after:2024-02-29 before:2024-03-02");
+        let march_1 = 19_783.0 * DAY_MS;
+        assert!(q.matches("bob", "general", "hi", march_1 + 1000.0));
+        assert!(!q.matches("bob", "general", "hi", march_1 - 1000.0));
+        assert!(!q.matches("bob", "general", "hi", march_1 + DAY_MS));
+    }
+
+    #[test]
+    fn matches_every_filter() {
+        let q = SearchQuery::parse("CAT from:Alice");
+        assert!(q.matches("alice", "general", "look, a cat", 0.0));
+        assert!(!q.matches("bob", "general", "look, a cat", 0.0));
+        assert!(!q.matches("alice", "general", "look, a dog", 0.0));
+
+        let q = SearchQuery::parse("has:image");
+        assert!(q.matches("bob", "general", "https://x.test/cat.gif", 0.0));
+        assert!(!q.matches("bob", "general", "cat", 0.0));
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::Read;
+
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+use yewchat_protocol::{BotIdentity, BotMessage, MsgTypes, WebSocketMessage};
+
+/// Usage: `bot-post <bot-name> [url] < message.json`
+///
+/// Posts one [`BotMessage`], read as JSON from stdin, as the named bot. Meant
+/// for CI jobs and scripts that have something to say but no reason to stay
+/// connected.
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let name = args.next().unwrap_or_else(|| {
+        eprintln!("usage: bot-post <bot-name> [url] < message.json");
+        std::process::exit(2);
+    });
+    let url = args
+        .next()
+        .unwrap_or_else(|| "ws://127.0.0.1:8080".to_string());
+
+    let mut input = String::new();
+    let post = std::io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::from_str::<BotMessage>(&input).map_err(|e| e.to_string()));
+    let post = match post {
+        Ok(post) => post,
+        Err(e) => {
+            eprintln!("can't read the message from stdin: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let (mut socket, _) = match tokio_tungstenite::connect_async(&url).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("can't connect to {}: {}", url, e);
+            std::process::exit(1);
+        }
+    };
+    let identity = BotIdentity {
+        name,
+        description: String::new(),
+    };
+    let frames = [
+        frame(
+            MsgTypes::RegisterBot,
+            serde_json::to_string(&identity).unwrap(),
+        ),
+        frame(MsgTypes::BotMessage, serde_json::to_string(&post).unwrap()),
+    ];
+    for frame in frames {
+        if let Err(e) = socket.send(frame).await {
+            eprintln!("can't post: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let _ = socket.close(None).await;
+}
+
+fn frame(message_type: MsgTypes, data: String) -> Message {
+    let msg = WebSocketMessage {
+        message_type,
+        data: Some(data),
+        data_array: None,
+    };
+    Message::Text(serde_json::to_string(&msg).unwrap())
+}
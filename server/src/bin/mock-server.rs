@@ -0,0 +1,33 @@
+use tokio::net::TcpListener;
+use yewchat_server::mock::{self, Script};
+
+/// Usage: `mock-server <script.jsonl> [addr]`
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| {
+        eprintln!("usage: mock-server <script.jsonl> [addr]");
+        std::process::exit(2);
+    });
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8080".to_string());
+
+    let script = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| Script::parse(&text));
+    let script = match script {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("can't load {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("can't listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    println!("replaying {} on ws://{}", path, addr);
+    mock::serve(listener, script).await;
+}
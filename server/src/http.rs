@@ -0,0 +1,187 @@
+//! Plain HTTP on the WebSocket port: webhooks, and the event-stream
+//! transport for clients that can't use a WebSocket.
+//!
+//! Each connection carries one request. Browsers call from wherever the
+//! client is hosted, so every response allows any origin.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::hub::{ClientId, Hub};
+use crate::sse::{self, Streams};
+use crate::webhook;
+
+/// Longest request line and headers accepted.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+/// Largest body accepted.
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    /// Seconds to wait before trying again, for `429`.
+    pub retry_after: Option<u64>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            body: body.to_string(),
+            retry_after: None,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            429 => "Too Many Requests",
+            _ => "Error",
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason());
+        if let Some(seconds) = self.retry_after {
+            head.push_str(&format!("Retry-After: {}\r\n", seconds));
+        }
+        head.push_str(CORS_HEADERS);
+        head.push_str(&format!(
+            "Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        ));
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+/// Lets pages on any origin make requests here, JSON bodies and all.
+pub const CORS_HEADERS: &str = "Access-Control-Allow-Origin: *\r\n\
+    Access-Control-Allow-Methods: GET, POST\r\n\
+    Access-Control-Allow-Headers: Content-Type\r\n";
+
+/// The parts of a request line and headers that matter here.
+#[derive(Debug, PartialEq)]
+struct Head {
+    method: String,
+    path: String,
+    content_length: usize,
+    /// Bytes taken up by the request line and headers.
+    len: usize,
+}
+
+/// Parses the head of a request, or returns `None` if more of it is still
+/// to come.
+fn parse_head(buf: &[u8]) -> Result<Option<Head>, Response> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    let len = match request.parse(buf) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD_LEN => return Ok(None),
+        Ok(httparse::Status::Partial) => return Err(Response::new(413, "headers too long")),
+        Err(e) => return Err(Response::new(400, &e.to_string())),
+    };
+    let content_length = match request
+        .headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case("content-length"))
+    {
+        Some(h) => std::str::from_utf8(h.value)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .ok_or_else(|| Response::new(400, "bad Content-Length"))?,
+        None => 0,
+    };
+    if content_length > MAX_BODY_LEN {
+        return Err(Response::new(413, "body too large"));
+    }
+    Ok(Some(Head {
+        method: request.method.unwrap_or_default().to_string(),
+        path: request.path.unwrap_or_default().to_string(),
+        content_length,
+        len,
+    }))
+}
+
+/// Reads one request from `stream` and answers it. Requests for an event
+/// stream keep the connection as client `id` until either side hangs up.
+pub async fn serve(
+    hub: Arc<Mutex<Hub>>,
+    streams: Arc<Streams>,
+    id: ClientId,
+    mut stream: TcpStream,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let head = loop {
+        match parse_head(&buf) {
+            Ok(Some(head)) => break head,
+            Ok(None) => {}
+            Err(response) => return stream.write_all(&response.to_bytes()).await,
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let end = head.len + head.content_length;
+    while buf.len() < end {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let body = &buf[head.len..end];
+    let (path, query) = head.path.split_once('?').unwrap_or((&head.path, ""));
+    let response = match (head.method.as_str(), path) {
+        // Preflight for a cross-origin POST; the headers say it's fine.
+        ("OPTIONS", _) => Response::new(204, ""),
+        ("GET", sse::EVENTS_PATH) => match sse::client_key(query) {
+            Some(key) => return sse::stream(hub, streams, id, key, stream).await,
+            None => Response::new(400, "which client?"),
+        },
+        (_, sse::SEND_PATH) => sse::send(&hub, &streams, &head.method, query, body),
+        (method, path) => webhook::respond(&hub, method, path, body),
+    };
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_heads() {
+        let request = b"POST /hooks/abc HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\n{}";
+        let head = parse_head(request).unwrap().unwrap();
+        assert_eq!(
+            (head.method.as_str(), head.path.as_str()),
+            ("POST", "/hooks/abc")
+        );
+        assert_eq!((head.content_length, head.len), (2, request.len() - 2));
+
+        assert_eq!(parse_head(b"POST /hooks/abc HTTP/1.1\r\nHost"), Ok(None));
+        let huge = b"POST /hooks/abc HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n";
+        assert_eq!(parse_head(huge).unwrap_err().status, 413);
+    }
+
+    #[test]
+    fn responses_allow_any_origin() {
+        let response = String::from_utf8(Response::new(204, "").to_bytes()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(response.contains("Access-Control-Allow-Headers: Content-Type\r\n"));
+    }
+}
@@ -0,0 +1,1861 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::de::DeserializeOwned;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::mpsc::UnboundedSender;
+use yewchat_protocol::{
+    Attachment, BotIdentity, BotMessage, Credentials, Encoding, InteractionData, MessageData,
+    ModAction, Moderation, ModerationEntry, MsgTypes, Profile, PublicKeys, ReadData, Report,
+    ReportRequest, Role, Sanction, Sealed, SearchQuery, SearchResultsData, TokenBucket, TopicData,
+    TypingData, WebSocketMessage, Webhook, WebhookList, WebhookPayload, WebhookRequest,
+    MAX_SEARCH_RESULTS, TYPING_INTERVAL_MS,
+};
+
+pub type ClientId = u64;
+
+/// Bot messages whose buttons still work; presses on older ones are refused.
+const BOT_POSTS_KEPT: usize = 1000;
+/// Messages that can still be removed or reported; older ones have
+/// scrolled away.
+const RECENT_KEPT: usize = 1000;
+/// Messages before a reported one that moderators get to see with it.
+const REPORT_CONTEXT: usize = 3;
+/// Open reports kept; past this the oldest are dropped unread.
+const OPEN_REPORTS_KEPT: usize = 500;
+/// Entries of the moderation log kept for moderators who join later.
+const MODERATION_LOG_KEPT: usize = 200;
+
+/// A frame on its way to a client, in the encoding it asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outgoing {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Close the connection, giving this reason.
+    Close(String),
+}
+
+struct Client {
+    /// Set once the client has sent `Register`.
+    username: Option<String>,
+    /// Set if it registered as a bot.
+    bot: Option<BotIdentity>,
+    encoding: Encoding,
+    tx: UnboundedSender<Outgoing>,
+    messages: TokenBucket,
+    /// When a typing frame from this client was last passed on.
+    last_typing: Option<f64>,
+}
+
+/// Why a webhook post was turned away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookError {
+    UnknownToken,
+    Empty,
+    /// Over the webhook's rate limit; holds how many milliseconds to wait.
+    SlowDown(f64),
+}
+
+struct Hook {
+    webhook: Webhook,
+    posts: TokenBucket,
+}
+
+/// Everyone connected, and what happens when they send a frame.
+///
+/// Kept free of any I/O so it can be driven directly in tests: each client
+/// is just the sending half of a channel its connection task drains.
+#[derive(Default)]
+pub struct Hub {
+    /// Ordered by id, so the user list comes out in order of joining.
+    clients: BTreeMap<ClientId, Client>,
+    last_message_id: u64,
+    topics: BTreeMap<String, TopicData>,
+    bot_posts: BTreeMap<u64, BotPost>,
+    /// Webhooks by token.
+    webhooks: BTreeMap<String, Hook>,
+    /// Tokens by username, handed out the first time each name registers.
+    /// Holding one is what makes someone that user, roles, bans and all.
+    sessions: BTreeMap<String, String>,
+    /// Roles by username, for everyone who isn't a member.
+    roles: BTreeMap<String, Role>,
+    /// Bans and mutes by username, so reconnecting doesn't shake them off.
+    bans: BTreeMap<String, Sanction>,
+    mutes: BTreeMap<String, Sanction>,
+    /// Recent messages by id.
+    recent: BTreeMap<u64, MessageData>,
+    moderation_log: VecDeque<ModerationEntry>,
+    /// Open reports by id.
+    reports: BTreeMap<u64, Report>,
+    last_report_id: u64,
+    /// Keys for encrypted direct messages, by username, while they're online.
+    public_keys: PublicKeys,
+    /// Profiles by username, kept like roles when people leave.
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// What's needed to route presses of a bot message's buttons.
+struct BotPost {
+    bot: String,
+    room: String,
+    buttons: Vec<String>,
+}
+
+impl Hub {
+    pub fn connect(&mut self, id: ClientId, tx: UnboundedSender<Outgoing>) {
+        let client = Client {
+            username: None,
+            bot: None,
+            encoding: Encoding::Json,
+            tx,
+            messages: TokenBucket::for_messages(),
+            last_typing: None,
+        };
+        self.clients.insert(id, client);
+    }
+
+    pub fn disconnect(&mut self, id: ClientId) {
+        let username = self.clients.remove(&id).and_then(|c| c.username);
+        if let Some(name) = username {
+            if !self
+                .clients
+                .values()
+                .any(|c| c.username.as_ref() == Some(&name))
+            {
+                self.public_keys.remove(&name);
+            }
+            self.broadcast_users();
+        }
+    }
+
+    /// Hands whatever client `from` registered as over to client `to`, which
+    /// has just connected in its place, and drops `from` without anyone
+    /// seeing them leave.
+    pub fn take_over(&mut self, from: ClientId, to: ClientId) {
+        if !self.clients.contains_key(&to) {
+            return self.disconnect(from);
+        }
+        let old = match self.clients.remove(&from) {
+            Some(old) => old,
+            None => return,
+        };
+        let client = self.clients.get_mut(&to).unwrap();
+        client.username = old.username;
+        client.bot = old.bot;
+        client.encoding = old.encoding;
+        client.messages = old.messages;
+        client.last_typing = old.last_typing;
+    }
+
+    /// Handles a text frame from client `id`. Frames the server doesn't act
+    /// on are ignored; malformed ones are an error.
+    pub fn handle(&mut self, id: ClientId, frame: &str) -> Result<(), String> {
+        let msg = serde_json::from_str(frame).map_err(|e| format!("malformed frame: {}", e))?;
+        self.dispatch(id, msg)
+    }
+
+    /// Handles a binary frame, in the encoding client `id` registered with.
+    pub fn handle_binary(&mut self, id: ClientId, frame: &[u8]) -> Result<(), String> {
+        let encoding = self.clients.get(&id).ok_or("unknown client")?.encoding;
+        if !encoding.is_binary() {
+            return Err("binary frame before agreeing on an encoding".to_string());
+        }
+        let msg = encoding.decode(frame).map_err(|e| e.to_string())?;
+        self.dispatch(id, msg)
+    }
+
+    fn dispatch(&mut self, id: ClientId, msg: WebSocketMessage) -> Result<(), String> {
+        match msg.message_type {
+            MsgTypes::Register => {
+                let credentials: Credentials = decode(msg.data, "credentials")?;
+                if credentials.name.trim().is_empty() {
+                    return Err("register without a username".to_string());
+                }
+                let offered = msg.data_array.unwrap_or_default();
+                self.register(id, credentials.name, Ok(credentials.token), offered)?;
+            }
+            MsgTypes::RegisterBot => {
+                let bot: BotIdentity = decode(msg.data, "bot identity")?;
+                if bot.name.trim().is_empty() {
+                    return Err("bot without a name".to_string());
+                }
+                self.register(
+                    id,
+                    bot.name.clone(),
+                    Err(bot),
+                    msg.data_array.unwrap_or_default(),
+                )?;
+            }
+            MsgTypes::Message => {
+                let (room, to) = msg.chat_target();
+                self.post(id, room, to, msg.data.unwrap_or_default(), vec![], None)?;
+            }
+            MsgTypes::Sealed => {
+                let (room, to) = msg.chat_target();
+                let to = to.ok_or("sealed message without a recipient")?;
+                let sealed: Sealed = decode(msg.data, "sealed message")?;
+                self.post(
+                    id,
+                    room,
+                    Some(to),
+                    String::new(),
+                    vec![],
+                    Some(Box::new(sealed)),
+                )?;
+            }
+            MsgTypes::Profile => {
+                let user = self.username(id).ok_or("profile before register")?;
+                if let Some(sanction) = self.muted(&user, now()) {
+                    let frame = sanction_frame(MsgTypes::Muted, &sanction.clone());
+                    self.send(id, &frame);
+                    return Ok(());
+                }
+                let profile: Profile = decode(msg.data, "profile")?;
+                profile.validate()?;
+                self.profiles.insert(user, profile);
+                self.broadcast(&self.profiles_frame());
+            }
+            MsgTypes::PublicKey => {
+                let user = self.username(id).ok_or("public key before register")?;
+                let key = msg.data.ok_or("public key without a key")?;
+                if !yewchat_protocol::is_public_key(&key) {
+                    return Err("malformed public key".to_string());
+                }
+                match self.public_keys.get(&user) {
+                    Some(known) if *known == key => {}
+                    // Another device of theirs mustn't swap it out from
+                    // under the people they're talking to; it waits until
+                    // the first one has gone.
+                    Some(_) => return Err(format!("{} already has a key", user)),
+                    None => {
+                        self.public_keys.insert(user, key);
+                        self.broadcast_users();
+                    }
+                }
+            }
+            MsgTypes::BotMessage => {
+                let post: BotMessage = decode(msg.data, "bot message")?;
+                let client = self.clients.get(&id).ok_or("unknown client")?;
+                if client.bot.is_none() && !post.attachments.is_empty() {
+                    return Err("only bots can attach cards".to_string());
+                }
+                self.post(id, post.room, post.to, post.text, post.attachments, None)?;
+            }
+            MsgTypes::Interaction => {
+                let user = self
+                    .clients
+                    .get(&id)
+                    .and_then(|c| c.username.clone())
+                    .ok_or("interaction before register")?;
+                let interaction: InteractionData = decode(msg.data, "interaction")?;
+                let post = self
+                    .bot_posts
+                    .get(&interaction.message_id)
+                    .ok_or("no bot is waiting on that message")?;
+                if !post.buttons.contains(&interaction.button) {
+                    return Err("that message has no such button".to_string());
+                }
+                let interaction = InteractionData {
+                    user,
+                    room: post.room.clone(),
+                    ..interaction
+                };
+                let frame = WebSocketMessage {
+                    message_type: MsgTypes::Interaction,
+                    data: Some(serde_json::to_string(&interaction).unwrap()),
+                    data_array: None,
+                };
+                let clients = &self.clients;
+                self.broadcast_where(&frame, |to, name| {
+                    name == post.bot && clients[&to].bot.is_some()
+                });
+            }
+            MsgTypes::CreateWebhook => {
+                let created_by = self.moderator(id)?;
+                let request: WebhookRequest = decode(msg.data, "webhook request")?;
+                if request.name.trim().is_empty() {
+                    return Err("webhook without a name".to_string());
+                }
+                let webhook = Webhook {
+                    id: random_hex(4),
+                    room: request.room,
+                    name: request.name.trim().to_string(),
+                    token: random_hex(16),
+                    created_by,
+                };
+                let room = webhook.room.clone();
+                let hook = Hook {
+                    webhook,
+                    posts: TokenBucket::for_messages(),
+                };
+                self.webhooks.insert(hook.webhook.token.clone(), hook);
+                self.send_webhooks(id, &room);
+            }
+            MsgTypes::RevokeWebhook => {
+                self.moderator(id)?;
+                let hook_id = msg.data.ok_or("revoke without a webhook id")?;
+                let token = self
+                    .webhooks
+                    .iter()
+                    .find(|(_, hook)| hook.webhook.id == hook_id)
+                    .map(|(token, _)| token.clone())
+                    .ok_or("no such webhook")?;
+                let room = self.webhooks.remove(&token).unwrap().webhook.room;
+                self.send_webhooks(id, &room);
+            }
+            MsgTypes::ListWebhooks => {
+                self.moderator(id)?;
+                let room = msg.data.ok_or("list webhooks without a room")?;
+                self.send_webhooks(id, &room);
+            }
+            MsgTypes::Ping => {
+                self.send(
+                    id,
+                    &WebSocketMessage {
+                        message_type: MsgTypes::Pong,
+                        data: msg.data,
+                        data_array: None,
+                    },
+                );
+            }
+            // The clients keep track of who read what.
+            MsgTypes::Read => {
+                let user = self.username(id).ok_or("read receipt before register")?;
+                let read: ReadData = decode(msg.data, "read receipt")?;
+                // Nobody marks messages read on someone else's behalf.
+                let read = ReadData { user, ..read };
+                self.broadcast(&WebSocketMessage {
+                    message_type: MsgTypes::Read,
+                    data: Some(serde_json::to_string(&read).unwrap()),
+                    data_array: None,
+                });
+            }
+            MsgTypes::Typing => {
+                let now = now();
+                let client = self.clients.get_mut(&id).ok_or("unknown client")?;
+                let user = client.username.clone().ok_or("typing before register")?;
+                if client
+                    .last_typing
+                    .is_some_and(|last| now - last < TYPING_INTERVAL_MS)
+                {
+                    return Ok(());
+                }
+                client.last_typing = Some(now);
+                if self.muted(&user, now).is_some() {
+                    return Ok(());
+                }
+                let typing: TypingData = decode(msg.data, "typing")?;
+                // Whatever the client claims, it's typing as itself.
+                let typing = TypingData { user, ..typing };
+                let frame = WebSocketMessage {
+                    message_type: MsgTypes::Typing,
+                    data: Some(serde_json::to_string(&typing).unwrap()),
+                    data_array: None,
+                };
+                self.broadcast_where(&frame, |to, _| to != id);
+            }
+            MsgTypes::Topic => {
+                let set_by = self
+                    .clients
+                    .get(&id)
+                    .and_then(|c| c.username.clone())
+                    .ok_or("topic before register")?;
+                if let Some(sanction) = self.muted(&set_by, now()) {
+                    let frame = sanction_frame(MsgTypes::Muted, &sanction.clone());
+                    self.send(id, &frame);
+                    return Ok(());
+                }
+                let topic: TopicData = decode(msg.data, "topic")?;
+                let topic = TopicData { set_by, ..topic };
+                if topic.topic.is_empty() {
+                    self.topics.remove(&topic.room);
+                } else {
+                    self.topics.insert(topic.room.clone(), topic.clone());
+                }
+                self.broadcast(&topic_frame(&topic));
+            }
+            MsgTypes::Moderate => {
+                let by = self.username(id).ok_or("moderation before register")?;
+                let moderation: Moderation = decode(msg.data, "moderation")?;
+                self.moderate(by, moderation)?;
+            }
+            MsgTypes::Report => {
+                let reporter = self.username(id).ok_or("report before register")?;
+                let request: ReportRequest = decode(msg.data, "report")?;
+                self.report(reporter, request)?;
+            }
+            MsgTypes::Search => {
+                let user = self.username(id).ok_or("search before register")?;
+                let query: SearchQuery = decode(msg.data, "search")?;
+                let found = self.search(&user, &query);
+                self.send(
+                    id,
+                    &WebSocketMessage {
+                        message_type: MsgTypes::SearchResults,
+                        data: Some(serde_json::to_string(&found).unwrap()),
+                        data_array: None,
+                    },
+                );
+            }
+            MsgTypes::Users
+            | MsgTypes::Session
+            | MsgTypes::Refused
+            | MsgTypes::SearchResults
+            | MsgTypes::Pong
+            | MsgTypes::Encoding
+            | MsgTypes::SlowDown
+            | MsgTypes::Webhooks
+            | MsgTypes::Roles
+            | MsgTypes::Removed
+            | MsgTypes::Muted
+            | MsgTypes::Unmuted
+            | MsgTypes::Kicked
+            | MsgTypes::ModerationLog
+            | MsgTypes::Reports
+            | MsgTypes::Profiles => {}
+        }
+        Ok(())
+    }
+
+    /// Posts what a script sent to the webhook with this token into its room.
+    pub fn post_webhook(
+        &mut self,
+        token: &str,
+        payload: WebhookPayload,
+    ) -> Result<(), WebhookError> {
+        if payload.text.trim().is_empty() && payload.attachments.is_empty() {
+            return Err(WebhookError::Empty);
+        }
+        let now = now();
+        let hook = self
+            .webhooks
+            .get_mut(token)
+            .ok_or(WebhookError::UnknownToken)?;
+        hook.posts.try_take(now).map_err(WebhookError::SlowDown)?;
+        let webhook = &hook.webhook;
+        // Names people have taken stay theirs; a script only gets to borrow
+        // one with a tag on it.
+        let from = match payload.username.filter(|name| !name.trim().is_empty()) {
+            Some(name)
+                if self.sessions.contains_key(&name)
+                    || self
+                        .clients
+                        .values()
+                        .any(|c| c.username.as_ref() == Some(&name)) =>
+            {
+                format!("{} (via {})", name, webhook.name)
+            }
+            Some(name) => name,
+            None => webhook.name.clone(),
+        };
+        let data = MessageData {
+            id: 0,
+            room: webhook.room.clone(),
+            from,
+            to: None,
+            message: payload.text,
+            timestamp: now,
+            bot: Some(BotIdentity {
+                name: webhook.name.clone(),
+                description: format!("Webhook posting to #{}", webhook.room),
+            }),
+            attachments: payload.attachments,
+            sealed: None,
+        };
+        self.publish(data);
+        Ok(())
+    }
+
+    fn username(&self, id: ClientId) -> Option<String> {
+        self.clients.get(&id).and_then(|c| c.username.clone())
+    }
+
+    fn role(&self, username: &str) -> Role {
+        self.roles.get(username).copied().unwrap_or_default()
+    }
+
+    /// The username of client `id`, if it's a moderator or the owner.
+    fn moderator(&self, id: ClientId) -> Result<String, String> {
+        let username = self
+            .username(id)
+            .ok_or("moderator action before register")?;
+        if self.role(&username) < Role::Moderator {
+            return Err(format!("{} isn't a moderator", username));
+        }
+        Ok(username)
+    }
+
+    /// How `username` is muted, forgetting mutes that have run out.
+    fn muted(&mut self, username: &str, now: f64) -> Option<&Sanction> {
+        if self
+            .mutes
+            .get(username)?
+            .until
+            .is_some_and(|until| until <= now)
+        {
+            self.mutes.remove(username);
+        }
+        self.mutes.get(username)
+    }
+
+    /// Carries out what `by` asked for, if their role allows it, and logs it.
+    fn moderate(&mut self, by: String, mut moderation: Moderation) -> Result<(), String> {
+        let now = now();
+        let role = self.role(&by);
+        let sanction = |until, banned| Sanction {
+            by: by.clone(),
+            reason: moderation.reason.clone(),
+            until,
+            banned,
+        };
+        match &mut moderation.action {
+            ModAction::Remove { message_id, user } => {
+                let (author, own) = self
+                    .recent
+                    .get(message_id)
+                    .map(|m| (m.from.clone(), m.from == by && m.bot.is_none()))
+                    .ok_or("no such message, or it's too old to remove")?;
+                if !own && !role.outranks(self.role(&author)) {
+                    return Err(format!("{} can't remove messages by {}", by, author));
+                }
+                self.recent.remove(message_id);
+                self.broadcast(&WebSocketMessage {
+                    message_type: MsgTypes::Removed,
+                    data: Some(message_id.to_string()),
+                    data_array: None,
+                });
+                *user = author;
+            }
+            ModAction::SetRole {
+                user,
+                role: new_role,
+            } => {
+                if role != Role::Owner {
+                    return Err("only the owner hands out roles".to_string());
+                }
+                if *new_role == Role::Owner || *user == by {
+                    return Err("there's only one owner".to_string());
+                }
+                // Bots and names nobody has registered have no token to
+                // keep a role from being picked up by whoever comes along.
+                if !self.sessions.contains_key(user.as_str()) {
+                    return Err(format!("{} has never signed in", user));
+                }
+                match new_role {
+                    Role::Member => self.roles.remove(user.as_str()),
+                    _ => self.roles.insert(user.clone(), *new_role),
+                };
+                self.broadcast(&self.roles_frame());
+                // Moderators need the log and the reports from now on.
+                if *new_role == Role::Moderator {
+                    let user = user.clone();
+                    let frame = self.log_frame(self.moderation_log.iter().cloned().collect());
+                    self.broadcast_where(&frame, |_, name| name == user);
+                    self.broadcast_where(&self.reports_frame(), |_, name| name == user);
+                }
+            }
+            ModAction::CloseReport {
+                report_id, user, ..
+            } => {
+                if role < Role::Moderator {
+                    return Err(format!("{} isn't a moderator", by));
+                }
+                let report = self.reports.remove(report_id).ok_or("no such report")?;
+                *user = report.message.from;
+                self.broadcast_to_moderators(&self.reports_frame());
+            }
+            action => {
+                let user = action.user().to_string();
+                if !role.outranks(self.role(&user)) {
+                    return Err(format!("{} can't moderate {}", by, user));
+                }
+                match action {
+                    ModAction::Kick { .. } => self.kick(&user, &sanction(None, false)),
+                    ModAction::Ban { .. } => {
+                        let sanction = sanction(None, true);
+                        self.bans.insert(user.clone(), sanction.clone());
+                        self.kick(&user, &sanction);
+                    }
+                    ModAction::Unban { .. } => {
+                        self.bans.remove(&user).ok_or("that user isn't banned")?;
+                    }
+                    ModAction::Mute { minutes, .. } => {
+                        let until = minutes.map(|minutes| now + minutes as f64 * 60_000.0);
+                        let sanction = sanction(until, false);
+                        let frame = sanction_frame(MsgTypes::Muted, &sanction);
+                        self.mutes.insert(user.clone(), sanction);
+                        self.broadcast_where(&frame, |_, name| name == user);
+                    }
+                    ModAction::Unmute { .. } => {
+                        self.mutes.remove(&user).ok_or("that user isn't muted")?;
+                        let frame = WebSocketMessage {
+                            message_type: MsgTypes::Unmuted,
+                            data: None,
+                            data_array: None,
+                        };
+                        self.broadcast_where(&frame, |_, name| name == user);
+                    }
+                    ModAction::Remove { .. }
+                    | ModAction::SetRole { .. }
+                    | ModAction::CloseReport { .. } => unreachable!(),
+                }
+            }
+        }
+
+        let entry = ModerationEntry {
+            id: self.moderation_log.back().map_or(1, |last| last.id + 1),
+            timestamp: now,
+            by,
+            moderation,
+        };
+        self.moderation_log.push_back(entry.clone());
+        if self.moderation_log.len() > MODERATION_LOG_KEPT {
+            self.moderation_log.pop_front();
+        }
+        self.broadcast_to_moderators(&self.log_frame(vec![entry]));
+        Ok(())
+    }
+
+    /// Files a report about a message `reporter` could see, for the
+    /// moderators to look at.
+    fn report(&mut self, reporter: String, request: ReportRequest) -> Result<(), String> {
+        let message = self
+            .recent
+            .get(&request.message_id)
+            .ok_or("no such message, or it's too old to report")?;
+        let visible = match &message.to {
+            Some(to) => *to == reporter || message.from == reporter,
+            None => true,
+        };
+        if !visible || message.from == reporter {
+            return Err(format!("{} can't report that message", reporter));
+        }
+        let already = self
+            .reports
+            .values()
+            .any(|r| r.message.id == message.id && r.reporter == reporter);
+        if already {
+            return Ok(());
+        }
+        // Only what everyone in the room could see; other people's direct
+        // messages stay private.
+        let mut context: Vec<MessageData> = self
+            .recent
+            .range(..message.id)
+            .rev()
+            .map(|(_, m)| m)
+            .filter(|m| m.room == message.room && m.to.is_none())
+            .take(REPORT_CONTEXT)
+            .cloned()
+            .collect();
+        context.reverse();
+        self.last_report_id += 1;
+        let report = Report {
+            id: self.last_report_id,
+            timestamp: now(),
+            reporter,
+            category: request.category,
+            note: request.note.trim().to_string(),
+            message: message.clone(),
+            context,
+        };
+        self.reports.insert(report.id, report);
+        while self.reports.len() > OPEN_REPORTS_KEPT {
+            self.reports.pop_first();
+        }
+        self.broadcast_to_moderators(&self.reports_frame());
+        Ok(())
+    }
+
+    /// The newest recent messages `user` could see that match `query`.
+    /// Sealed ones are left out: the server can't read them.
+    fn search(&self, user: &str, query: &SearchQuery) -> SearchResultsData {
+        let messages = self
+            .recent
+            .values()
+            .rev()
+            .filter(|m| m.sealed.is_none())
+            .filter(|m| m.to.as_ref().is_none_or(|to| to == user || m.from == user))
+            .filter(|m| query.matches(&m.from, &m.room, &m.message, m.timestamp))
+            .take(MAX_SEARCH_RESULTS)
+            .cloned()
+            .collect();
+        SearchResultsData {
+            query: query.raw.clone(),
+            messages,
+        }
+    }
+
+    fn broadcast_to_moderators(&self, msg: &WebSocketMessage) {
+        self.broadcast_where(msg, |_, name| self.role(name) >= Role::Moderator);
+    }
+
+    /// Tells every connection of `username` why, and closes it.
+    fn kick(&mut self, username: &str, sanction: &Sanction) {
+        let frame = sanction_frame(MsgTypes::Kicked, sanction);
+        let reason = if sanction.banned { "banned" } else { "kicked" };
+        let mut kicked = false;
+        for client in self.clients.values_mut() {
+            if client.username.as_deref() == Some(username) {
+                let _ = client.tx.send(encode(client.encoding, &frame));
+                let _ = client.tx.send(Outgoing::Close(reason.to_string()));
+                // Gone as far as everyone else is concerned, though the
+                // connection takes a moment to close.
+                client.username = None;
+                kicked = true;
+            }
+        }
+        if kicked {
+            self.broadcast_users();
+        }
+    }
+
+    fn roles_frame(&self) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: MsgTypes::Roles,
+            data: Some(serde_json::to_string(&self.roles).unwrap()),
+            data_array: None,
+        }
+    }
+
+    fn profiles_frame(&self) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: MsgTypes::Profiles,
+            data: Some(serde_json::to_string(&self.profiles).unwrap()),
+            data_array: None,
+        }
+    }
+
+    fn reports_frame(&self) -> WebSocketMessage {
+        let reports: Vec<&Report> = self.reports.values().collect();
+        WebSocketMessage {
+            message_type: MsgTypes::Reports,
+            data: Some(serde_json::to_string(&reports).unwrap()),
+            data_array: None,
+        }
+    }
+
+    fn log_frame(&self, entries: Vec<ModerationEntry>) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: MsgTypes::ModerationLog,
+            data: Some(serde_json::to_string(&entries).unwrap()),
+            data_array: None,
+        }
+    }
+
+    fn send_webhooks(&self, id: ClientId, room: &str) {
+        let list = WebhookList {
+            room: room.to_string(),
+            hooks: self
+                .webhooks
+                .values()
+                .filter(|hook| hook.webhook.room == room)
+                .map(|hook| hook.webhook.clone())
+                .collect(),
+        };
+        self.send(
+            id,
+            &WebSocketMessage {
+                message_type: MsgTypes::Webhooks,
+                data: Some(serde_json::to_string(&list).unwrap()),
+                data_array: None,
+            },
+        );
+    }
+
+    /// Signs client `id` in as `username`: a person with the token they
+    /// were given for it, if any, or a bot with its identity.
+    fn register(
+        &mut self,
+        id: ClientId,
+        username: String,
+        who: Result<Option<String>, BotIdentity>,
+        offered: Vec<String>,
+    ) -> Result<(), String> {
+        let client = self.clients.get(&id).ok_or("unknown client")?;
+        if client.username.is_some() {
+            return Err("registered twice".to_string());
+        }
+        if let Some(ban) = self.bans.get(&username) {
+            let _ = client.tx.send(encode(
+                client.encoding,
+                &sanction_frame(MsgTypes::Kicked, ban),
+            ));
+            let _ = client.tx.send(Outgoing::Close("banned".to_string()));
+            return Ok(());
+        }
+        let online = self
+            .clients
+            .values()
+            .any(|c| c.username.as_ref() == Some(&username));
+        let (bot, new_token) = match (who, self.sessions.get(&username)) {
+            (Ok(token), Some(known)) if token.as_ref() == Some(known) => (None, None),
+            (Ok(_), None) if !online => (None, Some(random_hex(16))),
+            (Err(bot), None) if !online => (Some(bot), None),
+            _ => {
+                let client = &self.clients[&id];
+                let refused = WebSocketMessage {
+                    message_type: MsgTypes::Refused,
+                    data: Some(format!("Someone else is using the name {}.", username)),
+                    data_array: None,
+                };
+                let _ = client.tx.send(encode(client.encoding, &refused));
+                let _ = client.tx.send(Outgoing::Close("name taken".to_string()));
+                return Ok(());
+            }
+        };
+        let encoding = Encoding::negotiate(&offered);
+        let client = self.clients.get_mut(&id).unwrap();
+        client.username = Some(username.clone());
+        let is_bot = bot.is_some();
+        client.bot = bot;
+        if encoding.is_binary() {
+            // Still in JSON: the client can't read anything else yet.
+            self.send(
+                id,
+                &WebSocketMessage {
+                    message_type: MsgTypes::Encoding,
+                    data: Some(encoding.name().to_string()),
+                    data_array: None,
+                },
+            );
+            self.clients.get_mut(&id).unwrap().encoding = encoding;
+        }
+        if let Some(token) = new_token {
+            self.sessions.insert(username.clone(), token.clone());
+            self.send(
+                id,
+                &WebSocketMessage {
+                    message_type: MsgTypes::Session,
+                    data: Some(token),
+                    data_array: None,
+                },
+            );
+        }
+        self.broadcast_users();
+        for topic in self.topics.values() {
+            self.send(id, &topic_frame(topic));
+        }
+        // Whoever gets here first runs the place, for as long as they hold
+        // on to their token.
+        if !is_bot && !self.roles.values().any(|role| *role == Role::Owner) {
+            self.roles.insert(username.clone(), Role::Owner);
+            self.broadcast(&self.roles_frame());
+        } else {
+            self.send(id, &self.roles_frame());
+        }
+        self.send(id, &self.profiles_frame());
+        if self.role(&username) >= Role::Moderator {
+            self.send(
+                id,
+                &self.log_frame(self.moderation_log.iter().cloned().collect()),
+            );
+            self.send(id, &self.reports_frame());
+        }
+        if let Some(sanction) = self.muted(&username, now()) {
+            let frame = sanction_frame(MsgTypes::Muted, &sanction.clone());
+            self.send(id, &frame);
+        }
+        Ok(())
+    }
+
+    /// Numbers and timestamps a chat message from client `id` and sends it
+    /// on, unless the client is over its rate limit.
+    fn post(
+        &mut self,
+        id: ClientId,
+        room: String,
+        to: Option<String>,
+        message: String,
+        attachments: Vec<Attachment>,
+        sealed: Option<Box<Sealed>>,
+    ) -> Result<(), String> {
+        let now = now();
+        let client = self.clients.get_mut(&id).ok_or("unknown client")?;
+        let from = client.username.clone().ok_or("message before register")?;
+        if let Some(sanction) = self.muted(&from, now) {
+            let frame = sanction_frame(MsgTypes::Muted, &sanction.clone());
+            self.send(id, &frame);
+            return Ok(());
+        }
+        let client = self.clients.get_mut(&id).unwrap();
+        if let Err(wait) = client.messages.try_take(now) {
+            self.send(
+                id,
+                &WebSocketMessage {
+                    message_type: MsgTypes::SlowDown,
+                    data: Some(wait.ceil().to_string()),
+                    data_array: None,
+                },
+            );
+            return Ok(());
+        }
+        let data = MessageData {
+            id: 0,
+            room,
+            from,
+            to,
+            message,
+            timestamp: now,
+            bot: client.bot.clone(),
+            attachments,
+            sealed,
+        };
+        self.publish(data);
+        Ok(())
+    }
+
+    /// Numbers a message and sends it to everyone it's for.
+    fn publish(&mut self, mut data: MessageData) {
+        self.last_message_id += 1;
+        data.id = self.last_message_id;
+        self.recent.insert(data.id, data.clone());
+        while self.recent.len() > RECENT_KEPT {
+            self.recent.pop_first();
+        }
+        let buttons: Vec<String> = data
+            .attachments
+            .iter()
+            .flat_map(|a| a.buttons.iter().map(|b| b.id.clone()))
+            .collect();
+        if data.bot.is_some() && !buttons.is_empty() {
+            let post = BotPost {
+                bot: data.from.clone(),
+                room: data.room.clone(),
+                buttons,
+            };
+            self.bot_posts.insert(data.id, post);
+            while self.bot_posts.len() > BOT_POSTS_KEPT {
+                self.bot_posts.pop_first();
+            }
+        }
+
+        let frame = WebSocketMessage {
+            message_type: MsgTypes::Message,
+            data: Some(serde_json::to_string(&data).unwrap()),
+            data_array: None,
+        };
+        match &data.to {
+            // Direct messages go to the recipient and back to the sender's
+            // other connections, and nobody else.
+            Some(to) => self.broadcast_where(&frame, |_, name| name == to || name == data.from),
+            None => self.broadcast(&frame),
+        }
+    }
+
+    fn broadcast_users(&self) {
+        let mut users: Vec<String> = vec![];
+        for name in self.clients.values().filter_map(|c| c.username.as_ref()) {
+            if !users.contains(name) {
+                users.push(name.clone());
+            }
+        }
+        let keys: PublicKeys = self
+            .public_keys
+            .iter()
+            .filter(|(name, _)| users.contains(name))
+            .map(|(name, key)| (name.clone(), key.clone()))
+            .collect();
+        self.broadcast(&WebSocketMessage {
+            message_type: MsgTypes::Users,
+            data: Some(serde_json::to_string(&keys).unwrap()),
+            data_array: Some(users),
+        });
+    }
+
+    /// Sends `msg` to every registered client.
+    fn broadcast(&self, msg: &WebSocketMessage) {
+        self.broadcast_where(msg, |_, _| true);
+    }
+
+    /// Sends `msg` to the registered clients `to` picks by id and username.
+    fn broadcast_where(&self, msg: &WebSocketMessage, to: impl Fn(ClientId, &str) -> bool) {
+        let mut encoded: Vec<(Encoding, Outgoing)> = vec![];
+        let recipients = self.clients.iter().filter_map(|(id, c)| match &c.username {
+            Some(name) if to(*id, name) => Some(c),
+            _ => None,
+        });
+        for client in recipients {
+            let frame = match encoded.iter().find(|(e, _)| *e == client.encoding) {
+                Some((_, frame)) => frame.clone(),
+                None => {
+                    let frame = encode(client.encoding, msg);
+                    encoded.push((client.encoding, frame.clone()));
+                    frame
+                }
+            };
+            // A closed channel means the connection is on its way out and
+            // will be disconnected shortly.
+            let _ = client.tx.send(frame);
+        }
+    }
+
+    fn send(&self, id: ClientId, msg: &WebSocketMessage) {
+        if let Some(client) = self.clients.get(&id) {
+            let _ = client.tx.send(encode(client.encoding, msg));
+        }
+    }
+}
+
+/// `bytes` random bytes, in hex.
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0; bytes];
+    getrandom::getrandom(&mut buf).expect("the OS to have randomness");
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses the JSON `data` of a frame.
+fn decode<T: DeserializeOwned>(data: Option<String>, what: &str) -> Result<T, String> {
+    let data = data.ok_or_else(|| format!("{} frame without data", what))?;
+    serde_json::from_str(&data).map_err(|e| format!("malformed {}: {}", what, e))
+}
+
+fn sanction_frame(message_type: MsgTypes, sanction: &Sanction) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type,
+        data: Some(serde_json::to_string(sanction).unwrap()),
+        data_array: None,
+    }
+}
+
+fn topic_frame(topic: &TopicData) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: MsgTypes::Topic,
+        data: Some(serde_json::to_string(topic).unwrap()),
+        data_array: None,
+    }
+}
+
+fn encode(encoding: Encoding, msg: &WebSocketMessage) -> Outgoing {
+    if encoding.is_binary() {
+        Outgoing::Binary(encoding.encode(msg).unwrap())
+    } else {
+        Outgoing::Text(serde_json::to_string(msg).unwrap())
+    }
+}
+
+/// Milliseconds since the epoch.
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_millis() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn frame(message_type: MsgTypes, data: &str) -> String {
+        serde_json::to_string(&WebSocketMessage {
+            message_type,
+            data: Some(data.to_string()),
+            data_array: None,
+        })
+        .unwrap()
+    }
+
+    fn join(hub: &mut Hub, id: ClientId, name: &str) -> UnboundedReceiver<Outgoing> {
+        let (tx, rx) = unbounded_channel();
+        hub.connect(id, tx);
+        // Back with the token from last time, if they've been here before.
+        let token = hub.sessions.get(name).cloned();
+        let register = WebSocketMessage::register(name, token.as_deref());
+        hub.handle(id, &serde_json::to_string(&register).unwrap())
+            .unwrap();
+        rx
+    }
+
+    fn received(rx: &mut UnboundedReceiver<Outgoing>) -> Vec<WebSocketMessage> {
+        let mut frames = vec![];
+        let mut encoding = Encoding::Json;
+        while let Ok(frame) = rx.try_recv() {
+            let msg: WebSocketMessage = match frame {
+                Outgoing::Text(text) => serde_json::from_str(&text).unwrap(),
+                Outgoing::Binary(bytes) => encoding.decode(&bytes).unwrap(),
+                Outgoing::Close(_) => continue,
+            };
+            if msg.message_type == MsgTypes::Encoding {
+                encoding = Encoding::from_name(msg.data.as_deref().unwrap()).unwrap();
+            }
+            frames.push(msg);
+        }
+        frames
+    }
+
+    fn users(msg: &WebSocketMessage) -> Vec<String> {
+        assert!(matches!(msg.message_type, MsgTypes::Users));
+        msg.data_array.clone().unwrap()
+    }
+
+    #[test]
+    fn names_belong_to_whoever_registered_them_first() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let sessions = of_type(&received(&mut alice), MsgTypes::Session);
+        let token = sessions[0].data.clone().unwrap();
+        assert_eq!(hub.sessions["alice"], token);
+        let again = serde_json::to_string(&WebSocketMessage::register("alice", None)).unwrap();
+        assert!(hub.handle(1, &again).is_err());
+
+        // Not while she's here, and not after she's left, without her token.
+        for (id, token) in [(2, None), (3, Some("guess"))] {
+            let (tx, mut rx) = unbounded_channel();
+            hub.connect(id, tx);
+            let register = WebSocketMessage::register("alice", token);
+            hub.handle(id, &serde_json::to_string(&register).unwrap())
+                .unwrap();
+            assert_eq!(received(&mut rx)[0].message_type, MsgTypes::Refused);
+            if id == 2 {
+                hub.disconnect(1);
+            }
+        }
+        assert!(received(&mut alice).is_empty());
+        assert_eq!(hub.username(2), None);
+
+        let mut alice = join(&mut hub, 4, "alice");
+        let frames = received(&mut alice);
+        assert_eq!(users(&frames[0]), vec!["alice"]);
+        assert!(of_type(&frames, MsgTypes::Session).is_empty());
+        assert_eq!(hub.role("alice"), Role::Owner);
+    }
+
+    #[test]
+    fn bots_cannot_take_names_in_use() {
+        let mut hub = Hub::default();
+        let _alice = join(&mut hub, 1, "alice");
+        hub.disconnect(1);
+        let (tx, mut ci) = unbounded_channel();
+        hub.connect(2, tx);
+        hub.handle(2, &frame(MsgTypes::RegisterBot, r#"{"name":"ci"}"#))
+            .unwrap();
+        assert_eq!(received(&mut ci)[0].message_type, MsgTypes::Users);
+
+        for (id, name) in [(3, "alice"), (4, "ci")] {
+            let (tx, mut rx) = unbounded_channel();
+            hub.connect(id, tx);
+            let identity = format!(r#"{{"name":"{}"}}"#, name);
+            hub.handle(id, &frame(MsgTypes::RegisterBot, &identity))
+                .unwrap();
+            assert_eq!(received(&mut rx)[0].message_type, MsgTypes::Refused);
+        }
+        // Nor can people, and the owner can't hand the bot a role either.
+        let mut bob = join(&mut hub, 5, "ci");
+        assert_eq!(received(&mut bob)[0].message_type, MsgTypes::Refused);
+        let _alice = join(&mut hub, 6, "alice");
+        let promote = r#"{"action":"setrole","user":"ci","role":"moderator"}"#;
+        assert!(moderate(&mut hub, 6, promote).is_err());
+    }
+
+    #[test]
+    fn user_list_follows_joins_and_leaves() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let _bob = join(&mut hub, 2, "bob");
+
+        let lists = |rx: &mut UnboundedReceiver<Outgoing>| -> Vec<WebSocketMessage> {
+            received(rx)
+                .into_iter()
+                .filter(|m| m.message_type == MsgTypes::Users)
+                .collect()
+        };
+        let frames = lists(&mut alice);
+        assert_eq!(users(&frames[0]), vec!["alice"]);
+        assert_eq!(users(&frames[1]), vec!["alice", "bob"]);
+
+        hub.disconnect(2);
+        assert_eq!(users(&lists(&mut alice)[0]), vec!["alice"]);
+    }
+
+    #[test]
+    fn messages_are_numbered_and_sent_to_everyone() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        hub.handle(2, &frame(MsgTypes::Message, "hi")).unwrap();
+        hub.handle(2, &frame(MsgTypes::Message, "there")).unwrap();
+        for rx in [&mut alice, &mut bob] {
+            let messages: Vec<MessageData> = received(rx)
+                .into_iter()
+                .map(|m| serde_json::from_str(&m.data.unwrap()).unwrap())
+                .collect();
+            assert_eq!(messages.len(), 2);
+            assert_eq!((messages[0].id, messages[1].id), (1, 2));
+            assert_eq!(messages[0].from, "bob");
+            assert_eq!(messages[0].message, "hi");
+            assert!(messages[0].timestamp > 0.0);
+        }
+    }
+
+    #[test]
+    fn rejects_messages_before_register() {
+        let mut hub = Hub::default();
+        let (tx, mut rx) = unbounded_channel();
+        hub.connect(1, tx);
+        assert!(hub.handle(1, &frame(MsgTypes::Message, "hi")).is_err());
+        assert!(hub.handle(1, "not json").is_err());
+        assert!(received(&mut rx).is_empty());
+    }
+
+    #[test]
+    fn switches_to_the_negotiated_encoding() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let (tx, mut bob) = unbounded_channel();
+        hub.connect(2, tx);
+        let register = WebSocketMessage {
+            data_array: Some(vec!["bson".into(), "cbor".into()]),
+            ..WebSocketMessage::register("bob", None)
+        };
+        hub.handle(2, &serde_json::to_string(&register).unwrap())
+            .unwrap();
+
+        let accepted = bob.try_recv().unwrap();
+        let Outgoing::Text(accepted) = accepted else {
+            panic!("the encoding is announced in JSON");
+        };
+        assert!(accepted.contains(r#""data":"cbor""#));
+        assert!(matches!(bob.try_recv().unwrap(), Outgoing::Binary(_)));
+
+        let hi = Encoding::Cbor
+            .encode(&WebSocketMessage {
+                message_type: MsgTypes::Message,
+                data: Some("hi".into()),
+                data_array: None,
+            })
+            .unwrap();
+        hub.handle_binary(2, &hi).unwrap();
+        assert!(hub.handle_binary(1, &hi).is_err());
+
+        // Alice still gets JSON.
+        let frames = received(&mut alice);
+        assert!(frames.iter().any(|m| m.message_type == MsgTypes::Message));
+    }
+
+    #[test]
+    fn answers_pings_to_the_sender_only() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        hub.handle(1, &frame(MsgTypes::Ping, "1234.5")).unwrap();
+        let pong = &received(&mut alice)[0];
+        assert!(matches!(pong.message_type, MsgTypes::Pong));
+        assert_eq!(pong.data.as_deref(), Some("1234.5"));
+        assert!(received(&mut bob).is_empty());
+    }
+
+    #[test]
+    fn slows_down_clients_that_flood() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        for _ in 0..=yewchat_protocol::MESSAGE_BURST {
+            hub.handle(2, &frame(MsgTypes::Message, "spam")).unwrap();
+        }
+        let to_alice = received(&mut alice);
+        assert_eq!(to_alice.len(), yewchat_protocol::MESSAGE_BURST as usize);
+        assert!(to_alice.iter().all(|m| m.message_type == MsgTypes::Message));
+
+        let to_bob = received(&mut bob);
+        let slow_down = to_bob.last().unwrap();
+        assert_eq!(slow_down.message_type, MsgTypes::SlowDown);
+        let wait: f64 = slow_down.data.as_deref().unwrap().parse().unwrap();
+        assert!(wait > 0.0 && wait <= 1000.0);
+    }
+
+    #[test]
+    fn typing_goes_to_everyone_else_once_per_interval() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        let typing = r#"{"user":"mallory","room":"general"}"#;
+        hub.handle(2, &frame(MsgTypes::Typing, typing)).unwrap();
+        hub.handle(2, &frame(MsgTypes::Typing, typing)).unwrap();
+
+        let to_alice = received(&mut alice);
+        assert_eq!(to_alice.len(), 1);
+        let data: TypingData = serde_json::from_str(to_alice[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!((data.user.as_str(), data.room.as_str()), ("bob", "general"));
+        assert!(received(&mut bob).is_empty());
+    }
+
+    #[test]
+    fn direct_messages_only_reach_the_two_people() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        let mut carol = join(&mut hub, 3, "carol");
+        for rx in [&mut alice, &mut bob, &mut carol] {
+            received(rx);
+        }
+
+        let dm = WebSocketMessage::chat("psst", "random", Some("carol"));
+        hub.handle(1, &serde_json::to_string(&dm).unwrap()).unwrap();
+        for rx in [&mut alice, &mut carol] {
+            let m: MessageData =
+                serde_json::from_str(&received(rx)[0].data.clone().unwrap()).unwrap();
+            assert_eq!(
+                (m.room.as_str(), m.to.as_deref()),
+                ("random", Some("carol"))
+            );
+        }
+        assert!(received(&mut bob).is_empty());
+    }
+
+    #[test]
+    fn sealed_messages_are_passed_on_unread() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        let mut carol = join(&mut hub, 3, "carol");
+        for rx in [&mut alice, &mut bob, &mut carol] {
+            received(rx);
+        }
+
+        let key = format!("04{}", "ab".repeat(64));
+        hub.handle(3, &frame(MsgTypes::PublicKey, &key)).unwrap();
+        assert!(hub.handle(1, &frame(MsgTypes::PublicKey, "04ab")).is_err());
+        let list = received(&mut alice).pop().unwrap();
+        let keys: PublicKeys = serde_json::from_str(list.data.as_deref().unwrap()).unwrap();
+        assert_eq!(keys, PublicKeys::from([("carol".to_string(), key.clone())]));
+
+        // Carol's second device can't replace the key she's using.
+        let _laptop = join(&mut hub, 4, "carol");
+        let other = format!("04{}", "ef".repeat(64));
+        assert!(hub.handle(4, &frame(MsgTypes::PublicKey, &other)).is_err());
+        hub.handle(4, &frame(MsgTypes::PublicKey, &key)).unwrap();
+        hub.disconnect(4);
+        assert_eq!(hub.public_keys["carol"], key);
+        received(&mut alice);
+        received(&mut bob);
+        received(&mut carol);
+
+        let sealed = Sealed {
+            sender_key: format!("04{}", "cd".repeat(64)),
+            recipient_key: key,
+            iv: "00".repeat(12),
+            ciphertext: "ff".repeat(20),
+        };
+        let mut dm = WebSocketMessage::chat("", "general", Some("carol"));
+        dm.message_type = MsgTypes::Sealed;
+        dm.data = Some(serde_json::to_string(&sealed).unwrap());
+        hub.handle(1, &serde_json::to_string(&dm).unwrap()).unwrap();
+        for rx in [&mut alice, &mut carol] {
+            let m: MessageData =
+                serde_json::from_str(&received(rx).pop().unwrap().data.unwrap()).unwrap();
+            assert_eq!((m.from.as_str(), m.message.as_str()), ("alice", ""));
+            assert_eq!(m.sealed.as_deref(), Some(&sealed));
+        }
+        assert!(received(&mut bob).is_empty());
+
+        // Sealed messages need someone to be sealed for.
+        dm.data_array = Some(vec!["general".into()]);
+        assert!(hub.handle(1, &serde_json::to_string(&dm).unwrap()).is_err());
+
+        hub.disconnect(3);
+        let list = received(&mut alice).pop().unwrap();
+        assert_eq!(list.data.as_deref(), Some("{}"));
+    }
+
+    #[test]
+    fn profile_changes_reach_everyone() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        let profile =
+            r#"{"displayName":"Alice L.","pronouns":"she/her","timeZone":"Asia/Jakarta"}"#;
+        hub.handle(1, &frame(MsgTypes::Profile, profile)).unwrap();
+        let frames = of_type(&received(&mut bob), MsgTypes::Profiles);
+        let profiles: BTreeMap<String, Profile> =
+            serde_json::from_str(frames[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(profiles["alice"].display_name, "Alice L.");
+
+        let too_long = format!(r#"{{"bio":"{}"}}"#, "x".repeat(Profile::BIO_MAX + 1));
+        assert!(hub.handle(1, &frame(MsgTypes::Profile, &too_long)).is_err());
+        assert!(received(&mut bob).is_empty());
+
+        // Profiles outlive the connection, for whoever joins later.
+        hub.disconnect(1);
+        let mut carol = join(&mut hub, 3, "carol");
+        let frames = of_type(&received(&mut carol), MsgTypes::Profiles);
+        let profiles: BTreeMap<String, Profile> =
+            serde_json::from_str(frames[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(profiles["alice"].pronouns, "she/her");
+    }
+
+    #[test]
+    fn topics_are_kept_for_people_who_join_later() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        received(&mut alice);
+
+        let topic = r#"{"room":"general","topic":"Friday demo"}"#;
+        hub.handle(1, &frame(MsgTypes::Topic, topic)).unwrap();
+        assert_eq!(received(&mut alice)[0].message_type, MsgTypes::Topic);
+
+        let mut bob = join(&mut hub, 2, "bob");
+        let topics = of_type(&received(&mut bob), MsgTypes::Topic);
+        let data: TopicData = serde_json::from_str(topics[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            (data.topic.as_str(), data.set_by.as_str()),
+            ("Friday demo", "alice")
+        );
+    }
+
+    #[test]
+    fn button_presses_go_back_to_the_bot() {
+        let mut hub = Hub::default();
+        let (tx, mut ci) = unbounded_channel();
+        hub.connect(1, tx);
+        let identity = r#"{"name":"ci","description":"Build status"}"#;
+        hub.handle(1, &frame(MsgTypes::RegisterBot, identity))
+            .unwrap();
+        let mut alice = join(&mut hub, 2, "alice");
+        received(&mut ci);
+        received(&mut alice);
+
+        let post = r##"{"room":"general","text":"Build failed","attachments":[
+            {"title":"main #42","color":"#d00","buttons":[{"id":"retry","label":"Retry"}]}]}"##;
+        hub.handle(1, &frame(MsgTypes::BotMessage, post)).unwrap();
+        let posted: MessageData =
+            serde_json::from_str(received(&mut alice)[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(posted.bot.unwrap().description, "Build status");
+        assert_eq!(posted.attachments[0].buttons[0].id, "retry");
+        received(&mut ci);
+
+        let press = format!(r#"{{"messageId":{},"button":"retry"}}"#, posted.id);
+        hub.handle(2, &frame(MsgTypes::Interaction, &press))
+            .unwrap();
+        let to_bot = received(&mut ci);
+        let pressed: InteractionData =
+            serde_json::from_str(to_bot[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            (pressed.user.as_str(), pressed.button.as_str()),
+            ("alice", "retry")
+        );
+        assert!(received(&mut alice).is_empty());
+
+        let bogus = format!(r#"{{"messageId":{},"button":"deploy"}}"#, posted.id);
+        assert!(hub
+            .handle(2, &frame(MsgTypes::Interaction, &bogus))
+            .is_err());
+    }
+
+    #[test]
+    fn people_cannot_attach_cards() {
+        let mut hub = Hub::default();
+        let _alice = join(&mut hub, 1, "alice");
+        let post = r#"{"text":"hi","attachments":[{"title":"fake build"}]}"#;
+        assert!(hub.handle(1, &frame(MsgTypes::BotMessage, post)).is_err());
+    }
+
+    #[test]
+    fn webhooks_post_into_their_room_until_revoked() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        received(&mut alice);
+
+        let request = r#"{"room":"builds","name":"Jenkins"}"#;
+        hub.handle(1, &frame(MsgTypes::CreateWebhook, request))
+            .unwrap();
+        let list: WebhookList =
+            serde_json::from_str(received(&mut alice)[0].data.as_deref().unwrap()).unwrap();
+        let hook = list.hooks[0].clone();
+        assert_eq!(
+            (hook.room.as_str(), hook.created_by.as_str()),
+            ("builds", "alice")
+        );
+        assert_eq!(hook.token.len(), 32);
+
+        let payload = WebhookPayload {
+            text: "deployed".into(),
+            username: None,
+            attachments: vec![],
+        };
+        hub.post_webhook(&hook.token, payload.clone()).unwrap();
+        let posted: MessageData =
+            serde_json::from_str(received(&mut alice)[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            (posted.room.as_str(), posted.from.as_str()),
+            ("builds", "Jenkins")
+        );
+        assert!(posted.bot.is_some());
+
+        assert_eq!(
+            hub.post_webhook("guess", payload.clone()),
+            Err(WebhookError::UnknownToken)
+        );
+        hub.handle(1, &frame(MsgTypes::RevokeWebhook, &hook.id))
+            .unwrap();
+        assert_eq!(
+            hub.post_webhook(&hook.token, payload),
+            Err(WebhookError::UnknownToken)
+        );
+    }
+
+    #[test]
+    fn webhooks_cannot_post_as_people() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let _bob = join(&mut hub, 2, "bob");
+        hub.disconnect(2);
+        received(&mut alice);
+        hub.handle(
+            1,
+            &frame(MsgTypes::CreateWebhook, r#"{"room":"general","name":"ci"}"#),
+        )
+        .unwrap();
+        let list: WebhookList =
+            serde_json::from_str(received(&mut alice)[0].data.as_deref().unwrap()).unwrap();
+        let token = list.hooks[0].token.clone();
+
+        // Online, gone but with their name kept, and nobody at all.
+        for (name, shown) in [
+            ("alice", "alice (via ci)"),
+            ("bob", "bob (via ci)"),
+            ("deploy", "deploy"),
+        ] {
+            let payload = WebhookPayload {
+                text: "deployed".into(),
+                username: Some(name.into()),
+                attachments: vec![],
+            };
+            hub.post_webhook(&token, payload).unwrap();
+            let posted: MessageData =
+                serde_json::from_str(received(&mut alice)[0].data.as_deref().unwrap()).unwrap();
+            assert_eq!(posted.from, shown);
+        }
+
+        // Whoever takes the name later doesn't get to treat it as theirs.
+        let _deploy = join(&mut hub, 3, "deploy");
+        let remove = format!(
+            r#"{{"action":"remove","messageId":{}}}"#,
+            hub.last_message_id
+        );
+        assert_eq!(
+            moderate(&mut hub, 3, &remove),
+            Err("deploy can't remove messages by deploy".into())
+        );
+    }
+
+    fn moderate(hub: &mut Hub, id: ClientId, moderation: &str) -> Result<(), String> {
+        hub.handle(id, &frame(MsgTypes::Moderate, moderation))
+    }
+
+    fn of_type(frames: &[WebSocketMessage], message_type: MsgTypes) -> Vec<WebSocketMessage> {
+        frames
+            .iter()
+            .filter(|m| m.message_type == message_type)
+            .cloned()
+            .collect()
+    }
+
+    fn roles(msg: &WebSocketMessage) -> BTreeMap<String, Role> {
+        serde_json::from_str(msg.data.as_deref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_first_person_to_register_is_the_owner() {
+        let mut hub = Hub::default();
+        let (tx, mut ci) = unbounded_channel();
+        hub.connect(1, tx);
+        hub.handle(1, &frame(MsgTypes::RegisterBot, r#"{"name":"ci"}"#))
+            .unwrap();
+        let mut alice = join(&mut hub, 2, "alice");
+        let mut bob = join(&mut hub, 3, "bob");
+
+        let owner = BTreeMap::from([("alice".to_string(), Role::Owner)]);
+        assert_eq!(
+            roles(of_type(&received(&mut ci), MsgTypes::Roles).last().unwrap()),
+            owner
+        );
+        let to_alice = received(&mut alice);
+        assert_eq!(roles(&of_type(&to_alice, MsgTypes::Roles)[0]), owner);
+        assert_eq!(of_type(&to_alice, MsgTypes::ModerationLog).len(), 1);
+        let to_bob = received(&mut bob);
+        assert_eq!(roles(&of_type(&to_bob, MsgTypes::Roles)[0]), owner);
+        assert!(of_type(&to_bob, MsgTypes::ModerationLog).is_empty());
+
+        let request = r#"{"room":"general","name":"ci"}"#;
+        assert!(hub
+            .handle(3, &frame(MsgTypes::CreateWebhook, request))
+            .is_err());
+        let promote = r#"{"action":"setrole","user":"bob","role":"moderator"}"#;
+        assert!(moderate(&mut hub, 3, promote).is_err());
+        moderate(&mut hub, 2, promote).unwrap();
+        let to_bob = received(&mut bob);
+        assert_eq!(
+            roles(&of_type(&to_bob, MsgTypes::Roles)[0])["bob"],
+            Role::Moderator
+        );
+        assert_eq!(of_type(&to_bob, MsgTypes::ModerationLog).len(), 2);
+        hub.handle(3, &frame(MsgTypes::CreateWebhook, request))
+            .unwrap();
+
+        let usurp = r#"{"action":"setrole","user":"bob","role":"owner"}"#;
+        assert!(moderate(&mut hub, 2, usurp).is_err());
+    }
+
+    #[test]
+    fn muted_people_cannot_post() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut alice);
+        received(&mut bob);
+
+        assert!(moderate(&mut hub, 2, r#"{"action":"mute","user":"alice"}"#).is_err());
+        moderate(
+            &mut hub,
+            1,
+            r#"{"action":"mute","user":"bob","minutes":5,"reason":"calm down"}"#,
+        )
+        .unwrap();
+        let muted: Sanction =
+            serde_json::from_str(received(&mut bob)[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            (muted.by.as_str(), muted.reason.as_str()),
+            ("alice", "calm down")
+        );
+        assert!(muted.until.unwrap() > now());
+        let log = of_type(&received(&mut alice), MsgTypes::ModerationLog);
+        let entries: Vec<ModerationEntry> =
+            serde_json::from_str(log[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            entries[0].moderation.action.describe(),
+            "muted bob for 5 minutes"
+        );
+
+        hub.handle(2, &frame(MsgTypes::Message, "still here"))
+            .unwrap();
+        assert!(received(&mut alice).is_empty());
+        assert_eq!(received(&mut bob)[0].message_type, MsgTypes::Muted);
+
+        // Reconnecting doesn't help.
+        hub.disconnect(2);
+        let mut bob = join(&mut hub, 3, "bob");
+        assert!(!of_type(&received(&mut bob), MsgTypes::Muted).is_empty());
+
+        moderate(&mut hub, 1, r#"{"action":"unmute","user":"bob"}"#).unwrap();
+        assert_eq!(received(&mut bob)[0].message_type, MsgTypes::Unmuted);
+        hub.handle(3, &frame(MsgTypes::Message, "thanks")).unwrap();
+        assert!(!of_type(&received(&mut alice), MsgTypes::Message).is_empty());
+    }
+
+    #[test]
+    fn kicked_and_banned_people_are_disconnected() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let (tx, mut bob) = unbounded_channel();
+        hub.connect(2, tx);
+        let register = WebSocketMessage::register("bob", None);
+        hub.handle(2, &serde_json::to_string(&register).unwrap())
+            .unwrap();
+        received(&mut alice);
+        received(&mut bob);
+
+        moderate(
+            &mut hub,
+            1,
+            r#"{"action":"kick","user":"bob","reason":"spam"}"#,
+        )
+        .unwrap();
+        let Outgoing::Text(kicked) = bob.try_recv().unwrap() else {
+            panic!("bob registered with JSON");
+        };
+        assert!(kicked.contains(r#""messageType":"kicked""#));
+        assert_eq!(bob.try_recv().unwrap(), Outgoing::Close("kicked".into()));
+        assert_eq!(users(&received(&mut alice)[0]), vec!["alice"]);
+
+        // Kicked people can come back; banned ones can't.
+        let mut bob = join(&mut hub, 3, "bob");
+        received(&mut bob);
+        moderate(&mut hub, 1, r#"{"action":"ban","user":"bob"}"#).unwrap();
+        assert_eq!(received(&mut bob)[0].message_type, MsgTypes::Kicked);
+        let mut bob = join(&mut hub, 4, "bob");
+        let refused = received(&mut bob);
+        assert_eq!(refused.len(), 1);
+        let sanction: Sanction = serde_json::from_str(refused[0].data.as_deref().unwrap()).unwrap();
+        assert!(sanction.banned);
+
+        moderate(&mut hub, 1, r#"{"action":"unban","user":"bob"}"#).unwrap();
+        let mut bob = join(&mut hub, 5, "bob");
+        assert_eq!(received(&mut bob)[0].message_type, MsgTypes::Users);
+    }
+
+    #[test]
+    fn messages_are_removed_by_their_author_or_a_moderator() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        let mut carol = join(&mut hub, 3, "carol");
+        hub.handle(1, &frame(MsgTypes::Message, "from alice"))
+            .unwrap();
+        hub.handle(2, &frame(MsgTypes::Message, "from bob"))
+            .unwrap();
+        hub.handle(2, &frame(MsgTypes::Message, "also from bob"))
+            .unwrap();
+        for rx in [&mut alice, &mut bob, &mut carol] {
+            received(rx);
+        }
+
+        let remove = |id: u64| format!(r#"{{"action":"remove","messageId":{}}}"#, id);
+        assert!(moderate(&mut hub, 3, &remove(2)).is_err());
+        assert!(moderate(&mut hub, 2, &remove(1)).is_err());
+        moderate(&mut hub, 2, &remove(2)).unwrap();
+        moderate(&mut hub, 1, &remove(3)).unwrap();
+        let removed: Vec<_> = of_type(&received(&mut carol), MsgTypes::Removed)
+            .iter()
+            .map(|m| m.data.clone().unwrap())
+            .collect();
+        assert_eq!(removed, vec!["2", "3"]);
+        assert!(moderate(&mut hub, 1, &remove(3)).is_err());
+
+        let log = of_type(&received(&mut alice), MsgTypes::ModerationLog);
+        let entries: Vec<ModerationEntry> =
+            serde_json::from_str(log[1].data.as_deref().unwrap()).unwrap();
+        assert_eq!((entries[0].id, entries[0].by.as_str()), (2, "alice"));
+        assert_eq!(
+            entries[0].moderation.action.describe(),
+            "removed a message by bob"
+        );
+    }
+
+    #[test]
+    fn read_receipts_name_their_sender() {
+        let mut hub = Hub::default();
+        let _alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        received(&mut bob);
+
+        let read = r#"{"user":"bob","room":"general","messageId":7}"#;
+        hub.handle(1, &frame(MsgTypes::Read, read)).unwrap();
+        let frames = received(&mut bob);
+        let read: ReadData = serde_json::from_str(frames[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!((read.user.as_str(), read.message_id), ("alice", 7));
+
+        assert!(hub.handle(1, &frame(MsgTypes::Read, "7")).is_err());
+    }
+
+    #[test]
+    fn searches_the_history_the_sender_can_see() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let _bob = join(&mut hub, 2, "bob");
+        let _carol = join(&mut hub, 3, "carol");
+        hub.handle(2, &frame(MsgTypes::Message, "the cat is back"))
+            .unwrap();
+        hub.handle(3, &frame(MsgTypes::Message, "a cat video"))
+            .unwrap();
+        hub.handle(2, &frame(MsgTypes::Message, "no pets here"))
+            .unwrap();
+        let dm = WebSocketMessage::chat("psst, cat", "general", Some("carol"));
+        hub.handle(2, &serde_json::to_string(&dm).unwrap()).unwrap();
+        received(&mut alice);
+
+        let query = SearchQuery::parse("CAT from:bob");
+        hub.handle(
+            1,
+            &frame(MsgTypes::Search, &serde_json::to_string(&query).unwrap()),
+        )
+        .unwrap();
+        let frames = received(&mut alice);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].message_type, MsgTypes::SearchResults);
+        let found: SearchResultsData =
+            serde_json::from_str(frames[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(found.query, "CAT from:bob");
+        // Not carol's message, nor bob's direct message to her.
+        let texts: Vec<&str> = found.messages.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(texts, vec!["the cat is back"]);
+
+        // Carol sees the direct message too, newest first.
+        let found = hub.search("carol", &SearchQuery::parse("cat"));
+        let texts: Vec<&str> = found.messages.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(texts, vec!["psst, cat", "a cat video", "the cat is back"]);
+    }
+
+    #[test]
+    fn reports_reach_moderators_until_closed() {
+        let mut hub = Hub::default();
+        let mut alice = join(&mut hub, 1, "alice");
+        let mut bob = join(&mut hub, 2, "bob");
+        let mut carol = join(&mut hub, 3, "carol");
+        hub.handle(2, &frame(MsgTypes::Message, "one")).unwrap();
+        let dm = WebSocketMessage::chat("psst", yewchat_protocol::DEFAULT_ROOM, Some("alice"));
+        hub.handle(3, &serde_json::to_string(&dm).unwrap()).unwrap();
+        hub.handle(2, &frame(MsgTypes::Message, "two")).unwrap();
+        hub.handle(3, &frame(MsgTypes::Message, "rude")).unwrap();
+        for rx in [&mut alice, &mut bob, &mut carol] {
+            received(rx);
+        }
+
+        let report = |id: u64| {
+            format!(
+                r#"{{"messageId":{},"category":"harassment","note":" mean "}}"#,
+                id
+            )
+        };
+        // Nobody reports their own messages, or direct messages they can't see.
+        assert!(hub.handle(3, &frame(MsgTypes::Report, &report(4))).is_err());
+        assert!(hub.handle(2, &frame(MsgTypes::Report, &report(2))).is_err());
+        hub.handle(2, &frame(MsgTypes::Report, &report(4))).unwrap();
+        hub.handle(2, &frame(MsgTypes::Report, &report(4))).unwrap();
+
+        let to_alice = of_type(&received(&mut alice), MsgTypes::Reports);
+        assert_eq!(to_alice.len(), 1);
+        let reports: Vec<Report> =
+            serde_json::from_str(to_alice[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(reports.len(), 1);
+        let filed = &reports[0];
+        assert_eq!(
+            (filed.reporter.as_str(), filed.note.as_str()),
+            ("bob", "mean")
+        );
+        assert_eq!(filed.message.message, "rude");
+        let context: Vec<&str> = filed.context.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(context, vec!["one", "two"]);
+        assert!(received(&mut bob).is_empty());
+
+        let close = |dismissed| {
+            format!(
+                r#"{{"action":"closereport","reportId":{},"dismissed":{}}}"#,
+                filed.id, dismissed
+            )
+        };
+        assert!(moderate(&mut hub, 2, &close(false)).is_err());
+        moderate(&mut hub, 1, &close(true)).unwrap();
+        let to_alice = received(&mut alice);
+        let reports: Vec<Report> = serde_json::from_str(
+            of_type(&to_alice, MsgTypes::Reports)[0]
+                .data
+                .as_deref()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(reports.is_empty());
+        let log = of_type(&to_alice, MsgTypes::ModerationLog);
+        let entries: Vec<ModerationEntry> =
+            serde_json::from_str(log[0].data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            entries[0].moderation.action.describe(),
+            "dismissed a report about carol"
+        );
+        assert!(moderate(&mut hub, 1, &close(false)).is_err());
+    }
+}
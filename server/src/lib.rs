@@ -0,0 +1,125 @@
+//! WebSocket server for Yewchat, with webhooks and an event-stream fallback
+//! over plain HTTP on the same port.
+
+pub mod http;
+pub mod hub;
+pub mod mock;
+pub mod sse;
+pub mod webhook;
+
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::hub::{ClientId, Hub, Outgoing};
+
+/// Accepts connections on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener) {
+    let hub = Arc::new(Mutex::new(Hub::default()));
+    let streams = Arc::new(sse::Streams::default());
+    let mut next_id: ClientId = 0;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        next_id += 1;
+        let id = next_id;
+        let hub = hub.clone();
+        let streams = streams.clone();
+        tokio::spawn(async move {
+            let result = if is_websocket(&stream).await {
+                handle_connection(hub, id, stream)
+                    .await
+                    .map_err(|e| e.to_string())
+            } else {
+                http::serve(hub, streams, id, stream)
+                    .await
+                    .map_err(|e| e.to_string())
+            };
+            if let Err(e) = result {
+                eprintln!("{}: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Whether the request on `stream` can be a WebSocket upgrade, which is
+/// always a `GET`. Webhooks and frames from event-stream clients are posted,
+/// and the event streams themselves have a path of their own.
+async fn is_websocket(stream: &TcpStream) -> bool {
+    let events = format!("GET {}", sse::EVENTS_PATH);
+    let mut start = [0; 16];
+    match stream.peek(&mut start).await {
+        Ok(n) => {
+            let start = &start[..n];
+            b"GET ".starts_with(&start[..n.min(4)]) && !start.starts_with(events.as_bytes())
+        }
+        Err(_) => true,
+    }
+}
+
+async fn handle_connection(
+    hub: Arc<Mutex<Hub>>,
+    id: ClientId,
+    stream: TcpStream,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let ws = tokio_tungstenite::accept_async(stream).await?;
+    let (mut write, mut read) = ws.split();
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Outgoing>();
+    hub.lock().unwrap().connect(id, tx);
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            let frame = match frame {
+                Outgoing::Text(text) => Message::Text(text),
+                Outgoing::Binary(bytes) => Message::Binary(bytes),
+                Outgoing::Close(reason) => {
+                    let close = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: reason.into(),
+                    };
+                    let _ = write.send(Message::Close(Some(close))).await;
+                    break;
+                }
+            };
+            if write.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut result = Ok(());
+    while let Some(msg) = read.next().await {
+        match msg {
+            Ok(Message::Text(frame)) => {
+                if let Err(e) = hub.lock().unwrap().handle(id, &frame) {
+                    eprintln!("client {}: {}", id, e);
+                }
+            }
+            Ok(Message::Binary(frame)) => {
+                if let Err(e) = hub.lock().unwrap().handle_binary(id, &frame) {
+                    eprintln!("client {}: {}", id, e);
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    hub.lock().unwrap().disconnect(id);
+    writer.abort();
+    result
+}
@@ -0,0 +1,20 @@
+use tokio::net::TcpListener;
+
+/// Where the client expects to find the server.
+const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+#[tokio::main]
+async fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("can't listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Yewchat server listening on ws://{}", addr);
+    yewchat_server::serve(listener).await;
+}
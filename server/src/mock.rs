@@ -0,0 +1,175 @@
+//! A stand-in server that plays a fixed script to every client, for testing
+//! the client against known conversations.
+//!
+//! A script is JSON lines, each either a frame to send or the type of frame
+//! to wait for:
+//!
+//! ```text
+//! {"expect": "register"}
+//! {"send": {"messageType": "users", "dataArray": ["alice", "bob"]}}
+//! ```
+//!
+//! Frames that don't match the next `expect` are skipped, and pings are
+//! always answered, so a client going about its business doesn't derail it.
+
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+use yewchat_protocol::{MsgTypes, WebSocketMessage};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Expect(MsgTypes),
+    Send(WebSocketMessage),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Parses a script, skipping blank lines and `#` comments.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let steps = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { steps })
+    }
+}
+
+/// Where one connection has got to in the script.
+pub struct Replay<'a> {
+    steps: &'a [Step],
+    next: usize,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(script: &'a Script) -> Self {
+        Self {
+            steps: &script.steps,
+            next: 0,
+        }
+    }
+
+    /// Frames to send straight away, before the client has said anything.
+    pub fn start(&mut self) -> Vec<WebSocketMessage> {
+        self.sends()
+    }
+
+    /// Frames to send in reply to `msg`.
+    pub fn receive(&mut self, msg: WebSocketMessage) -> Vec<WebSocketMessage> {
+        if msg.message_type == MsgTypes::Ping {
+            return vec![WebSocketMessage {
+                message_type: MsgTypes::Pong,
+                ..msg
+            }];
+        }
+        match self.steps.get(self.next) {
+            Some(Step::Expect(t)) if *t == msg.message_type => {
+                self.next += 1;
+                self.sends()
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next == self.steps.len()
+    }
+
+    fn sends(&mut self) -> Vec<WebSocketMessage> {
+        let mut frames = vec![];
+        while let Some(Step::Send(frame)) = self.steps.get(self.next) {
+            frames.push(frame.clone());
+            self.next += 1;
+        }
+        frames
+    }
+}
+
+/// Plays `script` to every client that connects to `listener`.
+pub async fn serve(listener: TcpListener, script: Script) {
+    let script = Arc::new(script);
+    while let Ok((stream, peer)) = listener.accept().await {
+        let script = script.clone();
+        tokio::spawn(async move {
+            let mut ws = match tokio_tungstenite::accept_async(stream).await {
+                Ok(ws) => ws,
+                Err(e) => return eprintln!("{}: {}", peer, e),
+            };
+            let mut replay = Replay::new(&script);
+            let mut replies = replay.start();
+            loop {
+                for frame in replies.drain(..) {
+                    let frame = serde_json::to_string(&frame).unwrap();
+                    if ws.send(Message::Text(frame)).await.is_err() {
+                        return;
+                    }
+                }
+                match ws.next().await {
+                    Some(Ok(Message::Text(frame))) => match serde_json::from_str(&frame) {
+                        Ok(msg) => replies = replay.receive(msg),
+                        Err(e) => eprintln!("{}: malformed frame: {}", peer, e),
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+            if !replay.is_done() {
+                eprintln!("{}: left before the end of the script", peer);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: &str = include_str!("../../tests/fixtures/chat.jsonl");
+
+    fn frame(message_type: MsgTypes, data: &str) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type,
+            data: Some(data.to_string()),
+            data_array: None,
+        }
+    }
+
+    #[test]
+    fn replays_the_chat_fixture() {
+        let script = Script::parse(CHAT).unwrap();
+        let mut replay = Replay::new(&script);
+        assert!(replay.start().is_empty());
+
+        let welcome = replay.receive(frame(MsgTypes::Register, "alice"));
+        assert_eq!(welcome[0].message_type, MsgTypes::Users);
+        assert_eq!(welcome[1].message_type, MsgTypes::Message);
+
+        // Unexpected frames don't advance the script; pings are answered.
+        assert!(replay.receive(frame(MsgTypes::Read, "{}")).is_empty());
+        let pong = replay.receive(frame(MsgTypes::Ping, "12"));
+        assert_eq!(pong, vec![frame(MsgTypes::Pong, "12")]);
+
+        let echo = replay.receive(frame(MsgTypes::Message, "hello bob"));
+        assert_eq!(echo[0].message_type, MsgTypes::Message);
+        assert!(replay.is_done());
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let err =
+            Script::parse("# comment\n{\"expect\": \"register\"}\n{\"shout\": 1}").unwrap_err();
+        assert!(err.starts_with("line 3:"), "{}", err);
+    }
+}
@@ -0,0 +1,228 @@
+//! The chat over Server-Sent Events, for networks that block WebSockets.
+//!
+//! `GET /events?client=<key>` opens a `text/event-stream` that carries every
+//! frame for the client as one event; `POST /send?client=<key>` hands the
+//! hub a frame from it. The key is made up by the client and is all that
+//! ties the two together, so it should be hard to guess.
+//!
+//! Browsers reopen a dropped event stream by themselves with the same key,
+//! and the new stream carries on as whoever the old one had registered as.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::http::{Response, CORS_HEADERS};
+use crate::hub::{ClientId, Hub, Outgoing};
+
+pub const EVENTS_PATH: &str = "/events";
+pub const SEND_PATH: &str = "/send";
+/// Longest client key accepted.
+const MAX_KEY_LEN: usize = 64;
+
+/// The open event streams: which hub client each key is.
+pub type Streams = Mutex<HashMap<String, ClientId>>;
+
+/// The client key in a query string, if there's a sensible one.
+pub fn client_key(query: &str) -> Option<String> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("client="))
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LEN)
+        .filter(|key| key.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .map(str::to_string)
+}
+
+/// Serves the event stream for `key` as hub client `id`, until the browser
+/// goes away or the hub closes it.
+pub async fn stream(
+    hub: Arc<Mutex<Hub>>,
+    streams: Arc<Streams>,
+    id: ClientId,
+    key: String,
+    stream: TcpStream,
+) -> io::Result<()> {
+    let (mut read, mut write) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Before answering, so frames can be sent as soon as the stream opens.
+    attach(&hub, &streams, id, &key, tx);
+    let head = format!(
+        "HTTP/1.1 200 OK\r\n{}Content-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+        CORS_HEADERS
+    );
+    let result = match write.write_all(head.as_bytes()).await {
+        Ok(()) => forward(&mut rx, &mut read, &mut write).await,
+        Err(e) => Err(e),
+    };
+    {
+        let mut streams = streams.lock().unwrap();
+        if streams.get(&key) == Some(&id) {
+            streams.remove(&key);
+        }
+    }
+    hub.lock().unwrap().disconnect(id);
+    result
+}
+
+/// Connects hub client `id` for the stream with `key`. A browser
+/// reconnecting after a hiccup takes its key, and whoever it had registered
+/// as, over from the stream it's leaving.
+fn attach(
+    hub: &Mutex<Hub>,
+    streams: &Streams,
+    id: ClientId,
+    key: &str,
+    tx: UnboundedSender<Outgoing>,
+) {
+    let mut hub = hub.lock().unwrap();
+    hub.connect(id, tx);
+    if let Some(old) = streams.lock().unwrap().insert(key.to_string(), id) {
+        hub.take_over(old, id);
+    }
+}
+
+/// Writes frames from the hub out as events until one side is done.
+async fn forward(
+    rx: &mut UnboundedReceiver<Outgoing>,
+    read: &mut (impl AsyncRead + Unpin),
+    write: &mut (impl AsyncWrite + Unpin),
+) -> io::Result<()> {
+    let mut ignored = [0; 256];
+    loop {
+        tokio::select! {
+            frame = rx.recv() => match frame {
+                // JSON frames are a single line, so one `data:` field does.
+                Some(Outgoing::Text(text)) => write.write_all(event(&text).as_bytes()).await?,
+                Some(Outgoing::Binary(_)) => {
+                    let e = "event streams only carry text; don't offer binary encodings";
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                Some(Outgoing::Close(_)) | None => return Ok(()),
+            },
+            // The browser has nothing more to say; this only notices it leave.
+            n = read.read(&mut ignored) => {
+                if n? == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn event(data: &str) -> String {
+    format!("data: {}\n\n", data)
+}
+
+/// Hands the hub a frame POSTed by the client with the key in `query`.
+pub fn send(
+    hub: &Mutex<Hub>,
+    streams: &Streams,
+    method: &str,
+    query: &str,
+    body: &[u8],
+) -> Response {
+    if method != "POST" {
+        return Response::new(405, "frames are sent with POST");
+    }
+    let id = match client_key(query).and_then(|key| streams.lock().unwrap().get(&key).copied()) {
+        Some(id) => id,
+        None => return Response::new(404, "no event stream for that client"),
+    };
+    let frame = match std::str::from_utf8(body) {
+        Ok(frame) => frame,
+        Err(_) => return Response::new(400, "frames are UTF-8 JSON"),
+    };
+    match hub.lock().unwrap().handle(id, frame) {
+        Ok(()) => Response::new(204, ""),
+        Err(e) => Response::new(400, &e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+    use yewchat_protocol::{MessageData, MsgTypes, WebSocketMessage};
+
+    #[test]
+    fn reads_client_keys() {
+        assert_eq!(client_key("client=3fa9"), Some("3fa9".to_string()));
+        assert_eq!(client_key("v=2&client=ab12"), Some("ab12".to_string()));
+        assert_eq!(client_key("client="), None);
+        assert_eq!(client_key("client=../x"), None);
+        assert_eq!(client_key(&format!("client={}", "a".repeat(65))), None);
+    }
+
+    #[test]
+    fn sends_frames_for_open_streams_only() {
+        let hub = Mutex::new(Hub::default());
+        let streams = Streams::default();
+        let (tx, mut rx) = unbounded_channel();
+        hub.lock().unwrap().connect(7, tx);
+        streams.lock().unwrap().insert("ab12".to_string(), 7);
+
+        let register = serde_json::to_string(&WebSocketMessage::register("alice", None)).unwrap();
+        let body = register.as_bytes();
+        assert_eq!(
+            send(&hub, &streams, "POST", "client=cd34", body).status,
+            404
+        );
+        assert_eq!(send(&hub, &streams, "GET", "client=ab12", body).status, 405);
+        assert_eq!(
+            send(&hub, &streams, "POST", "client=ab12", b"nope").status,
+            400
+        );
+        assert!(rx.try_recv().is_err());
+
+        assert_eq!(
+            send(&hub, &streams, "POST", "client=ab12", body).status,
+            204
+        );
+        assert!(matches!(rx.try_recv(), Ok(Outgoing::Text(_))));
+    }
+
+    #[test]
+    fn reopened_streams_stay_registered() {
+        let hub = Mutex::new(Hub::default());
+        let streams = Streams::default();
+        let (tx, _old) = unbounded_channel();
+        attach(&hub, &streams, 7, "ab12", tx);
+        let register = serde_json::to_string(&WebSocketMessage::register("alice", None)).unwrap();
+        assert_eq!(
+            send(&hub, &streams, "POST", "client=ab12", register.as_bytes()).status,
+            204
+        );
+
+        let (tx, mut rx) = unbounded_channel();
+        attach(&hub, &streams, 8, "ab12", tx);
+        // The old stream noticing it's gone changes nothing.
+        hub.lock().unwrap().disconnect(7);
+        let message = WebSocketMessage {
+            message_type: MsgTypes::Message,
+            data: Some("still here".to_string()),
+            data_array: None,
+        };
+        let message = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            send(&hub, &streams, "POST", "client=ab12", message.as_bytes()).status,
+            204
+        );
+        let mut frames = vec![];
+        while let Ok(Outgoing::Text(text)) = rx.try_recv() {
+            frames.push(serde_json::from_str::<WebSocketMessage>(&text).unwrap());
+        }
+        let message = frames
+            .iter()
+            .find(|f| f.message_type == MsgTypes::Message)
+            .unwrap();
+        let message: MessageData = serde_json::from_str(message.data.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            (message.from.as_str(), message.message.as_str()),
+            ("alice", "still here")
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Webhooks: plain HTTP posts that become room messages, for scripts that
+//! have no reason to hold a WebSocket open.
+//!
+//! `POST /hooks/<token>` with a JSON [`WebhookPayload`] posts it into the
+//! webhook's room. Other HTTP requests on this port that nothing else
+//! wants are answered here too, with an error.
+
+use std::sync::Mutex;
+
+use yewchat_protocol::{WebhookPayload, WEBHOOK_PATH};
+
+use crate::http::Response;
+use crate::hub::{Hub, WebhookError};
+
+/// Answers a request for `path` with `body`.
+pub fn respond(hub: &Mutex<Hub>, method: &str, path: &str, body: &[u8]) -> Response {
+    let token = match path.strip_prefix(WEBHOOK_PATH) {
+        Some(token) if !token.is_empty() && !token.contains('/') => token,
+        _ => return Response::new(404, "not found"),
+    };
+    if method != "POST" {
+        return Response::new(405, "webhooks only take POST");
+    }
+    let payload: WebhookPayload = match serde_json::from_slice(body) {
+        Ok(payload) => payload,
+        Err(e) => return Response::new(400, &format!("malformed payload: {}", e)),
+    };
+    match hub.lock().unwrap().post_webhook(token, payload) {
+        Ok(()) => Response::new(204, ""),
+        Err(WebhookError::UnknownToken) => Response::new(404, "no such webhook"),
+        Err(WebhookError::Empty) => Response::new(400, "nothing to post"),
+        Err(WebhookError::SlowDown(ms)) => Response {
+            retry_after: Some((ms / 1000.0).ceil() as u64),
+            ..Response::new(429, "slow down")
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+    use yewchat_protocol::{MsgTypes, WebSocketMessage, WebhookList};
+
+    /// A hub with one user and a webhook for `#builds`; returns its token.
+    fn hub_with_webhook() -> (Mutex<Hub>, String) {
+        let mut hub = Hub::default();
+        let (tx, mut rx) = unbounded_channel();
+        hub.connect(1, tx);
+        let create = WebSocketMessage {
+            message_type: MsgTypes::CreateWebhook,
+            data: Some(r#"{"room":"builds","name":"ci"}"#.to_string()),
+            data_array: None,
+        };
+        for frame in [WebSocketMessage::register("alice", None), create] {
+            hub.handle(1, &serde_json::to_string(&frame).unwrap())
+                .unwrap();
+        }
+        let mut token = None;
+        while let Ok(crate::hub::Outgoing::Text(text)) = rx.try_recv() {
+            let frame: WebSocketMessage = serde_json::from_str(&text).unwrap();
+            if frame.message_type == MsgTypes::Webhooks {
+                let list: WebhookList = serde_json::from_str(&frame.data.unwrap()).unwrap();
+                token = Some(list.hooks[0].token.clone());
+            }
+        }
+        (Mutex::new(hub), token.unwrap())
+    }
+
+    #[test]
+    fn posts_to_known_webhooks_only() {
+        let (hub, token) = hub_with_webhook();
+        let path = format!("{}{}", WEBHOOK_PATH, token);
+        let body = br#"{"text":"build passed"}"#;
+        assert_eq!(respond(&hub, "POST", &path, body).status, 204);
+        assert_eq!(respond(&hub, "GET", &path, body).status, 405);
+        assert_eq!(respond(&hub, "POST", "/hooks/nope", body).status, 404);
+        assert_eq!(respond(&hub, "POST", "/elsewhere", body).status, 404);
+        assert_eq!(respond(&hub, "POST", &path, b"not json").status, 400);
+        assert_eq!(respond(&hub, "POST", &path, b"{}").status, 400);
+    }
+
+    #[test]
+    fn rate_limits_each_webhook() {
+        let (hub, token) = hub_with_webhook();
+        let path = format!("{}{}", WEBHOOK_PATH, token);
+        let statuses: Vec<u16> = (0..=yewchat_protocol::MESSAGE_BURST)
+            .map(|_| respond(&hub, "POST", &path, br#"{"text":"spam"}"#).status)
+            .collect();
+        assert_eq!(statuses.last(), Some(&429));
+        let limited = respond(&hub, "POST", &path, br#"{"text":"spam"}"#);
+        assert_eq!(limited.retry_after, Some(1));
+        assert!(String::from_utf8(limited.to_bytes())
+            .unwrap()
+            .contains("Retry-After: 1\r\n"));
+    }
+}
@@ -0,0 +1,182 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use yewchat_protocol::{MessageData, MsgTypes, WebSocketMessage, WebhookList};
+
+fn frame(message_type: MsgTypes, data: &str) -> Message {
+    let msg = WebSocketMessage {
+        message_type,
+        data: Some(data.to_string()),
+        data_array: None,
+    };
+    Message::Text(serde_json::to_string(&msg).unwrap())
+}
+
+fn register(name: &str) -> Message {
+    Message::Text(serde_json::to_string(&WebSocketMessage::register(name, None)).unwrap())
+}
+
+#[tokio::test]
+async fn two_clients_chat_over_real_sockets() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    tokio::spawn(yewchat_server::serve(listener));
+
+    let (mut alice, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    alice.send(register("alice")).await.unwrap();
+    let (mut bob, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+    bob.send(register("bob")).await.unwrap();
+    bob.send(frame(MsgTypes::Message, "hello alice"))
+        .await
+        .unwrap();
+
+    let mut users = vec![];
+    loop {
+        let msg = alice.next().await.unwrap().unwrap();
+        let msg: WebSocketMessage = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+        match msg.message_type {
+            MsgTypes::Users => users = msg.data_array.unwrap(),
+            MsgTypes::Message => {
+                let data: MessageData = serde_json::from_str(&msg.data.unwrap()).unwrap();
+                assert_eq!(data.from, "bob");
+                assert_eq!(data.message, "hello alice");
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(users, vec!["alice", "bob"]);
+}
+
+#[tokio::test]
+async fn webhooks_are_posted_over_plain_http() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(yewchat_server::serve(listener));
+
+    let (mut alice, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+        .await
+        .unwrap();
+    alice.send(register("alice")).await.unwrap();
+    let request = r#"{"room":"general","name":"ci"}"#;
+    alice
+        .send(frame(MsgTypes::CreateWebhook, request))
+        .await
+        .unwrap();
+    let token = loop {
+        let msg = alice.next().await.unwrap().unwrap();
+        let msg: WebSocketMessage = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+        if msg.message_type == MsgTypes::Webhooks {
+            let list: WebhookList = serde_json::from_str(&msg.data.unwrap()).unwrap();
+            break list.hooks[0].token.clone();
+        }
+    };
+
+    let body = r#"{"text":"build passed"}"#;
+    let mut http = TcpStream::connect(addr).await.unwrap();
+    let post = format!(
+        "POST /hooks/{} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        token,
+        body.len(),
+        body
+    );
+    http.write_all(post.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+
+    loop {
+        let msg = alice.next().await.unwrap().unwrap();
+        let msg: WebSocketMessage = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+        if msg.message_type == MsgTypes::Message {
+            let data: MessageData = serde_json::from_str(&msg.data.unwrap()).unwrap();
+            assert_eq!(
+                (data.from.as_str(), data.message.as_str()),
+                ("ci", "build passed")
+            );
+            break;
+        }
+    }
+}
+
+/// Sends `request` on a connection of its own and returns the whole response.
+async fn http(addr: std::net::SocketAddr, request: &str) -> String {
+    let mut http = TcpStream::connect(addr).await.unwrap();
+    http.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn event_stream_clients_chat_with_websocket_clients() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(yewchat_server::serve(listener));
+
+    let events = TcpStream::connect(addr).await.unwrap();
+    let mut events = BufReader::new(events);
+    let request =
+        "GET /events?client=3fa9 HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n";
+    events
+        .get_mut()
+        .write_all(request.as_bytes())
+        .await
+        .unwrap();
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        events.read_line(&mut head).await.unwrap();
+    }
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Type: text/event-stream\r\n"));
+    assert!(head.contains("Access-Control-Allow-Origin: *\r\n"));
+
+    let preflight = http(
+        addr,
+        "OPTIONS /send?client=3fa9 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(preflight.starts_with("HTTP/1.1 204"), "{}", preflight);
+    assert!(preflight.contains("Access-Control-Allow-Headers: Content-Type\r\n"));
+
+    let credentials = register("alice").into_text().unwrap();
+    let post = format!(
+        "POST /send?client=3fa9 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        credentials.len(),
+        credentials
+    );
+    let response = http(addr, &post).await;
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+
+    let (mut bob, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+        .await
+        .unwrap();
+    bob.send(register("bob")).await.unwrap();
+    bob.send(frame(MsgTypes::Message, "hello alice"))
+        .await
+        .unwrap();
+
+    let mut users = vec![];
+    loop {
+        let mut line = String::new();
+        events.read_line(&mut line).await.unwrap();
+        let Some(data) = line.strip_prefix("data: ") else {
+            continue;
+        };
+        let msg: WebSocketMessage = serde_json::from_str(data.trim_end()).unwrap();
+        match msg.message_type {
+            MsgTypes::Users => users = msg.data_array.unwrap(),
+            MsgTypes::Message => {
+                let data: MessageData = serde_json::from_str(&msg.data.unwrap()).unwrap();
+                assert_eq!(
+                    (data.from.as_str(), data.message.as_str()),
+                    ("bob", "hello alice")
+                );
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(users, vec!["alice", "bob"]);
+}
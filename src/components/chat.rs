@@ -1,140 +1,2266 @@
-use serde::{Deserialize, Serialize};
-use web_sys::HtmlInputElement;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use gloo::events::EventListener;
+use gloo::timers::callback::Timeout;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{Element, HtmlElement, HtmlInputElement, NotificationPermission};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
+use yew_router::prelude::*;
+
+use crate::components::encryption::Encryption;
+use crate::components::moderation_log::ModerationLog;
+use crate::components::profile::ProfilePage;
+use crate::components::profile_card::ProfileCard;
+use crate::components::report_dialog::ReportDialog;
+use crate::components::reports::Reports;
+use crate::components::settings::Settings;
+use crate::components::webhooks::Webhooks;
+use crate::services::commands::{Action, Command, Commands};
+use crate::services::crypto::{self, direct_room, peer_of, Identity, KeyRing, Trust};
+use crate::services::event_bus::{ConnectionState, Event, EventBus, Request, SyncEvent, Topic};
+use crate::services::ignore_list::IgnoreList;
+use crate::services::content_filter::ContentFilter;
+use crate::services::notifications::{self, NotificationSettings, APP_TITLE};
+use crate::services::profiles;
+use crate::services::protocol::{
+    Attachment, ButtonStyle, InteractionData, MessageData, ModAction, Moderation,
+    ModerationEntry, MsgTypes, Profile, PublicKeys, ReadData, Report, ReportRequest, Role, Sanction,
+    SearchResultsData, TokenBucket, MAX_SEARCH_RESULTS,
+    TopicData, TypingData, WebSocketMessage, Webhook, WebhookRequest, DEFAULT_ROOM,
+    TYPING_INTERVAL_MS,
+};
+use crate::services::read_receipts::ReadReceipts;
+use crate::services::search::{self, SearchQuery};
+use crate::services::transport::TransportKind;
+use crate::services::virtual_list::{VirtualList, Window};
+use crate::{services::shared_connection::SharedConnection, Route, User};
 
-use crate::services::event_bus::EventBus;
-use crate::{services::websocket::WebsocketService, User};
+/// How many of the latest messages show read-by avatars.
+const READ_BY_WINDOW: usize = 5;
+/// Slack in pixels when deciding whether the list is scrolled to the bottom.
+const BOTTOM_THRESHOLD: i32 = 16;
+/// Assumed height of a message row before it has been rendered and measured.
+const ESTIMATED_ROW_HEIGHT: f64 = 96.0;
+/// Rows rendered beyond each edge of the viewport.
+const OVERSCAN: usize = 10;
+/// Consecutive messages from one sender closer together than this share a
+/// single avatar and header.
+const GROUP_WINDOW_MS: f64 = 5.0 * 60.0 * 1000.0;
+/// Round-trip times, in milliseconds, below which the connection counts as
+/// good or fair; anything slower is poor.
+const GOOD_LATENCY_MS: f64 = 150.0;
+const FAIR_LATENCY_MS: f64 = 400.0;
+/// How long a notice about sending stays up.
+const NOTICE_MS: u32 = 4000;
+/// How long someone counts as typing after their last typing frame; a bit
+/// longer than the interval they're sent at.
+const TYPING_TIMEOUT_MS: u32 = 5000;
 
 pub enum Msg {
-    HandleMsg(String),
+    HandleEvent(Event),
+    DismissError,
+    DismissNotice,
     SubmitMessage,
+    DraftChanged,
+    /// Fill in the composer with the command at this index of the
+    /// suggestions.
+    CompleteCommand(usize),
+    MoveSuggestion(i32),
+    DismissSuggestions,
+    SyncReadState,
+    Scrolled,
+    JumpToLatest,
+    RowsResized,
+    /// What was measured after rendering doesn't match the layout.
+    Relayout,
+    SwitchRoom(String),
+    Search,
+    CloseSearch,
+    JumpToMessage(u64),
+    RequestNotifications,
+    NotificationPermission(NotificationPermission),
+    ToggleMute,
+    ToggleSound,
+    PruneTyping,
+    PressButton { message_id: u64, button: String },
+    ToggleWebhooks,
+    CreateWebhook(String),
+    RevokeWebhook(String),
+    OpenMenu(ContextMenu),
+    CloseMenu,
+    Moderate(ModAction),
+    ToggleModerationLog,
+    /// A timed mute may have run out.
+    CheckMute,
+    ToggleSettings,
+    SetContentFilter(ContentFilter),
+    Ignore(String),
+    Unignore(String),
+    /// Show these hidden messages from ignored people.
+    Reveal(Vec<u64>),
+    /// Ask why the message with this id is being reported.
+    StartReport(u64),
+    SubmitReport(ReportRequest),
+    CancelReport,
+    ToggleReports,
+    CloseReport { report_id: u64, dismissed: bool },
+    /// Our key pair for this account is ready, or can't be had.
+    IdentityLoaded { account: String, identity: Result<Rc<Identity>, String> },
+    Fingerprint { key: String, fingerprint: String },
+    /// An encrypted message is ready to go out, or couldn't be encrypted.
+    /// The text is kept in case it has to go back in the composer.
+    SendSealed {
+        room: String,
+        text: String,
+        frame: Result<WebSocketMessage, String>,
+    },
+    /// An encrypted message was decrypted, or couldn't be.
+    Opened { id: u64, text: Option<String> },
+    /// Open the encrypted conversation with this user.
+    OpenDirect(String),
+    ToggleEncryption,
+    /// The user compared fingerprints with whoever we're talking to.
+    VerifyKey,
+    OpenCard(CardAt),
+    CloseCard,
+    SaveProfile(Profile),
+}
+
+/// Where a profile card was opened, and whose.
+pub struct CardAt {
+    x: i32,
+    y: i32,
+    user: String,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    /// Show this user's profile in place of the messages.
+    #[prop_or_default]
+    pub profile: Option<String>,
+}
+
+/// Moderation actions offered for a user, or one of their messages, on
+/// right-click.
+pub struct ContextMenu {
+    /// Where it was opened, in client coordinates.
+    x: i32,
+    y: i32,
+    user: String,
+    message: Option<u64>,
+}
+
+struct SearchState {
+    query: SearchQuery,
+    results: Vec<MessageData>,
+}
+
+/// A row of the message list.
+#[derive(Debug, PartialEq)]
+enum Row<'a> {
+    Message(&'a MessageData),
+    /// Consecutive messages from ignored people, folded away.
+    Hidden(Vec<u64>),
+}
+
+impl Row<'_> {
+    /// Key for the virtual list: the message's id, or the first hidden one's.
+    fn key(&self) -> u64 {
+        match self {
+            Row::Message(m) => m.id,
+            Row::Hidden(ids) => ids[0],
+        }
+    }
+}
+
+/// Rows for `messages`, folding each run of them that `hidden` picks into one.
+fn rows<'a>(messages: &[&'a MessageData], hidden: impl Fn(&MessageData) -> bool) -> Vec<Row<'a>> {
+    let mut rows = vec![];
+    for m in messages {
+        if !hidden(m) {
+            rows.push(Row::Message(m));
+            continue;
+        }
+        match rows.last_mut() {
+            Some(Row::Hidden(ids)) => ids.push(m.id),
+            _ => rows.push(Row::Hidden(vec![m.id])),
+        }
+    }
+    rows
+}
+
+/// Whether `m` needs its own avatar and header rather than continuing the
+/// group started by the messages before it.
+fn starts_group(prev: Option<&MessageData>, m: &MessageData) -> bool {
+    match prev {
+        Some(prev) => {
+            prev.from != m.from
+                || prev.room != m.room
+                || m.timestamp - prev.timestamp > GROUP_WINDOW_MS
+        }
+        None => true,
+    }
+}
+
+/// "alice is typing…" and so on, for the given people.
+fn typing_summary(names: &[&str]) -> Option<String> {
+    match names {
+        [] => None,
+        [name] => Some(format!("{} is typing\u{2026}", name)),
+        [first, second] => Some(format!("{} and {} are typing\u{2026}", first, second)),
+        _ => Some("Several people are typing\u{2026}".to_string()),
+    }
+}
+
+/// What the context menu offers `me` for `user`, or their message if one is
+/// given, each with the label to show.
+fn moderation_actions(
+    me: &str,
+    roles: &BTreeMap<String, Role>,
+    user: &str,
+    message: Option<u64>,
+) -> Vec<(&'static str, ModAction)> {
+    let role = |name: &str| roles.get(name).copied().unwrap_or_default();
+    let (mine, theirs) = (role(me), role(user));
+    let mut actions = vec![];
+    if let Some(message_id) = message {
+        if user == me || mine.outranks(theirs) {
+            let remove = ModAction::Remove {
+                message_id,
+                user: user.to_string(),
+            };
+            actions.push(("Remove message", remove));
+        }
+    }
+    if user == me || !mine.outranks(theirs) {
+        return actions;
+    }
+    let user = user.to_string();
+    let mute = |minutes| ModAction::Mute {
+        user: user.clone(),
+        minutes: Some(minutes),
+    };
+    actions.push(("Mute for 10 minutes", mute(10)));
+    actions.push(("Mute for an hour", mute(60)));
+    actions.push(("Unmute", ModAction::Unmute { user: user.clone() }));
+    actions.push(("Kick", ModAction::Kick { user: user.clone() }));
+    actions.push(("Ban", ModAction::Ban { user: user.clone() }));
+    if mine == Role::Owner {
+        let (label, role) = match theirs {
+            Role::Member => ("Make moderator", Role::Moderator),
+            _ => ("Make member", Role::Member),
+        };
+        actions.push((label, ModAction::SetRole { user, role }));
+    }
+    actions
+}
+
+/// What the login screen says after the server kicked us.
+fn kicked_notice(sanction: &Sanction) -> String {
+    let what = if sanction.banned { "banned" } else { "kicked out" };
+    match sanction.reason.as_str() {
+        "" => format!("You were {} by {}.", what, sanction.by),
+        reason => format!("You were {} by {}: {}", what, sanction.by, reason),
+    }
+}
+
+/// How a room is named on screen; encrypted conversations go by the peer.
+fn room_label(room: &str) -> String {
+    match peer_of(room) {
+        Some(_) => room.to_string(),
+        None => format!("# {}", room),
+    }
+}
+
+/// `HH:MM` in the user's local time.
+fn format_time(timestamp: f64) -> String {
+    let date = js_sys::Date::new(&timestamp.into());
+    format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
+
+/// How someone is shown in the chat.
+struct UserProfile {
+    /// Their display name, or username if they haven't set one.
+    name: String,
+    avatar: String,
 }
 
-#[derive(Deserialize)]
-struct MessageData {
-    from: String,
-    message: String,
-}
+pub struct Chat {
+    username: String,
+    room: String,
+    rooms: Vec<String>,
+    /// Everyone online.
+    users: Vec<String>,
+    /// Everyone's profiles, online or not, as the server last sent them.
+    profiles: BTreeMap<String, Profile>,
+    /// Ours, as last saved on this device.
+    own_profile: Profile,
+    card: Option<CardAt>,
+    chat_input: NodeRef,
+    message_list: NodeRef,
+    search_input: NodeRef,
+    _producer: Box<dyn Bridge<EventBus>>,
+    _visibility_listener: EventListener,
+    _focus_listener: EventListener,
+    _resize_listener: EventListener,
+    wss: SharedConnection,
+    messages: Vec<MessageData>,
+    read_receipts: ReadReceipts,
+    first_unread: Option<u64>,
+    notification_settings: NotificationSettings,
+    notification_permission: NotificationPermission,
+    unread_count: usize,
+    /// Keep the list pinned to the newest message; off once the user scrolls up.
+    stick_to_bottom: bool,
+    /// Messages that arrived in the current room while scrolled up.
+    unseen_below: usize,
+    /// Where each room was left, `None` meaning "at the bottom".
+    scroll_positions: HashMap<String, Option<i32>>,
+    restore_scroll: Option<i32>,
+    virtual_list: VirtualList,
+    scroll_top: f64,
+    viewport_height: f64,
+    last_window: Option<(usize, usize)>,
+    search: Option<SearchState>,
+    highlighted: Option<u64>,
+    connection: ConnectionState,
+    /// Last heartbeat round-trip time in milliseconds.
+    latency: Option<f64>,
+    last_error: Option<String>,
+    /// Unsent composer text per room, shared with the user's other tabs.
+    drafts: HashMap<String, String>,
+    /// Holds the user to the rate the server accepts messages at.
+    limiter: TokenBucket,
+    /// Why the last message couldn't be sent, if it couldn't.
+    send_notice: Option<String>,
+    notice_timeout: Option<Timeout>,
+    /// When each `(room, user)` stops counting as typing.
+    typing: HashMap<(String, String), f64>,
+    /// When we last told the server we're typing.
+    typing_sent_at: f64,
+    commands: Commands,
+    /// Highlighted row of the command suggestions.
+    suggestion: usize,
+    /// Suggestions were closed with Escape; they stay closed until the
+    /// composer changes.
+    suggestions_dismissed: bool,
+    /// `/help` was run: suggest every command.
+    show_help: bool,
+    topics: HashMap<String, TopicData>,
+    /// Rooms left with `/leave`, which new messages don't bring back.
+    left_rooms: HashSet<String>,
+    /// Bot buttons already pressed, by message id and button id.
+    pressed: HashSet<(u64, String)>,
+    webhooks_open: bool,
+    /// Webhooks by room, as last listed by the server.
+    webhooks: HashMap<String, Vec<Webhook>>,
+    /// Everyone who isn't a plain member.
+    roles: BTreeMap<String, Role>,
+    /// Set while we're muted; the composer is disabled.
+    muted: Option<Sanction>,
+    mute_timeout: Option<Timeout>,
+    menu: Option<ContextMenu>,
+    moderation_log_open: bool,
+    moderation_log: Vec<ModerationEntry>,
+    ignored: IgnoreList,
+    /// Messages from ignored people the user asked to see anyway.
+    revealed: HashSet<u64>,
+    settings_open: bool,
+    content_filter: ContentFilter,
+    /// The message being reported, while the dialog asks why.
+    reporting: Option<MessageData>,
+    reports_open: bool,
+    /// Open reports, for moderators.
+    reports: Vec<Report>,
+    /// Our key pair for encrypted direct messages, once it's loaded.
+    identity: Option<Rc<Identity>>,
+    /// Set if this browser can't do the encryption, such as on a page not
+    /// served over HTTPS.
+    identity_failed: bool,
+    /// Everyone's published keys, from the user list.
+    public_keys: PublicKeys,
+    key_ring: KeyRing,
+    /// Fingerprints of the keys seen so far, by key.
+    fingerprints: HashMap<String, String>,
+    /// Encrypted messages that couldn't be decrypted.
+    unreadable: HashSet<u64>,
+    encryption_open: bool,
+}
+
+impl Chat {
+    fn is_at_bottom(&self) -> bool {
+        self.message_list.cast::<Element>().is_some_and(|list| {
+            list.scroll_top() + list.client_height() >= list.scroll_height() - BOTTOM_THRESHOLD
+        })
+    }
+
+    /// Whether the user can currently see the bottom of the message list.
+    fn is_caught_up(&self) -> bool {
+        !gloo::utils::document().hidden() && self.is_at_bottom()
+    }
+
+    fn switch_room(&mut self, room: String) {
+        if room == self.room {
+            return;
+        }
+        let position = match self.message_list.cast::<Element>() {
+            Some(list) if !self.stick_to_bottom => Some(list.scroll_top()),
+            _ => None,
+        };
+        self.scroll_positions.insert(self.room.clone(), position);
+        if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+            self.drafts.insert(self.room.clone(), input.value());
+            input.set_value(self.drafts.get(&room).map_or("", String::as_str));
+        }
+        if !self.rooms.contains(&room) {
+            self.rooms.push(room.clone());
+        }
+
+        self.restore_scroll = self.scroll_positions.get(&room).copied().flatten();
+        self.stick_to_bottom = self.restore_scroll.is_none();
+        self.scroll_top = self.restore_scroll.unwrap_or_default() as f64;
+        self.unseen_below = 0;
+        self.room = room;
+        self.encryption_open = false;
+        if self.webhooks_open {
+            self.list_webhooks();
+        }
+    }
+
+    fn list_webhooks(&self) {
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::ListWebhooks,
+            data: Some(self.room.clone()),
+            data_array: None,
+        });
+    }
+
+    /// Advances our read position to the latest message and tells the server,
+    /// but only while the user is actually looking at it.
+    fn sync_read_state(&mut self) {
+        if !self.is_caught_up() {
+            return;
+        }
+        self.set_unread_count(0);
+        let latest = match self.messages.iter().rev().find(|m| m.room == self.room) {
+            Some(m) => m.id,
+            None => return,
+        };
+        if !self.read_receipts.mark_read(&self.room, &self.username, latest) {
+            return;
+        }
+
+        let read = ReadData {
+            user: self.username.clone(),
+            room: self.room.clone(),
+            message_id: latest,
+        };
+        // Other tabs keep up with encrypted conversations, but the server
+        // doesn't hear of them.
+        if peer_of(&self.room).is_none() {
+            self.send(WebSocketMessage {
+                message_type: MsgTypes::Read,
+                data: Some(serde_json::to_string(&read).unwrap()),
+                data_array: None,
+            });
+        }
+        self.wss.share(SyncEvent::Read(read));
+    }
+
+    fn send(&self, message: WebSocketMessage) {
+        if let Err(e) = self.wss.send(serde_json::to_string(&message).unwrap()) {
+            log::debug!("error sending to channel: {:?}", e);
+        }
+    }
+
+    /// Sends a chat message unless the user is muted, over the rate limit or
+    /// not connected, telling them which. Returns whether it went.
+    fn post(&mut self, ctx: &Context<Self>, message: WebSocketMessage) -> bool {
+        self.may_post(ctx) && self.deliver(ctx, message)
+    }
+
+    /// Whether the user may post right now, telling them why not if they
+    /// can't. Counts against the rate limit if they can.
+    fn may_post(&mut self, ctx: &Context<Self>) -> bool {
+        if let Some(notice) = self.muted_notice() {
+            self.show_notice(ctx, format!("{}.", notice));
+            return false;
+        }
+        if let Err(wait) = self.limiter.try_take(js_sys::Date::now()) {
+            self.show_notice(
+                ctx,
+                format!(
+                    "You're sending messages too fast. Try again in {}s.",
+                    (wait / 1000.0).ceil()
+                ),
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Sends a chat message that [`Self::may_post`] let through.
+    fn deliver(&mut self, ctx: &Context<Self>, message: WebSocketMessage) -> bool {
+        if let Err(e) = self.wss.send(serde_json::to_string(&message).unwrap()) {
+            log::debug!("error sending to channel: {:?}", e);
+            let notice = "Not connected to the server. Try again once it's back.";
+            self.show_notice(ctx, notice.to_string());
+            return false;
+        }
+        self.send_notice = None;
+        self.notice_timeout = None;
+        self.typing_sent_at = 0.0;
+        // Replying means we've caught up on whatever was unread, and we
+        // want to see our own message land.
+        self.stick_to_bottom = true;
+        self.unseen_below = 0;
+        self.first_unread = None;
+        true
+    }
+
+    /// Carries out what the user typed. Returns `false` if it couldn't be
+    /// done, so the text stays in the composer.
+    fn run(&mut self, ctx: &Context<Self>, action: Action) -> bool {
+        match action {
+            Action::Say(text) => match peer_of(&self.room) {
+                Some(peer) => self.send_sealed(ctx, peer.to_string(), text),
+                None => self.post(ctx, WebSocketMessage::chat(&text, &self.room, None)),
+            },
+            Action::Emote(text) => {
+                let text = format!("/me {}", text);
+                match peer_of(&self.room) {
+                    Some(peer) => self.send_sealed(ctx, peer.to_string(), text),
+                    None => self.post(ctx, WebSocketMessage::chat(&text, &self.room, None)),
+                }
+            }
+            Action::Whisper { .. } | Action::SetTopic(_) if peer_of(&self.room).is_some() => {
+                self.show_notice(ctx, "That only works in a room.".to_string());
+                false
+            }
+            Action::Whisper { to, text } => {
+                self.post(ctx, WebSocketMessage::chat(&text, &self.room, Some(&to)))
+            }
+            Action::Rename(name) => self.rename(ctx, name),
+            Action::Join(room) => {
+                self.left_rooms.remove(&room);
+                self.switch_room(room);
+                true
+            }
+            Action::Leave(room) => {
+                let room = room.unwrap_or_else(|| self.room.clone());
+                if room == DEFAULT_ROOM {
+                    self.show_notice(ctx, format!("Everyone stays in #{}.", DEFAULT_ROOM));
+                    return false;
+                }
+                self.rooms.retain(|r| *r != room);
+                if self.room == room {
+                    self.switch_room(DEFAULT_ROOM.to_string());
+                }
+                self.left_rooms.insert(room);
+                true
+            }
+            Action::SetTopic(topic) => {
+                let topic = TopicData {
+                    room: self.room.clone(),
+                    topic,
+                    set_by: self.username.clone(),
+                };
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::Topic,
+                    data: Some(serde_json::to_string(&topic).unwrap()),
+                    data_array: None,
+                });
+                true
+            }
+            Action::Clear => {
+                self.messages.retain(|m| m.room != self.room);
+                self.first_unread = None;
+                self.unseen_below = 0;
+                self.stick_to_bottom = true;
+                true
+            }
+            Action::Help => {
+                self.show_help = true;
+                self.suggestion = 0;
+                true
+            }
+        }
+    }
+
+    /// Reconnects under another name. The old connection closes as it's
+    /// dropped, and everyone sees the new name once this one registers.
+    fn rename(&mut self, ctx: &Context<Self>, name: String) -> bool {
+        if name != self.username && self.users.contains(&name) {
+            self.show_notice(ctx, format!("{} is already taken.", name));
+            return false;
+        }
+        if let Some((user, _)) = ctx.link().context::<User>(Callback::noop()) {
+            *user.username.borrow_mut() = name.clone();
+        }
+        self.wss = SharedConnection::new(&name, TransportKind::from_query());
+        self.ignored = IgnoreList::load(&name);
+        self.key_ring = KeyRing::load(&name);
+        self.own_profile = profiles::load_own(&name);
+        self.identity = None;
+        self.identity_failed = false;
+        Self::load_identity(ctx, name.clone());
+        self.username = name;
+        true
+    }
+
+    /// Loads or makes the key pair for `account`, which takes a moment.
+    fn load_identity(ctx: &Context<Self>, account: String) {
+        let link = ctx.link().clone();
+        spawn_local(async move {
+            let identity = Identity::load_or_create(&account).await.map(Rc::new);
+            link.send_message(Msg::IdentityLoaded { account, identity });
+        });
+    }
+
+    /// Tells the server our public key, which it forgets whenever we
+    /// disconnect.
+    fn publish_key(&self) {
+        if let Some(identity) = &self.identity {
+            self.send(WebSocketMessage {
+                message_type: MsgTypes::PublicKey,
+                data: Some(identity.public_key.clone()),
+                data_array: None,
+            });
+        }
+    }
+
+    /// Tells the server our profile, in case it forgot it while we were away.
+    fn publish_profile(&self) {
+        if self.own_profile != Profile::default() {
+            self.send(WebSocketMessage {
+                message_type: MsgTypes::Profile,
+                data: Some(serde_json::to_string(&self.own_profile).unwrap()),
+                data_array: None,
+            });
+        }
+    }
+
+    fn compute_fingerprint(&self, ctx: &Context<Self>, key: &str) {
+        if self.fingerprints.contains_key(key) {
+            return;
+        }
+        let key = key.to_string();
+        let link = ctx.link().clone();
+        spawn_local(async move {
+            match crypto::fingerprint(&key).await {
+                Ok(fingerprint) => link.send_message(Msg::Fingerprint { key, fingerprint }),
+                Err(e) => log::error!("couldn't fingerprint a key: {}", e),
+            }
+        });
+    }
+
+    /// Decrypts message `m` in the background, once we have our key.
+    fn open_sealed(&self, ctx: &Context<Self>, m: &MessageData) {
+        let (identity, sealed, to) = match (&self.identity, &m.sealed, &m.to) {
+            (Some(identity), Some(sealed), Some(to)) => (identity.clone(), sealed.clone(), to.clone()),
+            _ => return,
+        };
+        let (id, from) = (m.id, m.from.clone());
+        let link = ctx.link().clone();
+        spawn_local(async move {
+            let text = identity.open(&sealed, &from, &to).await;
+            if let Err(e) = &text {
+                log::warn!("couldn't decrypt message {}: {}", id, e);
+            }
+            link.send_message(Msg::Opened { id, text: text.ok() });
+        });
+    }
+
+    /// Encrypts `text` for `peer` and sends it once that's done. Like
+    /// [`Self::post`], returns `false` if it can't go; if it turns out it
+    /// can't once it's encrypted, the text goes back in the composer.
+    fn send_sealed(&mut self, ctx: &Context<Self>, peer: String, text: String) -> bool {
+        let identity = match &self.identity {
+            Some(identity) => identity.clone(),
+            None => {
+                let notice = if self.identity_failed {
+                    "This browser can't encrypt messages here; it needs the chat served over HTTPS."
+                } else {
+                    "Your encryption key isn't ready yet. Try again in a moment."
+                };
+                self.show_notice(ctx, notice.to_string());
+                return false;
+            }
+        };
+        let key = match self.public_keys.get(&peer) {
+            Some(key) => key.clone(),
+            None => {
+                self.show_notice(ctx, format!("{} isn't online, so there's no key to encrypt for.", peer));
+                return false;
+            }
+        };
+        if !self.key_ring.pin(&peer, &key) {
+            self.show_notice(ctx, format!("{}'s key has changed. Check their fingerprint before sending.", peer));
+            return false;
+        }
+        self.key_ring.save(&self.username);
+        if !self.may_post(ctx) {
+            return false;
+        }
+
+        let from = self.username.clone();
+        let link = ctx.link().clone();
+        spawn_local(async move {
+            let frame = identity.seal(&text, &from, &peer, &key).await.map(|sealed| WebSocketMessage {
+                message_type: MsgTypes::Sealed,
+                data: Some(serde_json::to_string(&sealed).unwrap()),
+                // The room doesn't matter; only the two of us get it.
+                data_array: Some(vec![DEFAULT_ROOM.to_string(), peer.clone()]),
+            });
+            link.send_message(Msg::SendSealed {
+                room: direct_room(&peer),
+                text,
+                frame,
+            });
+        });
+        true
+    }
+
+    /// Puts text that couldn't be sent back in `room`'s composer, ahead of
+    /// anything typed since.
+    fn restore_draft(&mut self, room: String, text: String) {
+        let input = self
+            .chat_input
+            .cast::<HtmlInputElement>()
+            .filter(|_| room == self.room);
+        let typed = match &input {
+            Some(input) => input.value(),
+            None => self.drafts.get(&room).cloned().unwrap_or_default(),
+        };
+        let text = if typed.is_empty() {
+            text
+        } else {
+            format!("{} {}", text, typed)
+        };
+        if let Some(input) = input {
+            input.set_value(&text);
+        }
+        self.drafts.insert(room.clone(), text.clone());
+        self.wss.share(SyncEvent::Draft { room, text });
+    }
+
+    /// How far the key `peer` has published can be trusted; `None` if they
+    /// haven't published one.
+    fn peer_trust(&self, peer: &str) -> Option<Trust> {
+        let key = self.public_keys.get(peer)?;
+        Some(self.key_ring.trust(peer, key))
+    }
+
+    fn view_lock(&self, ctx: &Context<Self>, peer: &str) -> Html {
+        let (label, color) = match self.peer_trust(peer) {
+            Some(Trust::Verified) => ("Verified", "text-green-700 bg-green-100"),
+            Some(Trust::Unverified) => ("Encrypted", "text-gray-700 bg-gray-100"),
+            Some(Trust::Changed) => ("Key changed", "text-red-700 bg-red-100"),
+            None => ("Offline", "text-gray-500 bg-gray-100"),
+        };
+        html! {
+            <button
+                class={classes!("flex", "items-center", "ml-3", "px-2", "py-0.5", "text-xs", "font-medium", "rounded-full", "focus:outline-none", color)}
+                title="Encryption details"
+                onclick={ctx.link().callback(|_| Msg::ToggleEncryption)}
+            >
+                <svg xmlns="http://www.w3.org/2000/svg" class="h-3.5 w-3.5 mr-1" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                    <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M12 15v2m-6 4h12a2 2 0 002-2v-6a2 2 0 00-2-2H6a2 2 0 00-2 2v6a2 2 0 002 2zm10-10V7a4 4 0 00-8 0v4h8z" />
+                </svg>
+                {label}
+            </button>
+        }
+    }
+
+    fn role(&self) -> Role {
+        self.roles.get(&self.username).copied().unwrap_or_default()
+    }
+
+    /// Asks the server to carry out `action`, first asking the moderator why
+    /// if it's done to someone else. Cancelling that cancels the action.
+    fn moderate(&self, action: ModAction) {
+        let reason = match &action {
+            ModAction::Remove { user, .. } if *user == self.username => String::new(),
+            ModAction::Kick { user } | ModAction::Ban { user } | ModAction::Mute { user, .. } => {
+                let question = format!("Why? {} will see this.", user);
+                match gloo::dialogs::prompt(&question, None) {
+                    Some(reason) => reason.trim().to_string(),
+                    None => return,
+                }
+            }
+            ModAction::Remove { .. } => {
+                match gloo::dialogs::prompt("Why remove it? This goes in the moderation log.", None) {
+                    Some(reason) => reason.trim().to_string(),
+                    None => return,
+                }
+            }
+            _ => String::new(),
+        };
+        let moderation = Moderation { action, reason };
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Moderate,
+            data: Some(serde_json::to_string(&moderation).unwrap()),
+            data_array: None,
+        });
+    }
+
+    /// Takes the muted notice down once a timed mute runs out.
+    fn check_mute(&mut self, ctx: &Context<Self>) -> bool {
+        let until = match &self.muted {
+            Some(Sanction { until: Some(until), .. }) => *until,
+            _ => return false,
+        };
+        let left = until - js_sys::Date::now();
+        if left <= 0.0 {
+            self.muted = None;
+            self.mute_timeout = None;
+            return true;
+        }
+        let link = ctx.link().clone();
+        // Timers can't wait longer than an i32 of milliseconds; check back.
+        let wait = left.min(i32::MAX as f64) as u32;
+        self.mute_timeout = Some(Timeout::new(wait, move || link.send_message(Msg::CheckMute)));
+        false
+    }
+
+    fn muted_notice(&self) -> Option<String> {
+        let sanction = self.muted.as_ref()?;
+        let until = match sanction.until {
+            Some(until) => format!(" until {}", format_time(until)),
+            None => String::new(),
+        };
+        let reason = match sanction.reason.as_str() {
+            "" => String::new(),
+            reason => format!(": {}", reason),
+        };
+        Some(format!("{} muted you{}{}", sanction.by, until, reason))
+    }
+
+    fn view_menu(&self, ctx: &Context<Self>, menu: &ContextMenu) -> Html {
+        let actions = moderation_actions(&self.username, &self.roles, &menu.user, menu.message);
+        // Moderators couldn't read an encrypted message anyway.
+        let sealed = menu
+            .message
+            .and_then(|id| self.messages.iter().find(|m| m.id == id))
+            .is_some_and(|m| m.sealed.is_some());
+        let report = match menu.message {
+            Some(id) if menu.user != self.username && !sealed => {
+                Some(ctx.link().callback_once(move |_| Msg::StartReport(id)))
+            }
+            _ => None,
+        };
+        let direct = (menu.user != self.username && self.public_keys.contains_key(&menu.user))
+            .then(|| {
+                let user = menu.user.clone();
+                ctx.link().callback_once(move |_| Msg::OpenDirect(user))
+            });
+        let ignore = (menu.user != self.username).then(|| {
+            let user = menu.user.clone();
+            if self.ignored.contains(&user) {
+                ("Stop ignoring", ctx.link().callback_once(move |_| Msg::Unignore(user)))
+            } else {
+                ("Ignore", ctx.link().callback_once(move |_| Msg::Ignore(user)))
+            }
+        });
+        let close = ctx.link().callback(|e: MouseEvent| {
+            e.prevent_default();
+            Msg::CloseMenu
+        });
+        html! {
+            <>
+                <div class="fixed inset-0 z-40" onclick={close.clone()} oncontextmenu={close}></div>
+                <ul
+                    class="fixed z-50 w-52 py-1 bg-white border border-gray-200 rounded-lg shadow-lg text-sm"
+                    style={format!("left: {}px; top: {}px", menu.x, menu.y)}
+                >
+                    <li class="px-4 py-1 text-xs font-semibold text-gray-400 truncate">{menu.user.clone()}</li>
+                    if let Some(onclick) = direct {
+                        <li>
+                            <button
+                                class="w-full px-4 py-1 text-left text-gray-700 hover:bg-gray-50 focus:outline-none"
+                                {onclick}
+                            >
+                                {"Send encrypted message"}
+                            </button>
+                        </li>
+                    }
+                    if let Some(onclick) = report {
+                        <li>
+                            <button
+                                class="w-full px-4 py-1 text-left text-gray-700 hover:bg-gray-50 focus:outline-none"
+                                {onclick}
+                            >
+                                {"Report"}
+                            </button>
+                        </li>
+                    }
+                    if let Some((label, onclick)) = ignore {
+                        <li>
+                            <button
+                                class="w-full px-4 py-1 text-left text-gray-700 hover:bg-gray-50 focus:outline-none"
+                                {onclick}
+                            >
+                                {label}
+                            </button>
+                        </li>
+                    }
+                    {
+                        actions.into_iter().map(|(label, action)| {
+                            let danger = matches!(action, ModAction::Kick { .. } | ModAction::Ban { .. } | ModAction::Remove { .. });
+                            let onclick = ctx.link().callback_once(move |_| Msg::Moderate(action));
+                            html! {
+                                <li>
+                                    <button
+                                        class={classes!("w-full", "px-4", "py-1", "text-left", "hover:bg-gray-50", "focus:outline-none", if danger { "text-red-600" } else { "text-gray-700" })}
+                                        {onclick}
+                                    >
+                                        {label}
+                                    </button>
+                                </li>
+                            }
+                        }).collect::<Html>()
+                    }
+                </ul>
+            </>
+        }
+    }
+
+    /// Opens the context menu for `user` and maybe their `message`, if
+    /// there's anything in it for us: there always is for other people,
+    /// who can be ignored and reported.
+    fn oncontextmenu(
+        &self,
+        ctx: &Context<Self>,
+        user: &str,
+        message: Option<u64>,
+    ) -> Option<Callback<MouseEvent>> {
+        if user == self.username
+            && moderation_actions(&self.username, &self.roles, user, message).is_empty()
+        {
+            return None;
+        }
+        let user = user.to_string();
+        Some(ctx.link().callback(move |e: MouseEvent| {
+            e.prevent_default();
+            Msg::OpenMenu(ContextMenu {
+                x: e.client_x(),
+                y: e.client_y(),
+                user: user.clone(),
+                message,
+            })
+        }))
+    }
+
+    fn composer_text(&self) -> &str {
+        self.drafts.get(&self.room).map_or("", String::as_str)
+    }
+
+    /// Commands to offer under the composer right now.
+    fn suggestions(&self) -> Vec<&Command> {
+        if self.show_help {
+            self.commands.iter().collect()
+        } else if self.suggestions_dismissed {
+            vec![]
+        } else {
+            self.commands.suggest(self.composer_text())
+        }
+    }
+
+    fn show_notice(&mut self, ctx: &Context<Self>, notice: String) {
+        self.send_notice = Some(notice);
+        let link = ctx.link().clone();
+        self.notice_timeout = Some(Timeout::new(NOTICE_MS, move || {
+            link.send_message(Msg::DismissNotice)
+        }));
+    }
+
+    /// Lets the room know we're typing, at most once per
+    /// [`TYPING_INTERVAL_MS`] however fast the keys come.
+    fn send_typing(&mut self) {
+        let now = js_sys::Date::now();
+        // Where we're typing privately is nobody else's business.
+        if now - self.typing_sent_at < TYPING_INTERVAL_MS || peer_of(&self.room).is_some() {
+            return;
+        }
+        self.typing_sent_at = now;
+        let typing = TypingData {
+            user: self.username.clone(),
+            room: self.room.clone(),
+        };
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Typing,
+            data: Some(serde_json::to_string(&typing).unwrap()),
+            data_array: None,
+        });
+    }
+
+    /// Everyone but us typing in the current room, in name order.
+    fn typing_here(&self) -> Vec<&str> {
+        let now = js_sys::Date::now();
+        let mut names: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|((room, _), expires)| *room == self.room && **expires > now)
+            .map(|((_, user), _)| user.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Goes back to the login screen, which shows `notice` until the user
+    /// starts chatting again.
+    fn sign_out(&self, ctx: &Context<Self>, notice: String) {
+        if let Some((user, _)) = ctx.link().context::<User>(Callback::noop()) {
+            *user.signed_out.borrow_mut() = Some(notice);
+        }
+        if let Some(history) = ctx.link().history() {
+            history.push(Route::Login);
+        }
+    }
+
+    /// Searches the messages we already have and asks the server to search
+    /// its history too; server hits are merged in as they arrive.
+    fn search(&mut self, input: &str) {
+        let query = SearchQuery::parse(input);
+        if query.is_empty() {
+            self.search = None;
+            return;
+        }
+        let results = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| query.matches(&m.from, &m.room, &m.message, m.timestamp))
+            .take(MAX_SEARCH_RESULTS)
+            .cloned()
+            .collect();
+        self.send(WebSocketMessage {
+            message_type: MsgTypes::Search,
+            data: Some(serde_json::to_string(&query).unwrap()),
+            data_array: None,
+        });
+        self.search = Some(SearchState { query, results });
+    }
+
+    fn merge_search_results(&mut self, found: SearchResultsData) -> bool {
+        let search = match self.search.as_mut() {
+            Some(search) if search.query.raw == found.query => search,
+            _ => return false,
+        };
+        for m in found.messages.into_iter().filter(|m| m.sealed.is_none()) {
+            if !search.results.iter().any(|r| r.id == m.id) {
+                search.results.push(m);
+            }
+        }
+        search.results.sort_unstable_by_key(|m| std::cmp::Reverse(m.id));
+        search.results.truncate(MAX_SEARCH_RESULTS);
+        true
+    }
+
+    /// Shows a message in its room, pulling it in from the search results if
+    /// it's older than anything we've received.
+    fn jump_to_message(&mut self, id: u64) -> bool {
+        let room = match self.messages.iter().find(|m| m.id == id) {
+            Some(m) => m.room.clone(),
+            None => {
+                let found = match self
+                    .search
+                    .as_ref()
+                    .and_then(|s| s.results.iter().find(|m| m.id == id))
+                {
+                    Some(m) => m.clone(),
+                    None => return false,
+                };
+                let room = found.room.clone();
+                let at = self.messages.partition_point(|m| m.id < id);
+                self.messages.insert(at, found);
+                room
+            }
+        };
+
+        self.switch_room(room);
+        self.revealed.insert(id);
+        let offset = self
+            .virtual_list
+            .offset_of(&self.room_keys(), id)
+            .unwrap_or_default();
+        let top = (offset - ESTIMATED_ROW_HEIGHT).max(0.0);
+        self.stick_to_bottom = false;
+        self.scroll_top = top;
+        self.restore_scroll = Some(top as i32);
+        self.highlighted = Some(id);
+        true
+    }
+
+    fn view_search_results(&self, ctx: &Context<Self>, search: &SearchState) -> Html {
+        html! {
+            <div class="hidden lg:flex flex-col w-96 bg-white border-l border-gray-200">
+                <div class="flex items-center px-4 py-3 border-b border-gray-200">
+                    <h2 class="font-semibold text-gray-700">{"Search results"}</h2>
+                    <span class="ml-2 text-xs text-gray-400">{search.results.len().to_string()}</span>
+                    <button
+                        class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                        title="Close search"
+                        onclick={ctx.link().callback(|_| Msg::CloseSearch)}
+                    >
+                        <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                            <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                        </svg>
+                    </button>
+                </div>
+                <div class="flex-1 overflow-y-auto">
+                    if search.results.is_empty() {
+                        <p class="p-4 text-sm text-gray-500">{"No messages found"}</p>
+                    }
+                    {
+                        search.results.iter().map(|m| {
+                            let shown = self.content_filter.apply(m);
+                            let m = &*shown.message;
+                            let id = m.id;
+                            let date = js_sys::Date::new(&m.timestamp.into())
+                                .to_locale_string("default", &JsValue::UNDEFINED);
+                            html! {
+                                <div
+                                    class="p-4 border-b border-gray-100 hover:bg-gray-50 cursor-pointer"
+                                    onclick={ctx.link().callback(move |_| Msg::JumpToMessage(id))}
+                                >
+                                    <div class="flex items-center text-xs text-gray-500">
+                                        <span class="font-semibold text-gray-700">{m.from.clone()}</span>
+                                        <span class="ml-2">{room_label(&m.room)}</span>
+                                        <span class="ml-auto">{String::from(date)}</span>
+                                    </div>
+                                    <p class="mt-1 text-sm text-gray-700 break-words">
+                                        {
+                                            search::highlight(&m.message, &search.query.terms).into_iter().map(|(hit, text)| {
+                                                if hit {
+                                                    html! { <mark class="bg-yellow-200 rounded">{text}</mark> }
+                                                } else {
+                                                    html! { {text} }
+                                                }
+                                            }).collect::<Html>()
+                                        }
+                                    </p>
+                                </div>
+                            }
+                        }).collect::<Html>()
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    fn set_unread_count(&mut self, count: usize) {
+        if count == self.unread_count {
+            return;
+        }
+        self.unread_count = count;
+        let title = match count {
+            0 => APP_TITLE.to_string(),
+            n => format!("({}) {}", n, APP_TITLE),
+        };
+        gloo::utils::document().set_title(&title);
+    }
+
+    /// Counts a message the user hasn't seen yet and, if it's addressed to
+    /// them while the tab is in the background, raises a notification.
+    fn notify_incoming(&mut self, m: &MessageData) {
+        if m.from == self.username
+            || self.ignored.contains(&m.from)
+            || self.notification_settings.is_muted(&m.room)
+        {
+            return;
+        }
+        if !self.is_caught_up() {
+            self.set_unread_count(self.unread_count + 1);
+        }
+        if gloo::utils::document().has_focus().unwrap_or(false) {
+            return;
+        }
+
+        let direct = m.to.as_deref() == Some(self.username.as_str());
+        if !direct && !notifications::is_mention(&m.message, &self.username) {
+            return;
+        }
+        let user = self.profile(&m.from);
+        let title = if direct {
+            format!("{} sent you a message", user.name)
+        } else {
+            format!("{} mentioned you in #{}", user.name, m.room)
+        };
+        let shown = self.content_filter.apply(m);
+        notifications::notify(&title, &shown.message.message, &user.avatar);
+        if self.notification_settings.sound {
+            notifications::play_sound();
+        }
+    }
+
+    fn view_notification_controls(&self, ctx: &Context<Self>) -> Html {
+        let muted = self.notification_settings.is_muted(&self.room);
+        let button = "p-1 rounded hover:bg-gray-100 focus:outline-none";
+
+        html! {
+            <div class="flex items-center mr-4 space-x-1">
+                if self.notification_permission == NotificationPermission::Default {
+                    <button
+                        class="px-2 py-0.5 text-xs text-blue-700 bg-blue-50 rounded hover:bg-blue-100 focus:outline-none"
+                        onclick={ctx.link().callback(|_| Msg::RequestNotifications)}
+                    >
+                        {"Enable notifications"}
+                    </button>
+                }
+                <button
+                    class={button}
+                    title={if muted { "Unmute this room" } else { "Mute this room" }}
+                    onclick={ctx.link().callback(|_| Msg::ToggleMute)}
+                >
+                    <svg xmlns="http://www.w3.org/2000/svg" class={classes!("h-5", "w-5", if muted { "text-gray-300" } else { "text-blue-600" })} fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15 17h5l-1.405-1.405A2.032 2.032 0 0118 14.158V11a6.002 6.002 0 00-4-5.659V5a2 2 0 10-4 0v.341C7.67 6.165 6 8.388 6 11v3.159c0 .538-.214 1.055-.595 1.436L4 17h5m6 0v1a3 3 0 11-6 0v-1m6 0H9" />
+                    </svg>
+                </button>
+                <button
+                    class={button}
+                    title={if self.notification_settings.sound { "Turn sound off" } else { "Turn sound on" }}
+                    onclick={ctx.link().callback(|_| Msg::ToggleSound)}
+                >
+                    <svg xmlns="http://www.w3.org/2000/svg" class={classes!("h-5", "w-5", if self.notification_settings.sound { "text-blue-600" } else { "text-gray-300" })} fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15.536 8.464a5 5 0 010 7.072M18.364 5.636a9 9 0 010 12.728M11 5L6 9H2v6h4l5 4V5z" />
+                    </svg>
+                </button>
+            </div>
+        }
+    }
+
+    fn view_message(
+        &self,
+        ctx: &Context<Self>,
+        prev: Option<&MessageData>,
+        m: &MessageData,
+        show_read_by: bool,
+    ) -> Html {
+        let shown = self.content_filter.apply(m);
+        let m = &*shown.message;
+        // Webhooks may post under any free name, even one we'll go on to use.
+        let own = m.from == self.username && m.bot.is_none();
+        let unread_divider = self.first_unread == Some(m.id);
+        let group_start = unread_divider || starts_group(prev, m);
+        let user = self.profile(&m.from);
+        let open_card = {
+            let from = m.from.clone();
+            ctx.link().callback(move |e: MouseEvent| {
+                Msg::OpenCard(CardAt {
+                    x: e.client_x(),
+                    y: e.client_y(),
+                    user: from.clone(),
+                })
+            })
+        };
+        let bubble = if own {
+            "p-3 bg-blue-600 text-white rounded-lg rounded-tr-none shadow-sm"
+        } else {
+            "p-3 bg-white text-gray-700 rounded-lg rounded-tl-none shadow-sm border-l-4 border-blue-300"
+        };
+
+        html! {
+            <div
+                key={m.id}
+                data-key={m.id.to_string()}
+                class={classes!(if group_start { "pt-6" } else { "pt-1" }, (self.highlighted == Some(m.id)).then_some("bg-yellow-50"))}
+            >
+                if unread_divider {
+                    <div class="flex items-center mb-6 text-xs font-semibold text-red-500">
+                        <div class="flex-1 border-t border-red-300"></div>
+                        <span class="px-2">{"New messages"}</span>
+                        <div class="flex-1 border-t border-red-300"></div>
+                    </div>
+                }
+                <div class={classes!("flex", "items-start", own.then_some("flex-row-reverse"))}>
+                    if group_start {
+                        <img
+                            class={classes!("w-10", "h-10", "rounded-full", "shadow", "cursor-pointer", if own { "ml-3" } else { "mr-3" })}
+                            src={user.avatar.clone()}
+                            alt="avatar"
+                            onclick={open_card.clone()}
+                        />
+                    } else {
+                        <div class={classes!("w-10", "flex-shrink-0", if own { "ml-3" } else { "mr-3" })}></div>
+                    }
+                    <div class={classes!("flex", "flex-col", "max-w-3xl", own.then_some("items-end"))}>
+                        if group_start {
+                            <div class={classes!("flex", "items-center", "mb-1", own.then_some("flex-row-reverse"))}>
+                                <span class="font-semibold text-gray-800 cursor-pointer hover:underline" title={m.from.clone()} onclick={open_card}>
+                                    {if own { "You".to_string() } else { user.name.clone() }}
+                                </span>
+                                if let Some(bot) = &m.bot {
+                                    <span
+                                        class={classes!("px-1", "text-xs", "font-bold", "text-white", "bg-gray-500", "rounded", if own { "mr-2" } else { "ml-2" })}
+                                        title={bot.description.clone()}
+                                    >
+                                        {"BOT"}
+                                    </span>
+                                }
+                                <span class={classes!("text-xs", "text-gray-400", if own { "mr-2" } else { "ml-2" })}>{format_time(m.timestamp)}</span>
+                            </div>
+                        }
+                        if !m.message.is_empty() || m.attachments.is_empty() {
+                            <div class={bubble} oncontextmenu={self.oncontextmenu(ctx, &m.from, Some(m.id))}>
+                                if let (Some(to), None) = (&m.to, &m.sealed) {
+                                    <div class="mb-1 text-xs opacity-75">
+                                        {if own { format!("Only to {}", to) } else { "Only to you".to_string() }}
+                                    </div>
+                                }
+                                {
+                                    if m.sealed.is_some() && m.message.is_empty() {
+                                        let status = if self.identity_failed || self.unreadable.contains(&m.id) {
+                                            "This message couldn't be decrypted in this browser"
+                                        } else {
+                                            "Decrypting\u{2026}"
+                                        };
+                                        html! {
+                                            <p class="text-sm italic opacity-75">{status}</p>
+                                        }
+                                    } else if shown.hide_images {
+                                        html! {
+                                            <p class="text-sm italic opacity-75">
+                                                {"Image hidden by your content filter \u{b7} "}
+                                                <a class="underline" href={m.message.clone()} target="_blank" rel="noopener noreferrer">{"open"}</a>
+                                            </p>
+                                        }
+                                    } else if search::is_image(&m.message) {
+                                        html! {
+                                            <div class="mt-1 rounded-md overflow-hidden">
+                                                <img class="max-w-full rounded" src={m.message.clone()} alt="GIF" onload={ctx.link().callback(|_| Msg::RowsResized)}/>
+                                            </div>
+                                        }
+                                    } else if let Some(action) = m.message.strip_prefix("/me ") {
+                                        html! {
+                                            <p class="italic">{format!("* {} {}", user.name, action)}</p>
+                                        }
+                                    } else {
+                                        html! {
+                                            <p>{m.message.clone()}</p>
+                                        }
+                                    }
+                                }
+                            </div>
+                        }
+                        { m.attachments.iter().map(|a| self.view_attachment(ctx, m.id, a)).collect::<Html>() }
+                        if show_read_by {
+                            { self.view_read_by(m) }
+                        }
+                    </div>
+                </div>
+            </div>
+        }
+    }
+
+    /// The placeholder for a run of messages from ignored people.
+    fn view_hidden(&self, ctx: &Context<Self>, ids: &[u64]) -> Html {
+        let onclick = {
+            let ids = ids.to_vec();
+            ctx.link().callback_once(move |_| Msg::Reveal(ids))
+        };
+        let count = match ids.len() {
+            1 => "1 hidden message".to_string(),
+            n => format!("{} hidden messages", n),
+        };
+        html! {
+            <div key={ids[0]} data-key={ids[0].to_string()} class="pt-2 text-center text-xs text-gray-400">
+                {format!("{} from people you ignore \u{b7} ", count)}
+                <button class="text-blue-600 hover:underline focus:outline-none" {onclick}>{"show"}</button>
+            </div>
+        }
+    }
+
+    /// A card a bot attached to message `id`.
+    fn view_attachment(&self, ctx: &Context<Self>, id: u64, a: &Attachment) -> Html {
+        let accent = a.accent().map(|color| format!("border-left-color: {}", color));
+        html! {
+            <div class="mt-2 p-3 w-96 max-w-full bg-white text-gray-700 border border-gray-200 border-l-4 border-l-gray-400 rounded shadow-sm" style={accent}>
+                <div class="font-semibold">{a.title.clone()}</div>
+                if !a.text.is_empty() {
+                    <p class="mt-1 text-sm">{a.text.clone()}</p>
+                }
+                if !a.fields.is_empty() {
+                    <div class="grid grid-cols-2 gap-2 mt-2 text-sm">
+                        {
+                            a.fields.iter().map(|f| html! {
+                                <div class={classes!((!f.short).then_some("col-span-2"))}>
+                                    <div class="text-xs font-semibold text-gray-500">{f.name.clone()}</div>
+                                    <div>{f.value.clone()}</div>
+                                </div>
+                            }).collect::<Html>()
+                        }
+                    </div>
+                }
+                if !a.buttons.is_empty() {
+                    <div class="flex flex-wrap gap-2 mt-3">
+                        {
+                            a.buttons.iter().map(|b| {
+                                let pressed = self.pressed.contains(&(id, b.id.clone()));
+                                let style = match b.style {
+                                    ButtonStyle::Default => "border border-gray-300 text-gray-700 hover:bg-gray-50",
+                                    ButtonStyle::Primary => "bg-blue-600 text-white hover:bg-blue-700",
+                                    ButtonStyle::Danger => "bg-red-600 text-white hover:bg-red-700",
+                                };
+                                let onclick = {
+                                    let button = b.id.clone();
+                                    ctx.link().callback(move |_| Msg::PressButton { message_id: id, button: button.clone() })
+                                };
+                                html! {
+                                    <button
+                                        class={classes!("px-3", "py-1", "text-sm", "rounded", "focus:outline-none", style, pressed.then_some("opacity-50 cursor-default"))}
+                                        disabled={pressed}
+                                        {onclick}
+                                    >
+                                        {b.label.clone()}
+                                    </button>
+                                }
+                            }).collect::<Html>()
+                        }
+                    </div>
+                }
+            </div>
+        }
+    }
+
+    /// Rows of the message list to render for the current scroll position.
+    fn window(&self, keys: &[u64]) -> Window {
+        let scroll_top = if self.stick_to_bottom {
+            f64::MAX
+        } else {
+            self.scroll_top
+        };
+        self.virtual_list
+            .window(keys, scroll_top, self.viewport_height)
+    }
+
+    fn room_rows(&self) -> Vec<Row<'_>> {
+        let messages: Vec<&MessageData> =
+            self.messages.iter().filter(|m| m.room == self.room).collect();
+        rows(&messages, |m| self.is_hidden(m))
+    }
+
+    fn room_keys(&self) -> Vec<u64> {
+        self.room_rows().iter().map(Row::key).collect()
+    }
+
+    /// Whether `m` is from someone ignored and hasn't been asked for.
+    fn is_hidden(&self, m: &MessageData) -> bool {
+        m.from != self.username && self.ignored.contains(&m.from) && !self.revealed.contains(&m.id)
+    }
+
+    /// Picks up the current scroll offset and viewport size of the list;
+    /// returns `true` if that brings different rows into the window.
+    fn update_viewport(&mut self) -> bool {
+        let list = match self.message_list.cast::<Element>() {
+            Some(list) => list,
+            None => return false,
+        };
+        self.scroll_top = list.scroll_top() as f64;
+        self.viewport_height = list.client_height() as f64;
+        let window = self.window(&self.room_keys());
+        self.last_window.replace((window.start, window.end)) != Some((window.start, window.end))
+    }
+
+    /// Feeds the heights of the rendered rows back into the virtual list;
+    /// returns `true` if any of them differ from what the layout assumed.
+    fn measure_rows(&mut self) -> bool {
+        let rows = match self
+            .message_list
+            .cast::<Element>()
+            .and_then(|list| list.query_selector_all("[data-key]").ok())
+        {
+            Some(rows) => rows,
+            None => return false,
+        };
+        let mut changed = false;
+        for i in 0..rows.length() {
+            let row = match rows.item(i).and_then(|n| n.dyn_into::<HtmlElement>().ok()) {
+                Some(row) => row,
+                None => continue,
+            };
+            if let Some(key) = row.get_attribute("data-key").and_then(|k| k.parse().ok()) {
+                changed |= self.virtual_list.set_height(key, row.offset_height() as f64);
+            }
+        }
+        changed
+    }
+
+    fn profile(&self, name: &str) -> UserProfile {
+        let profile = self.profiles.get(name);
+        UserProfile {
+            name: profiles::display_name(name, profile),
+            avatar: profiles::avatar_url(name, profile),
+        }
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MsgTypes {
-    Users,
-    Register,
-    Message,
-}
+    fn role_label(&self, name: &str) -> Option<&'static str> {
+        match self.roles.get(name) {
+            Some(Role::Owner) => Some("Owner"),
+            Some(Role::Moderator) => Some("Moderator"),
+            _ => None,
+        }
+    }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketMessage {
-    message_type: MsgTypes,
-    data_array: Option<Vec<String>>,
-    data: Option<String>,
-}
+    fn view_latency(&self) -> Html {
+        let ms = match self.latency {
+            Some(ms) if self.connection == ConnectionState::Connected => ms,
+            _ => return html! {},
+        };
+        let (quality, color) = match ms {
+            ms if ms < GOOD_LATENCY_MS => ("Good", "bg-green-500"),
+            ms if ms < FAIR_LATENCY_MS => ("Fair", "bg-yellow-500"),
+            _ => ("Poor", "bg-red-500"),
+        };
+        html! {
+            <span class="flex items-center mr-4" title={format!("{} connection", quality)}>
+                <span class={classes!("inline-block", "w-2", "h-2", "mr-1", "rounded-full", color)}></span>
+                {format!("{:.0} ms", ms)}
+            </span>
+        }
+    }
 
-#[derive(Clone)]
-struct UserProfile {
-    name: String,
-    avatar: String,
-}
+    fn view_read_by(&self, m: &MessageData) -> Html {
+        let readers: Vec<&str> = self
+            .read_receipts
+            .readers_of(&m.room, m.id)
+            .into_iter()
+            .filter(|r| *r != self.username && *r != m.from)
+            .collect();
+        if readers.is_empty() {
+            return html! {};
+        }
 
-pub struct Chat {
-    users: Vec<UserProfile>,
-    chat_input: NodeRef,
-    _producer: Box<dyn Bridge<EventBus>>,
-    wss: WebsocketService,
-    messages: Vec<MessageData>,
+        html! {
+            <div class="flex items-center justify-end mt-1 space-x-1" title={format!("Read by {}", readers.join(", "))}>
+                {
+                    readers.iter().map(|r| {
+                        html! {
+                            <img class="w-4 h-4 rounded-full border border-white shadow-sm" src={self.profile(r).avatar} alt={r.to_string()}/>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
 }
+
 impl Component for Chat {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
     fn create(ctx: &Context<Self>) -> Self {
         let (user, _) = ctx
             .link()
             .context::<User>(Callback::noop())
             .expect("context to be set");
-        let wss = WebsocketService::new();
         let username = user.username.borrow().clone();
+        // Registers with the server, unless another tab already did.
+        let wss = SharedConnection::new(&username, TransportKind::from_query());
+        let ignored = IgnoreList::load(&username);
+        let key_ring = KeyRing::load(&username);
+        let own_profile = profiles::load_own(&username);
+        Self::load_identity(ctx, username.clone());
 
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
-            data_array: None,
-        };
+        let mut producer = EventBus::bridge(ctx.link().callback(Msg::HandleEvent));
+        producer.send(Request::Subscribe(vec![
+            Topic::Users,
+            Topic::Messages,
+            Topic::Rooms,
+            Topic::ReadReceipts,
+            Topic::Typing,
+            Topic::Search,
+            Topic::Connection,
+            Topic::Errors,
+            Topic::Sync,
+            Topic::Moderation,
+        ]));
 
-        if let Ok(_) = wss
-            .tx
-            .clone()
-            .try_send(serde_json::to_string(&message).unwrap())
-        {
-            log::debug!("message sent successfully");
-        }
+        let link = ctx.link().clone();
+        let visibility_listener =
+            EventListener::new(&gloo::utils::document(), "visibilitychange", move |_| {
+                link.send_message(Msg::SyncReadState)
+            });
+        let link = ctx.link().clone();
+        let focus_listener = EventListener::new(&gloo::utils::window(), "focus", move |_| {
+            link.send_message(Msg::SyncReadState)
+        });
+        let link = ctx.link().clone();
+        let resize_listener = EventListener::new(&gloo::utils::window(), "resize", move |_| {
+            link.send_message(Msg::Scrolled)
+        });
 
         Self {
+            username,
+            room: DEFAULT_ROOM.to_string(),
+            rooms: vec![DEFAULT_ROOM.to_string()],
             users: vec![],
+            profiles: BTreeMap::new(),
+            own_profile,
+            card: None,
             messages: vec![],
             chat_input: NodeRef::default(),
+            message_list: NodeRef::default(),
+            search_input: NodeRef::default(),
             wss,
-            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+            _producer: producer,
+            _visibility_listener: visibility_listener,
+            _focus_listener: focus_listener,
+            _resize_listener: resize_listener,
+            read_receipts: ReadReceipts::default(),
+            first_unread: None,
+            notification_settings: NotificationSettings::load(),
+            notification_permission: notifications::permission(),
+            unread_count: 0,
+            stick_to_bottom: true,
+            unseen_below: 0,
+            scroll_positions: HashMap::new(),
+            restore_scroll: None,
+            virtual_list: VirtualList::new(ESTIMATED_ROW_HEIGHT, OVERSCAN),
+            scroll_top: 0.0,
+            viewport_height: 0.0,
+            last_window: None,
+            search: None,
+            highlighted: None,
+            connection: ConnectionState::Connected,
+            latency: None,
+            last_error: None,
+            drafts: HashMap::new(),
+            limiter: TokenBucket::for_messages(),
+            send_notice: None,
+            notice_timeout: None,
+            typing: HashMap::new(),
+            typing_sent_at: 0.0,
+            commands: Commands::default(),
+            suggestion: 0,
+            suggestions_dismissed: false,
+            show_help: false,
+            topics: HashMap::new(),
+            left_rooms: HashSet::new(),
+            pressed: HashSet::new(),
+            webhooks_open: false,
+            webhooks: HashMap::new(),
+            roles: BTreeMap::new(),
+            muted: None,
+            mute_timeout: None,
+            menu: None,
+            moderation_log_open: false,
+            moderation_log: vec![],
+            ignored,
+            revealed: HashSet::new(),
+            settings_open: false,
+            content_filter: ContentFilter::load(),
+            reporting: None,
+            reports_open: false,
+            reports: vec![],
+            identity: None,
+            identity_failed: false,
+            public_keys: PublicKeys::new(),
+            key_ring,
+            fingerprints: HashMap::new(),
+            unreadable: HashSet::new(),
+            encryption_open: false,
         }
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            Msg::HandleMsg(s) => {
-                let msg: WebSocketMessage = serde_json::from_str(&s).unwrap();
-                match msg.message_type {
-                    MsgTypes::Users => {
-                        let users_from_message = msg.data_array.unwrap_or_default();
-                        self.users = users_from_message
-                            .iter()
-                            .map(|u| UserProfile {
-                                name: u.into(),
-                                avatar: format!(
-                                    "https://avatars.dicebear.com/api/adventurer-neutral/{}.svg",
-                                    u
-                                )
-                                .into(),
-                            })
-                            .collect();
-                        return true;
+            Msg::HandleEvent(event) => match event {
+                Event::Users(users_from_message, keys) => {
+                    for key in keys.values() {
+                        self.compute_fingerprint(ctx, key);
                     }
-                    MsgTypes::Message => {
-                        let message_data: MessageData =
-                            serde_json::from_str(&msg.data.unwrap()).unwrap();
-                        self.messages.push(message_data);
-                        return true;
+                    // Only once per change, not every time the list comes round.
+                    let changed: Vec<String> = self
+                        .key_ring
+                        .changed(&keys)
+                        .filter(|peer| self.public_keys.get(*peer) != keys.get(*peer))
+                        .map(str::to_string)
+                        .collect();
+                    for peer in changed {
+                        let notice = format!("{}'s encryption key changed. Check their fingerprint before trusting it.", peer);
+                        self.show_notice(ctx, notice);
                     }
-                    _ => {
+                    self.public_keys = keys;
+                    self.users = users_from_message;
+                    true
+                }
+                // Already numbered and timestamped by the connection, for
+                // servers that don't.
+                Event::Message(mut message_data) => {
+                    if message_data.sealed.is_some() {
+                        // Encrypted messages live in a conversation of their
+                        // own, whatever room they were sent from.
+                        let peer = match &message_data.to {
+                            Some(to) if message_data.from == self.username => to,
+                            _ => &message_data.from,
+                        };
+                        message_data.room = direct_room(peer);
+                        message_data.message.clear();
+                    }
+                    if !self.rooms.contains(&message_data.room)
+                        && !self.left_rooms.contains(&message_data.room)
+                    {
+                        self.rooms.push(message_data.room.clone());
+                    }
+                    if message_data.room == self.room && !self.ignored.contains(&message_data.from) {
+                        if self.first_unread.is_none() && !self.is_caught_up() {
+                            self.first_unread = Some(message_data.id);
+                        }
+                        if !self.stick_to_bottom {
+                            self.unseen_below += 1;
+                        }
+                    }
+                    if message_data.sealed.is_some() {
+                        // Notified about once we can tell what it says.
+                        self.open_sealed(ctx, &message_data);
+                    } else {
+                        self.notify_incoming(&message_data);
+                    }
+                    self.typing
+                        .remove(&(message_data.room.clone(), message_data.from.clone()));
+                    self.messages.push(message_data);
+                    true
+                }
+                Event::Profiles(profiles) => {
+                    self.profiles = profiles;
+                    true
+                }
+                Event::Roles(roles) => {
+                    self.roles = roles;
+                    if self.role() < Role::Moderator {
+                        self.webhooks_open = false;
+                        self.moderation_log_open = false;
+                        self.reports_open = false;
+                        self.reports.clear();
+                    }
+                    true
+                }
+                Event::Removed(id) => {
+                    self.messages.retain(|m| m.id != id);
+                    if let Some(search) = self.search.as_mut() {
+                        search.results.retain(|m| m.id != id);
+                    }
+                    if self.first_unread == Some(id) {
+                        self.first_unread = None;
+                    }
+                    true
+                }
+                Event::Muted(sanction) => {
+                    self.muted = Some(sanction);
+                    self.check_mute(ctx);
+                    true
+                }
+                Event::Unmuted => {
+                    self.mute_timeout = None;
+                    self.muted.take().is_some()
+                }
+                Event::Kicked(sanction) => {
+                    self.sign_out(ctx, kicked_notice(&sanction));
+                    false
+                }
+                Event::Refused(reason) => {
+                    self.sign_out(ctx, reason);
+                    false
+                }
+                Event::ModerationLog(entries) => {
+                    for entry in entries {
+                        if !self.moderation_log.iter().any(|e| e.id == entry.id) {
+                            self.moderation_log.push(entry);
+                        }
+                    }
+                    self.moderation_log.sort_unstable_by_key(|e| e.id);
+                    self.moderation_log_open
+                }
+                Event::Reports(reports) => {
+                    self.reports = reports;
+                    true
+                }
+                Event::Read(read) => {
+                    self.read_receipts
+                        .mark_read(&read.room, &read.user, read.message_id)
+                }
+                Event::Typing(typing) => {
+                    if typing.user == self.username || self.ignored.contains(&typing.user) {
                         return false;
                     }
+                    let expires = js_sys::Date::now() + TYPING_TIMEOUT_MS as f64;
+                    self.typing.insert((typing.room, typing.user), expires);
+                    let link = ctx.link().clone();
+                    Timeout::new(TYPING_TIMEOUT_MS, move || link.send_message(Msg::PruneTyping))
+                        .forget();
+                    true
+                }
+                Event::Topic(topic) => {
+                    let here = topic.room == self.room;
+                    if topic.topic.is_empty() {
+                        self.topics.remove(&topic.room);
+                    } else {
+                        self.topics.insert(topic.room.clone(), topic);
+                    }
+                    here
                 }
+                Event::Webhooks(list) => {
+                    let here = list.room == self.room;
+                    self.webhooks.insert(list.room, list.hooks);
+                    self.webhooks_open && here
+                }
+                Event::SearchResults(found) => self.merge_search_results(found),
+                Event::Connection(state) => {
+                    self.connection = state;
+                    match state {
+                        ConnectionState::Connected => {
+                            self.publish_key();
+                            self.publish_profile();
+                        }
+                        ConnectionState::Disconnected => self.latency = None,
+                    }
+                    true
+                }
+                Event::Latency(ms) => {
+                    self.latency = Some(ms);
+                    true
+                }
+                // Both are kept by the connection, not shown.
+                Event::Pong(_) | Event::Session(_) => false,
+                Event::SlowDown(ms) => {
+                    // Whichever tab sent it, the limit is on the user.
+                    self.limiter.pause(js_sys::Date::now(), ms);
+                    self.show_notice(
+                        ctx,
+                        format!(
+                            "The server is getting too many messages from you; the last one wasn't delivered. Try again in {}s.",
+                            (ms / 1000.0).ceil()
+                        ),
+                    );
+                    true
+                }
+                Event::Error(e) => {
+                    log::error!("{}", e);
+                    self.last_error = Some(e);
+                    true
+                }
+                Event::Sync(SyncEvent::Read(read)) => {
+                    // Read in another tab counts as read here too.
+                    self.read_receipts
+                        .mark_read(&read.room, &read.user, read.message_id);
+                    if read.room == self.room {
+                        self.first_unread = None;
+                        self.set_unread_count(0);
+                    }
+                    true
+                }
+                Event::Sync(SyncEvent::Draft { room, text }) => {
+                    if room == self.room {
+                        if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                            input.set_value(&text);
+                        }
+                    }
+                    self.drafts.insert(room, text);
+                    false
+                }
+            },
+            Msg::DismissError => self.last_error.take().is_some(),
+            Msg::DismissNotice => {
+                self.notice_timeout = None;
+                self.send_notice.take().is_some()
             }
             Msg::SubmitMessage => {
-                let input = self.chat_input.cast::<HtmlInputElement>();
-                if let Some(input) = input {
-                    let message = WebSocketMessage {
-                        message_type: MsgTypes::Message,
-                        data: Some(input.value()),
-                        data_array: None,
-                    };
-                    if let Err(e) = self
-                        .wss
-                        .tx
-                        .clone()
-                        .try_send(serde_json::to_string(&message).unwrap())
-                    {
-                        log::debug!("error sending to channel: {:?}", e);
+                let input = match self.chat_input.cast::<HtmlInputElement>() {
+                    Some(input) => input,
+                    None => return false,
+                };
+                let text = input.value();
+                let action = match self.commands.parse(&text) {
+                    Ok(action) => action,
+                    Err(e) => {
+                        self.show_notice(ctx, e.to_string());
+                        return true;
+                    }
+                };
+                // Cleared before running, since commands like /join move the
+                // composer on to another room's draft.
+                input.set_value("");
+                self.drafts.remove(&self.room);
+                self.show_help = false;
+                let room = self.room.clone();
+                if self.run(ctx, action) {
+                    self.wss.share(SyncEvent::Draft {
+                        room,
+                        text: String::new(),
+                    });
+                } else if self.room == room {
+                    input.set_value(&text);
+                    self.drafts.insert(room, text);
+                }
+                true
+            }
+            Msg::DraftChanged => {
+                let suggesting = !self.suggestions().is_empty();
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    let text = input.value();
+                    self.drafts.insert(self.room.clone(), text.clone());
+                    // Commands are nobody's business until they've run.
+                    if !text.is_empty() && !text.starts_with('/') {
+                        self.send_typing();
+                    }
+                    self.wss.share(SyncEvent::Draft {
+                        room: self.room.clone(),
+                        text,
+                    });
+                }
+                self.show_help = false;
+                self.suggestions_dismissed = false;
+                self.suggestion = 0;
+                suggesting || !self.suggestions().is_empty()
+            }
+            Msg::CompleteCommand(i) => {
+                let name = match self.suggestions().get(i) {
+                    Some(command) => command.name,
+                    None => return false,
+                };
+                // Once arguments are being typed there's nothing to complete.
+                if self.composer_text().contains(char::is_whitespace) {
+                    return false;
+                }
+                let text = format!("/{} ", name);
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    input.set_value(&text);
+                    let _ = input.focus();
+                }
+                self.drafts.insert(self.room.clone(), text);
+                self.show_help = false;
+                self.suggestion = 0;
+                true
+            }
+            Msg::MoveSuggestion(step) => {
+                let count = self.suggestions().len() as i32;
+                if count == 0 {
+                    return false;
+                }
+                self.suggestion = (self.suggestion as i32 + step).rem_euclid(count) as usize;
+                true
+            }
+            Msg::DismissSuggestions => {
+                self.show_help = false;
+                self.suggestions_dismissed = true;
+                true
+            }
+            Msg::PressButton { message_id, button } => {
+                if !self.pressed.insert((message_id, button.clone())) {
+                    return false;
+                }
+                let interaction = InteractionData {
+                    message_id,
+                    button,
+                    user: self.username.clone(),
+                    room: self.room.clone(),
+                };
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::Interaction,
+                    data: Some(serde_json::to_string(&interaction).unwrap()),
+                    data_array: None,
+                });
+                true
+            }
+            Msg::ToggleWebhooks => {
+                self.webhooks_open = !self.webhooks_open;
+                if self.webhooks_open {
+                    self.list_webhooks();
+                }
+                true
+            }
+            Msg::CreateWebhook(name) => {
+                let request = WebhookRequest {
+                    room: self.room.clone(),
+                    name,
+                };
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::CreateWebhook,
+                    data: Some(serde_json::to_string(&request).unwrap()),
+                    data_array: None,
+                });
+                false
+            }
+            Msg::RevokeWebhook(id) => {
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::RevokeWebhook,
+                    data: Some(id),
+                    data_array: None,
+                });
+                false
+            }
+            Msg::OpenMenu(menu) => {
+                self.menu = Some(menu);
+                true
+            }
+            Msg::CloseMenu => self.menu.take().is_some(),
+            Msg::OpenCard(card) => {
+                self.card = Some(card);
+                true
+            }
+            Msg::CloseCard => self.card.take().is_some(),
+            Msg::SaveProfile(profile) => {
+                profiles::save_own(&self.username, &profile);
+                self.own_profile = profile;
+                self.publish_profile();
+                true
+            }
+            Msg::Moderate(action) => {
+                self.menu = None;
+                self.moderate(action);
+                true
+            }
+            Msg::ToggleModerationLog => {
+                self.moderation_log_open = !self.moderation_log_open;
+                true
+            }
+            Msg::CheckMute => self.check_mute(ctx),
+            Msg::ToggleSettings => {
+                self.settings_open = !self.settings_open;
+                true
+            }
+            Msg::SetContentFilter(filter) => {
+                filter.save();
+                self.content_filter = filter;
+                true
+            }
+            Msg::Ignore(user) => {
+                self.menu = None;
+                if user == self.username || !self.ignored.ignore(&user) {
+                    return true;
+                }
+                self.ignored.save(&self.username);
+                self.typing.retain(|(_, name), _| *name != user);
+                true
+            }
+            Msg::Unignore(user) => {
+                self.menu = None;
+                if !self.ignored.unignore(&user) {
+                    return true;
+                }
+                self.ignored.save(&self.username);
+                true
+            }
+            Msg::StartReport(id) => {
+                self.menu = None;
+                self.reporting = self.messages.iter().find(|m| m.id == id).cloned();
+                true
+            }
+            Msg::SubmitReport(report) => {
+                self.reporting = None;
+                self.send(WebSocketMessage {
+                    message_type: MsgTypes::Report,
+                    data: Some(serde_json::to_string(&report).unwrap()),
+                    data_array: None,
+                });
+                self.show_notice(ctx, "Thanks. The moderators will take a look.".to_string());
+                true
+            }
+            Msg::CancelReport => self.reporting.take().is_some(),
+            Msg::ToggleReports => {
+                self.reports_open = !self.reports_open;
+                true
+            }
+            Msg::CloseReport { report_id, dismissed } => {
+                self.moderate(ModAction::CloseReport {
+                    report_id,
+                    user: String::new(),
+                    dismissed,
+                });
+                false
+            }
+            Msg::Reveal(ids) => {
+                self.revealed.extend(ids);
+                true
+            }
+            Msg::IdentityLoaded { account, identity } => {
+                if account != self.username {
+                    return false;
+                }
+                let identity = match identity {
+                    Ok(identity) => identity,
+                    Err(e) => {
+                        log::error!("no key for encrypted messages: {}", e);
+                        self.identity_failed = true;
+                        return true;
+                    }
+                };
+                self.compute_fingerprint(ctx, &identity.public_key);
+                self.identity = Some(identity);
+                self.publish_key();
+                for m in self.messages.iter().filter(|m| m.sealed.is_some()) {
+                    self.open_sealed(ctx, m);
+                }
+                false
+            }
+            Msg::Fingerprint { key, fingerprint } => {
+                self.fingerprints.insert(key, fingerprint);
+                self.encryption_open
+            }
+            Msg::SendSealed { room, text, frame } => {
+                let sent = match frame {
+                    // Already let through before it was encrypted.
+                    Ok(frame) => self.deliver(ctx, frame),
+                    Err(e) => {
+                        log::error!("couldn't encrypt a message: {}", e);
+                        self.show_notice(ctx, "Couldn't encrypt the message.".to_string());
+                        false
                     }
-                    input.set_value("");
                 };
+                if !sent {
+                    self.restore_draft(room, text);
+                }
+                true
+            }
+            Msg::Opened { id, text } => {
+                let m = match self.messages.iter_mut().find(|m| m.id == id) {
+                    Some(m) => m,
+                    None => return false,
+                };
+                let (text, sealed) = match (text, &m.sealed) {
+                    (Some(text), Some(sealed)) => (text, sealed),
+                    _ => {
+                        self.unreadable.insert(id);
+                        return true;
+                    }
+                };
+                m.message = text;
+                let m = m.clone();
+                let (peer, key) = if m.from == self.username {
+                    (m.to.as_deref().unwrap_or_default(), &sealed.recipient_key)
+                } else {
+                    (m.from.as_str(), &sealed.sender_key)
+                };
+                if self.key_ring.pin(peer, key) {
+                    self.key_ring.save(&self.username);
+                } else {
+                    let notice = format!("{} wrote with a key you haven't pinned for them. Check their fingerprint.", peer);
+                    self.show_notice(ctx, notice);
+                }
+                self.notify_incoming(&m);
+                true
+            }
+            Msg::OpenDirect(user) => {
+                self.menu = None;
+                self.switch_room(direct_room(&user));
+                true
+            }
+            Msg::ToggleEncryption => {
+                self.encryption_open = !self.encryption_open;
+                true
+            }
+            Msg::VerifyKey => {
+                if let Some(peer) = peer_of(&self.room) {
+                    if let Some(key) = self.public_keys.get(peer) {
+                        self.key_ring.verify(peer, key);
+                        self.key_ring.save(&self.username);
+                    }
+                }
+                true
+            }
+            Msg::PruneTyping => {
+                let now = js_sys::Date::now();
+                let before = self.typing.len();
+                self.typing.retain(|_, expires| *expires > now);
+                self.typing.len() != before
+            }
+            Msg::SyncReadState => {
+                self.sync_read_state();
                 false
             }
+            Msg::Scrolled => {
+                let at_bottom = self.is_at_bottom();
+                let changed = at_bottom != self.stick_to_bottom
+                    || (at_bottom && self.unseen_below > 0)
+                    || self.update_viewport();
+                self.stick_to_bottom = at_bottom;
+                if at_bottom {
+                    self.unseen_below = 0;
+                }
+                self.sync_read_state();
+                changed
+            }
+            Msg::RowsResized => {
+                let resized = self.measure_rows();
+                self.update_viewport() || resized
+            }
+            Msg::Relayout => true,
+            Msg::JumpToLatest => {
+                self.stick_to_bottom = true;
+                self.unseen_below = 0;
+                true
+            }
+            Msg::SwitchRoom(room) => {
+                self.switch_room(room);
+                true
+            }
+            Msg::Search => {
+                if let Some(input) = self.search_input.cast::<HtmlInputElement>() {
+                    self.search(&input.value());
+                }
+                true
+            }
+            Msg::CloseSearch => {
+                self.highlighted = None;
+                self.search.take().is_some()
+            }
+            Msg::JumpToMessage(id) => self.jump_to_message(id),
+            Msg::RequestNotifications => {
+                notifications::request_permission(
+                    ctx.link().callback(Msg::NotificationPermission),
+                );
+                false
+            }
+            Msg::NotificationPermission(permission) => {
+                self.notification_permission = permission;
+                true
+            }
+            Msg::ToggleMute => {
+                self.notification_settings.toggle_mute(&self.room);
+                self.notification_settings.save();
+                true
+            }
+            Msg::ToggleSound => {
+                self.notification_settings.sound = !self.notification_settings.sound;
+                self.notification_settings.save();
+                true
+            }
+        }
+    }
+
+    fn rendered(&mut self, ctx: &Context<Self>, _first_render: bool) {
+        if let Some(list) = self.message_list.cast::<Element>() {
+            if let Some(position) = self.restore_scroll.take() {
+                list.set_scroll_top(position);
+            } else if self.stick_to_bottom {
+                list.set_scroll_top(list.scroll_height());
+            }
+        }
+        // Heights we guessed or a viewport we hadn't seen yet mean the
+        // window we just rendered may be the wrong one; only then is it
+        // worth rendering again.
+        let resized = self.measure_rows();
+        if self.update_viewport() || resized {
+            ctx.link().send_message(Msg::Relayout);
         }
+        self.sync_read_state();
+    }
+
+    fn changed(&mut self, _ctx: &Context<Self>) -> bool {
+        self.card = None;
+        true
     }
 
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        self.set_unread_count(0);
+    }
+
+    // `html!` turns props with no value to pass into bare statements.
+    #[allow(clippy::unnecessary_operation)]
     fn view(&self, ctx: &Context<Self>) -> Html {
         let submit = ctx.link().callback(|_| Msg::SubmitMessage);
         let onkeypress = ctx.link().batch_callback(|e: KeyboardEvent| {
@@ -144,10 +2270,53 @@ impl Component for Chat {
                 None
             }
         });
+        let suggestions = self.suggestions();
+        let selected = self.suggestion.min(suggestions.len().saturating_sub(1));
+        let suggesting = !suggestions.is_empty();
+        let onkeydown = ctx.link().batch_callback(move |e: KeyboardEvent| {
+            let msg = match e.key().as_str() {
+                "Tab" if suggesting => Msg::CompleteCommand(selected),
+                "ArrowDown" if suggesting => Msg::MoveSuggestion(1),
+                "ArrowUp" if suggesting => Msg::MoveSuggestion(-1),
+                "Escape" if suggesting => Msg::DismissSuggestions,
+                _ => return None,
+            };
+            e.prevent_default();
+            Some(msg)
+        });
+        let onscroll = ctx.link().callback(|_| Msg::Scrolled);
+        let onsearch = ctx.link().batch_callback(|e: KeyboardEvent| {
+            if e.key() == "Enter" {
+                Some(Msg::Search)
+            } else {
+                None
+            }
+        });
+        let room_rows = self.room_rows();
 
         html! {
             <div class="flex w-full h-screen bg-gray-100 overflow-hidden">
                 <div class="hidden md:flex flex-col w-80 bg-white shadow-lg">
+                    <div class="p-4 border-b border-gray-200">
+                        <h2 class="text-xs font-semibold uppercase tracking-wide text-gray-500 mb-2">{"Rooms"}</h2>
+                        {
+                            self.rooms.iter().map(|room| {
+                                let active = *room == self.room;
+                                let onclick = {
+                                    let room = room.clone();
+                                    ctx.link().callback(move |_| Msg::SwitchRoom(room.clone()))
+                                };
+                                html! {
+                                    <button
+                                        {onclick}
+                                        class={classes!("block", "w-full", "text-left", "px-2", "py-1", "rounded", "focus:outline-none", if active { "bg-blue-100 text-blue-800 font-semibold" } else { "text-gray-700 hover:bg-gray-50" })}
+                                    >
+                                        {room_label(room)}
+                                    </button>
+                                }
+                            }).collect::<Html>()
+                        }
+                    </div>
                     <div class="p-4 border-b border-gray-200 bg-blue-700 text-white">
                         <h2 class="text-xl font-bold flex items-center">
                             <svg xmlns="http://www.w3.org/2000/svg" class="h-6 w-6 mr-2" fill="none" viewBox="0 0 24 24" stroke="currentColor">
@@ -168,16 +2337,31 @@ impl Component for Chat {
                                     </div>
                                 }
                             } else {
-                                self.users.clone().iter().map(|u| {
+                                self.users.iter().map(|name| {
+                                    let u = self.profile(name);
+                                    let role = self.role_label(name).unwrap_or("Online");
+                                    let onclick = {
+                                        let username = name.clone();
+                                        let history = ctx.link().history();
+                                        Callback::from(move |_| {
+                                            if let Some(history) = &history {
+                                                history.push(Route::Profile { username: username.clone() });
+                                            }
+                                        })
+                                    };
                                     html!{
-                                        <div class="flex items-center p-4 border-b border-gray-100 hover:bg-gray-50 transition-colors duration-150 cursor-pointer">
+                                        <div
+                                            class="flex items-center p-4 border-b border-gray-100 hover:bg-gray-50 transition-colors duration-150 cursor-pointer"
+                                            {onclick}
+                                            oncontextmenu={self.oncontextmenu(ctx, name, None)}
+                                        >
                                             <div class="relative">
                                                 <img class="w-12 h-12 rounded-full object-cover border-2 border-blue-400" src={u.avatar.clone()} alt="avatar"/>
                                                 <div class="absolute bottom-0 right-0 w-3 h-3 bg-blue-400 rounded-full border-2 border-white"></div>
                                             </div>
                                             <div class="ml-4">
                                                 <h3 class="font-semibold">{u.name.clone()}</h3>
-                                                <p class="text-xs text-gray-500">{"Online"}</p>
+                                                <p class="text-xs text-gray-500">{role}</p>
                                             </div>
                                         </div>
                                     }
@@ -187,13 +2371,90 @@ impl Component for Chat {
                     </div>
                 </div>
 
-                <div class="flex flex-col flex-1 bg-white overflow-hidden">
+                <div class="relative flex flex-col flex-1 bg-white overflow-hidden">
+                    if let Some(username) = &ctx.props().profile {
+                        <div class="absolute inset-0 z-20 flex">
+                            <ProfilePage
+                                username={username.clone()}
+                                profile={self.profiles.get(username).cloned().unwrap_or_default()}
+                                role={self.role_label(username)}
+                                online={self.users.contains(username)}
+                                editable={*username == self.username}
+                                on_save={ctx.link().callback(Msg::SaveProfile)}
+                            />
+                        </div>
+                    }
                     <div class="flex items-center px-6 py-3 border-b border-gray-200 shadow-sm">
                         <svg xmlns="http://www.w3.org/2000/svg" class="h-6 w-6 text-blue-600 mr-2" fill="none" viewBox="0 0 24 24" stroke="currentColor">
                             <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M17 8h2a2 2 0 012 2v6a2 2 0 01-2 2h-2v4l-4-4H9a1.994 1.994 0 01-1.414-.586m0 0L11 14h4a2 2 0 002-2V6a2 2 0 00-2-2H5a2 2 0 00-2 2v6a2 2 0 002 2h2v4l.586-.586z" />
                         </svg>
                         <h1 class="text-xl font-bold text-gray-700">{"YewChat"}</h1>
+                        <span class="ml-2 text-gray-400">{room_label(&self.room)}</span>
+                        if let Some(peer) = peer_of(&self.room) {
+                            { self.view_lock(ctx, peer) }
+                        }
+                        if let Some(topic) = self.topics.get(&self.room) {
+                            <span class="ml-3 text-sm text-gray-500 truncate" title={format!("Set by {}", topic.set_by)}>
+                                {topic.topic.clone()}
+                            </span>
+                        }
+                        if self.connection == ConnectionState::Disconnected {
+                            <span class="ml-3 px-2 py-0.5 text-xs font-medium text-red-700 bg-red-100 rounded-full">{"Disconnected"}</span>
+                        }
                         <div class="ml-auto text-sm text-gray-500 flex items-center">
+                            <input
+                                ref={self.search_input.clone()}
+                                type="search"
+                                placeholder="Search  (from: in:# has:image after: before:)"
+                                class="hidden lg:block w-72 mr-4 px-3 py-1 text-sm border border-gray-300 rounded-full focus:outline-none focus:ring-2 focus:ring-blue-500"
+                                onkeypress={onsearch}
+                            />
+                            if self.role() >= Role::Moderator {
+                                <button
+                                    class={classes!("relative", "p-1", "mr-2", "rounded", "hover:bg-gray-100", "focus:outline-none", if self.reports_open { "text-blue-600" } else { "text-gray-400" })}
+                                    title="Reports"
+                                    onclick={ctx.link().callback(|_| Msg::ToggleReports)}
+                                >
+                                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M3 21v-4m0 0V5a2 2 0 012-2h6.5l1 1H21l-3 6 3 6h-8.5l-1-1H5a2 2 0 00-2 2zm9-13.5V9" />
+                                    </svg>
+                                    if !self.reports.is_empty() {
+                                        <span class="absolute -top-1 -right-1 px-1 text-xs leading-4 text-white bg-red-600 rounded-full">
+                                            {self.reports.len().to_string()}
+                                        </span>
+                                    }
+                                </button>
+                                <button
+                                    class={classes!("p-1", "mr-2", "rounded", "hover:bg-gray-100", "focus:outline-none", if self.moderation_log_open { "text-blue-600" } else { "text-gray-400" })}
+                                    title="Moderation log"
+                                    onclick={ctx.link().callback(|_| Msg::ToggleModerationLog)}
+                                >
+                                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M9 12l2 2 4-4m5.618-4.016A11.955 11.955 0 0112 2.944a11.955 11.955 0 01-8.618 3.04A12.02 12.02 0 003 9c0 5.591 3.824 10.29 9 11.622 5.176-1.332 9-6.03 9-11.622 0-1.042-.133-2.052-.382-3.016z" />
+                                    </svg>
+                                </button>
+                                <button
+                                    class={classes!("p-1", "mr-2", "rounded", "hover:bg-gray-100", "focus:outline-none", if self.webhooks_open { "text-blue-600" } else { "text-gray-400" })}
+                                    title="Webhooks for this room"
+                                    onclick={ctx.link().callback(|_| Msg::ToggleWebhooks)}
+                                >
+                                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M13.828 10.172a4 4 0 00-5.656 0l-4 4a4 4 0 105.656 5.656l1.102-1.101m-.758-4.899a4 4 0 005.656 0l4-4a4 4 0 00-5.656-5.656l-1.1 1.1" />
+                                    </svg>
+                                </button>
+                            }
+                            <button
+                                class={classes!("p-1", "mr-2", "rounded", "hover:bg-gray-100", "focus:outline-none", if self.settings_open { "text-blue-600" } else { "text-gray-400" })}
+                                title="Settings"
+                                onclick={ctx.link().callback(|_| Msg::ToggleSettings)}
+                            >
+                                <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                                    <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M10.325 4.317c.426-1.756 2.924-1.756 3.35 0a1.724 1.724 0 002.573 1.066c1.543-.94 3.31.826 2.37 2.37a1.724 1.724 0 001.065 2.572c1.756.426 1.756 2.924 0 3.35a1.724 1.724 0 00-1.066 2.573c.94 1.543-.826 3.31-2.37 2.37a1.724 1.724 0 00-2.572 1.065c-.426 1.756-2.924 1.756-3.35 0a1.724 1.724 0 00-2.573-1.066c-1.543.94-3.31-.826-2.37-2.37a1.724 1.724 0 00-1.065-2.572c-1.756-.426-1.756-2.924 0-3.35a1.724 1.724 0 001.066-2.573c-.94-1.543.826-3.31 2.37-2.37.996.608 2.296.07 2.572-1.065z" />
+                                    <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M15 12a3 3 0 11-6 0 3 3 0 016 0z" />
+                                </svg>
+                            </button>
+                            { self.view_notification_controls(ctx) }
+                            { self.view_latency() }
                             <span class="mr-1">{"Active users:"}</span>
                             <span class="bg-blue-100 text-blue-800 px-2 py-0.5 rounded-full font-medium">
                                 {self.users.len().to_string()}
@@ -201,70 +2462,106 @@ impl Component for Chat {
                         </div>
                     </div>
 
-                    <div class="flex-1 p-6 overflow-y-auto bg-gray-50">
-                        {
-                            if self.messages.is_empty() {
-                                html! {
-                                    <div class="flex flex-col items-center justify-center h-full text-gray-500">
-                                        <svg xmlns="http://www.w3.org/2000/svg" class="h-16 w-16 mb-4 text-gray-300" fill="none" viewBox="0 0 24 24" stroke="currentColor">
-                                            <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M8 12h.01M12 12h.01M16 12h.01M21 12c0 4.418-4.03 8-9 8a9.863 9.863 0 01-4.255-.949L3 20l1.395-3.72C3.512 15.042 3 13.574 3 12c0-4.418 4.03-8 9-8s9 3.582 9 8z" />
-                                        </svg>
-                                        <p class="text-lg font-medium">{"No messages yet"}</p>
-                                        <p class="mt-1">{"Start chatting by typing a message below"}</p>
-                                    </div>
-                                }
-                            } else {
-                                html! {
-                                    <div class="space-y-6">
+                    <div class="relative flex-1 flex flex-col overflow-hidden">
+                        <div ref={self.message_list.clone()} onscroll={onscroll} class="flex-1 p-6 overflow-y-auto bg-gray-50">
+                            {
+                                if room_rows.is_empty() {
+                                    html! {
+                                        <div class="flex flex-col items-center justify-center h-full text-gray-500">
+                                            <svg xmlns="http://www.w3.org/2000/svg" class="h-16 w-16 mb-4 text-gray-300" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                                                <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M8 12h.01M12 12h.01M16 12h.01M21 12c0 4.418-4.03 8-9 8a9.863 9.863 0 01-4.255-.949L3 20l1.395-3.72C3.512 15.042 3 13.574 3 12c0-4.418 4.03-8 9-8s9 3.582 9 8z" />
+                                            </svg>
+                                            <p class="text-lg font-medium">{"No messages yet"}</p>
+                                            <p class="mt-1">{"Start chatting by typing a message below"}</p>
+                                        </div>
+                                    }
+                                } else {
+                                    let keys: Vec<u64> = room_rows.iter().map(Row::key).collect();
+                                    let window = self.window(&keys);
+                                    let read_by_from = room_rows.len().saturating_sub(READ_BY_WINDOW);
+                                    html! {
+                                        <>
+                                        <div style={format!("height: {}px", window.before)}></div>
                                         {
-                                            self.messages.iter().map(|m| {
-                                                let user_found = self.users.iter().find(|u| u.name == m.from);
-                                                let user = user_found.cloned().unwrap_or_else(|| UserProfile {
-                                                            name: m.from.clone(),
-                                                            avatar: format!("https://avatars.dicebear.com/api/identicon/{}.svg", m.from)
-                                                        });
-                                                html!{
-                                                    <div class="flex items-start">
-                                                        <img class="w-10 h-10 rounded-full mr-3 shadow" src={user.avatar.clone()} alt="avatar"/>
-                                                        <div class="flex flex-col max-w-3xl">
-                                                            <div class="flex items-center">
-                                                                <span class="font-semibold text-gray-800">{user.name.clone()}</span>
-                                                                <span class="text-xs text-gray-400 ml-2">{"just now"}</span>
-                                                            </div>
-                                                            <div class={format!("mt-1 p-3 bg-white rounded-lg shadow-sm border-l-4 {}", if m.from == "You" {"border-blue-500"} else {"border-blue-300"} )}>
-                                                                {
-                                                                    if m.message.ends_with(".gif") {
-                                                                        html! {
-                                                                            <div class="mt-1 rounded-md overflow-hidden">
-                                                                                <img class="max-w-full rounded" src={m.message.clone()} alt="GIF"/>
-                                                                            </div>
-                                                                        }
-                                                                    } else {
-                                                                        html! {
-                                                                            <p class="text-gray-700">{m.message.clone()}</p>
-                                                                        }
-                                                                    }
-                                                                }
-                                                            </div>
-                                                        </div>
-                                                    </div>
+                                            (window.start..window.end).map(|i| {
+                                                let prev = match i.checked_sub(1).map(|p| &room_rows[p]) {
+                                                    Some(Row::Message(prev)) => Some(*prev),
+                                                    _ => None,
+                                                };
+                                                match &room_rows[i] {
+                                                    Row::Message(m) => self.view_message(ctx, prev, m, i >= read_by_from),
+                                                    Row::Hidden(ids) => self.view_hidden(ctx, ids),
                                                 }
                                             }).collect::<Html>()
                                         }
-                                    </div>
+                                        <div style={format!("height: {}px", window.after)}></div>
+                                        </>
+                                    }
                                 }
                             }
+                        </div>
+                        if self.unseen_below > 0 {
+                            <button
+                                class="absolute bottom-4 left-1/2 transform -translate-x-1/2 px-4 py-2 bg-blue-600 text-white text-sm font-medium rounded-full shadow-lg hover:bg-blue-700 focus:outline-none"
+                                onclick={ctx.link().callback(|_| Msg::JumpToLatest)}
+                            >
+                                {format!("{} new message{} \u{2014} jump to latest", self.unseen_below, if self.unseen_below == 1 { "" } else { "s" })}
+                            </button>
                         }
                     </div>
 
-                    <div class="p-4 border-t border-gray-200 bg-white">
+                    if let Some(error) = &self.last_error {
+                        <div class="flex items-center px-4 py-2 text-sm text-red-700 bg-red-50 border-t border-red-200">
+                            <span class="flex-1 truncate">{error.clone()}</span>
+                            <button
+                                class="ml-2 text-red-400 hover:text-red-600 focus:outline-none"
+                                onclick={ctx.link().callback(|_| Msg::DismissError)}
+                            >
+                                {"Dismiss"}
+                            </button>
+                        </div>
+                    }
+
+                    if let Some(notice) = &self.send_notice {
+                        <div class="flex items-center px-4 py-2 text-sm text-amber-800 bg-amber-50 border-t border-amber-200">
+                            <span class="flex-1 truncate">{notice.clone()}</span>
+                            <button
+                                class="ml-2 text-amber-500 hover:text-amber-700 focus:outline-none"
+                                onclick={ctx.link().callback(|_| Msg::DismissNotice)}
+                            >
+                                {"Dismiss"}
+                            </button>
+                        </div>
+                    }
+
+                    <div class="relative p-4 border-t border-gray-200 bg-white">
+                        if suggesting {
+                            <ul class="absolute bottom-full left-4 right-4 mb-2 py-1 bg-white border border-gray-200 rounded-lg shadow-lg text-sm overflow-hidden">
+                                {
+                                    suggestions.iter().enumerate().map(|(i, command)| html! {
+                                        <li>
+                                            <button
+                                                class={classes!("flex", "w-full", "px-4", "py-2", "text-left", "focus:outline-none", if i == selected { "bg-blue-50" } else { "hover:bg-gray-50" })}
+                                                onclick={ctx.link().callback(move |_| Msg::CompleteCommand(i))}
+                                            >
+                                                <span class="font-mono text-gray-800">{command.usage()}</span>
+                                                <span class="ml-4 text-gray-500 truncate">{command.help}</span>
+                                            </button>
+                                        </li>
+                                    }).collect::<Html>()
+                                }
+                            </ul>
+                        }
                         <div class="flex rounded-lg border border-gray-300 overflow-hidden shadow-sm focus-within:ring-2 focus-within:ring-blue-500 focus-within:border-blue-500">
                             <input 
                                 ref={self.chat_input.clone()} 
                                 type="text" 
-                                placeholder="Type your message..." 
-                                class="flex-1 px-4 py-3 focus:outline-none" 
+                                disabled={self.muted.is_some()}
+                                placeholder={self.muted_notice().unwrap_or_else(|| "Type your message...".to_string())}
+                                class="flex-1 px-4 py-3 focus:outline-none disabled:bg-gray-100 disabled:cursor-not-allowed" 
                                 onkeypress={onkeypress}
+                                onkeydown={onkeydown}
+                                oninput={ctx.link().callback(|_| Msg::DraftChanged)}
                             />
                             <div class="flex items-center px-2 bg-gray-50 border-l border-gray-300">
                                 <button 
@@ -277,6 +2574,7 @@ impl Component for Chat {
                                 </button>
                                 <button 
                                     onclick={submit} 
+                                    disabled={self.muted.is_some()}
                                     class="ml-2 px-4 py-2 bg-blue-600 text-white font-medium rounded hover:bg-blue-700 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-blue-500 transition-colors duration-150 flex items-center"
                                 >
                                     <span class="mr-1">{"Send"}</span>
@@ -287,12 +2585,221 @@ impl Component for Chat {
                             </div>
                         </div>
                         <div class="flex items-center justify-between text-xs text-gray-500 mt-2 px-2">
-                            <div>{"Type @username to mention a user"}</div>
-                            <div>{"Enter to send, Shift+Enter for new line"}</div>
+                            if let Some(typing) = typing_summary(&self.typing_here()) {
+                                <div class="italic">{typing}</div>
+                            } else {
+                                <div>{"Type @username to mention a user"}</div>
+                            }
+                            <div>{"Enter to send, / for commands"}</div>
                         </div>
                     </div>
                 </div>
+
+                if let Some(search) = &self.search {
+                    { self.view_search_results(ctx, search) }
+                }
+                if self.webhooks_open {
+                    <Webhooks
+                        room={self.room.clone()}
+                        hooks={self.webhooks.get(&self.room).cloned()}
+                        on_create={ctx.link().callback(Msg::CreateWebhook)}
+                        on_revoke={ctx.link().callback(Msg::RevokeWebhook)}
+                        on_close={ctx.link().callback(|_| Msg::ToggleWebhooks)}
+                    />
+                }
+                if self.moderation_log_open {
+                    <ModerationLog
+                        entries={self.moderation_log.clone()}
+                        on_close={ctx.link().callback(|_| Msg::ToggleModerationLog)}
+                    />
+                }
+                if self.reports_open {
+                    <Reports
+                        reports={self.reports.clone()}
+                        on_close_report={ctx.link().callback(|(report_id, dismissed)| Msg::CloseReport { report_id, dismissed })}
+                        on_remove={ctx.link().callback(|m: MessageData| Msg::Moderate(ModAction::Remove { message_id: m.id, user: m.from }))}
+                        on_close={ctx.link().callback(|_| Msg::ToggleReports)}
+                    />
+                }
+                if let Some(message) = &self.reporting {
+                    <ReportDialog
+                        message={message.clone()}
+                        on_submit={ctx.link().callback(Msg::SubmitReport)}
+                        on_close={ctx.link().callback(|_| Msg::CancelReport)}
+                    />
+                }
+                if let (true, Some(peer)) = (self.encryption_open, peer_of(&self.room)) {
+                    <Encryption
+                        peer={peer.to_string()}
+                        own_fingerprint={self.identity.as_ref().and_then(|i| self.fingerprints.get(&i.public_key)).cloned()}
+                        peer_fingerprint={self.public_keys.get(peer).and_then(|key| self.fingerprints.get(key)).cloned()}
+                        trust={self.peer_trust(peer)}
+                        on_verify={ctx.link().callback(|_| Msg::VerifyKey)}
+                        on_close={ctx.link().callback(|_| Msg::ToggleEncryption)}
+                    />
+                }
+                if self.settings_open {
+                    <Settings
+                        ignored={self.ignored.iter().cloned().collect::<Vec<_>>()}
+                        on_ignore={ctx.link().callback(Msg::Ignore)}
+                        on_unignore={ctx.link().callback(Msg::Unignore)}
+                        room={self.room.clone()}
+                        content_filter={self.content_filter.clone()}
+                        on_content_filter={ctx.link().callback(Msg::SetContentFilter)}
+                        on_close={ctx.link().callback(|_| Msg::ToggleSettings)}
+                    />
+                }
+                if let Some(menu) = &self.menu {
+                    { self.view_menu(ctx, menu) }
+                }
+                if let Some(card) = &self.card {
+                    <ProfileCard
+                        username={card.user.clone()}
+                        profile={self.profiles.get(&card.user).cloned().unwrap_or_default()}
+                        online={self.users.contains(&card.user)}
+                        x={card.x}
+                        y={card.y}
+                        on_close={ctx.link().callback(|_| Msg::CloseCard)}
+                    />
+                }
             </div>
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: u64, from: &str, minute: f64) -> MessageData {
+        MessageData {
+            id,
+            room: DEFAULT_ROOM.to_string(),
+            from: from.to_string(),
+            to: None,
+            message: format!("message {}", id),
+            timestamp: minute * 60.0 * 1000.0,
+            bot: None,
+            attachments: vec![],
+            sealed: None,
+        }
+    }
+
+    fn group_starts(messages: &[MessageData]) -> Vec<u64> {
+        messages
+            .iter()
+            .enumerate()
+            .filter(|(i, m)| starts_group(i.checked_sub(1).map(|p| &messages[p]), m))
+            .map(|(_, m)| m.id)
+            .collect()
+    }
+
+    #[test]
+    fn first_message_starts_a_group() {
+        assert_eq!(group_starts(&[message(1, "alice", 0.0)]), vec![1]);
+    }
+
+    #[test]
+    fn consecutive_messages_from_one_sender_are_grouped() {
+        let messages = [
+            message(1, "alice", 0.0),
+            message(2, "alice", 1.0),
+            message(3, "bob", 1.5),
+            message(4, "bob", 2.0),
+            message(5, "alice", 2.5),
+        ];
+        assert_eq!(group_starts(&messages), vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn long_pauses_split_a_group() {
+        let messages = [
+            message(1, "alice", 0.0),
+            message(2, "alice", 4.0),
+            message(3, "alice", 10.0),
+        ];
+        assert_eq!(group_starts(&messages), vec![1, 3]);
+    }
+
+    #[test]
+    fn room_changes_split_a_group() {
+        let mut other_room = message(2, "alice", 0.5);
+        other_room.room = "random".to_string();
+        let messages = [message(1, "alice", 0.0), other_room];
+        assert_eq!(group_starts(&messages), vec![1, 2]);
+    }
+
+    #[test]
+    fn folds_runs_of_hidden_messages() {
+        let messages = [
+            message(1, "alice", 0.0),
+            message(2, "mallory", 0.1),
+            message(3, "mallory", 0.2),
+            message(4, "alice", 0.3),
+            message(5, "mallory", 0.4),
+        ];
+        let refs: Vec<&MessageData> = messages.iter().collect();
+        let rows = rows(&refs, |m| m.from == "mallory");
+        assert_eq!(
+            rows,
+            vec![
+                Row::Message(&messages[0]),
+                Row::Hidden(vec![2, 3]),
+                Row::Message(&messages[3]),
+                Row::Hidden(vec![5]),
+            ]
+        );
+        assert_eq!(rows.iter().map(Row::key).collect::<Vec<_>>(), vec![1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn offers_moderation_to_those_who_outrank() {
+        let roles = BTreeMap::from([
+            ("alice".to_string(), Role::Owner),
+            ("bob".to_string(), Role::Moderator),
+        ]);
+        let labels = |me, user, message| {
+            moderation_actions(me, &roles, user, message)
+                .into_iter()
+                .map(|(label, _)| label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels("carol", "carol", Some(1)), vec!["Remove message"]);
+        assert!(labels("carol", "bob", Some(1)).is_empty());
+        assert!(labels("bob", "alice", None).is_empty());
+        assert_eq!(
+            labels("bob", "carol", Some(1)),
+            vec!["Remove message", "Mute for 10 minutes", "Mute for an hour", "Unmute", "Kick", "Ban"]
+        );
+        assert_eq!(labels("alice", "bob", None).last(), Some(&"Make member"));
+        assert_eq!(labels("alice", "carol", None).last(), Some(&"Make moderator"));
+    }
+
+    #[test]
+    fn explains_why_we_were_kicked() {
+        let mut sanction = Sanction {
+            by: "alice".into(),
+            reason: String::new(),
+            until: None,
+            banned: false,
+        };
+        assert_eq!(kicked_notice(&sanction), "You were kicked out by alice.");
+        sanction.banned = true;
+        sanction.reason = "spam".into();
+        assert_eq!(kicked_notice(&sanction), "You were banned by alice: spam");
+    }
+
+    #[test]
+    fn summarises_who_is_typing() {
+        assert_eq!(typing_summary(&[]), None);
+        assert_eq!(typing_summary(&["bob"]).unwrap(), "bob is typing\u{2026}");
+        assert_eq!(
+            typing_summary(&["alice", "bob"]).unwrap(),
+            "alice and bob are typing\u{2026}"
+        );
+        assert_eq!(
+            typing_summary(&["alice", "bob", "carol"]).unwrap(),
+            "Several people are typing\u{2026}"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::crypto::Trust;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub peer: String,
+    pub own_fingerprint: Option<String>,
+    pub peer_fingerprint: Option<String>,
+    /// `None` while the peer has no key published, like when they're offline.
+    pub trust: Option<Trust>,
+    /// Called to mark the peer's current key as checked.
+    pub on_verify: Callback<()>,
+    pub on_close: Callback<()>,
+}
+
+/// How a direct conversation is encrypted, with the fingerprints to compare.
+#[function_component(Encryption)]
+pub fn encryption(props: &Props) -> Html {
+    let on_verify = props.on_verify.reform(|_| ());
+    let on_close = props.on_close.reform(|_| ());
+    let fingerprint = |label: String, fingerprint: &Option<String>| {
+        html! {
+            <div class="mt-3">
+                <p class="text-xs font-medium text-gray-500">{label}</p>
+                <p class="mt-1 font-mono text-sm text-gray-800">
+                    {fingerprint.clone().unwrap_or_else(|| "\u{2014}".to_string())}
+                </p>
+            </div>
+        }
+    };
+
+    html! {
+        <div class="fixed inset-0 z-30 flex items-center justify-center bg-black bg-opacity-30">
+            <div class="w-full max-w-md bg-white rounded-lg shadow-xl">
+                <div class="flex items-center px-6 py-4 border-b border-gray-200">
+                    <h2 class="text-lg font-semibold text-gray-800">{format!("Encryption with {}", props.peer)}</h2>
+                    <button
+                        class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                        title="Close"
+                        onclick={on_close}
+                    >
+                        <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                            <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                        </svg>
+                    </button>
+                </div>
+                <div class="px-6 py-4 text-sm text-gray-600">
+                    <p>
+                        {format!("Messages here are encrypted in your browser and only you and {} can read them; the server just passes them on. ", props.peer)}
+                        {"To be sure nobody is in between, compare these fingerprints with them somewhere other than this chat."}
+                    </p>
+                    if props.trust == Some(Trust::Changed) {
+                        <p class="mt-3 p-2 text-red-700 bg-red-50 rounded">
+                            {format!("{}'s key has changed since you last talked. That happens when they sign in from another browser, but it could also mean someone is intercepting your messages.", props.peer)}
+                        </p>
+                    }
+                    if props.trust.is_none() {
+                        <p class="mt-3 p-2 text-gray-700 bg-gray-50 rounded">
+                            {format!("{} isn't online, so there's no key to send to.", props.peer)}
+                        </p>
+                    }
+                    { fingerprint("Your fingerprint".to_string(), &props.own_fingerprint) }
+                    { fingerprint(format!("{}'s fingerprint", props.peer), &props.peer_fingerprint) }
+                </div>
+                <div class="flex items-center justify-end px-6 py-4 border-t border-gray-200">
+                    if props.trust == Some(Trust::Verified) {
+                        <span class="text-sm text-green-700">{"Verified"}</span>
+                    } else if props.trust.is_some() {
+                        <button
+                            class="px-4 py-2 text-sm text-white bg-blue-600 rounded hover:bg-blue-700 focus:outline-none"
+                            onclick={on_verify}
+                        >
+                            {"They match, mark as verified"}
+                        </button>
+                    }
+                </div>
+            </div>
+        </div>
+    }
+}
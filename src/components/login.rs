@@ -8,7 +8,7 @@ use crate::User;
 
 #[function_component(Login)]
 pub fn login() -> Html {
-    let username = use_state(|| String::new());
+    let username = use_state(String::new);
     let user = use_context::<User>().expect("No context found.");
 
     let oninput = {
@@ -23,7 +23,27 @@ pub fn login() -> Html {
     let onclick = {
         let username = username.clone();
         let user = user.clone();
-        Callback::from(move |_| *user.username.borrow_mut() = (*username).clone())
+        Callback::from(move |_| {
+            *user.username.borrow_mut() = (*username).clone();
+            user.signed_out.borrow_mut().take();
+        })
+    };
+    let signed_out = user.signed_out.borrow().clone();
+    // `html!` turns the props of nested components into bare statements.
+    #[allow(clippy::unnecessary_operation)]
+    let start = html! {
+        <Link<Route> to={Route::Chat} classes="w-full block"> 
+            <button 
+                {onclick} 
+                disabled={username.is_empty()} 
+                class="w-full flex justify-center py-3 px-4 rounded-lg bg-gradient-to-r from-blue-500 to-blue-700 text-white font-medium shadow-lg hover:from-blue-600 hover:to-blue-800 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-blue-500 transition-all duration-200 disabled:opacity-50"
+            >
+                <span>{"Start Chatting!"}</span>
+                <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5 ml-2" viewBox="0 0 20 20" fill="currentColor">
+                    <path fill-rule="evenodd" d="M10.293 5.293a1 1 0 011.414 0l4 4a1 1 0 010 1.414l-4 4a1 1 0 01-1.414-1.414L12.586 11H5a1 1 0 110-2h7.586l-2.293-2.293a1 1 0 010-1.414z" clip-rule="evenodd" />
+                </svg>
+            </button>
+        </Link<Route>>
     };
 
     html! {
@@ -41,6 +61,12 @@ pub fn login() -> Html {
                         <p class="text-gray-500 mt-2">{"Connect with friends in real-time"}</p>
                     </div>
                     
+                    if let Some(reason) = signed_out {
+                        <div class="mb-6 px-4 py-3 text-sm text-red-700 bg-red-50 border border-red-200 rounded-lg">
+                            {reason}
+                        </div>
+                    }
+
                     <div class="mt-6">
                         <label class="block text-sm font-medium text-gray-700 mb-2">{"Username"}</label>
                         <div class="relative">
@@ -58,18 +84,7 @@ pub fn login() -> Html {
                     </div>
                     
                     <div class="mt-8">
-                        <Link<Route> to={Route::Chat} classes="w-full block"> 
-                            <button 
-                                {onclick} 
-                                disabled={username.len()<1} 
-                                class="w-full flex justify-center py-3 px-4 rounded-lg bg-gradient-to-r from-blue-500 to-blue-700 text-white font-medium shadow-lg hover:from-blue-600 hover:to-blue-800 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-blue-500 transition-all duration-200 disabled:opacity-50"
-                            >
-                                <span>{"Start Chatting!"}</span>
-                                <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5 ml-2" viewBox="0 0 20 20" fill="currentColor">
-                                    <path fill-rule="evenodd" d="M10.293 5.293a1 1 0 011.414 0l4 4a1 1 0 010 1.414l-4 4a1 1 0 01-1.414-1.414L12.586 11H5a1 1 0 110-2h7.586l-2.293-2.293a1 1 0 010-1.414z" clip-rule="evenodd" />
-                                </svg>
-                            </button>
-                        </Link<Route>>
+                        {start}
                     </div>
                     
                     <p class="text-center text-sm text-gray-500 mt-6">
@@ -1,2 +1,10 @@
 pub mod chat;
+pub mod encryption;
 pub mod login;
+pub mod moderation_log;
+pub mod profile;
+pub mod profile_card;
+pub mod report_dialog;
+pub mod reports;
+pub mod settings;
+pub mod webhooks;
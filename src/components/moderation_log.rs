@@ -0,0 +1,59 @@
+use wasm_bindgen::JsValue;
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::protocol::ModerationEntry;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    /// Oldest first, as the server numbers them.
+    pub entries: Vec<ModerationEntry>,
+    pub on_close: Callback<()>,
+}
+
+/// Side panel listing what moderators have done, newest first. Only
+/// moderators and the owner get to see it.
+#[function_component(ModerationLog)]
+pub fn moderation_log(props: &Props) -> Html {
+    let on_close = props.on_close.reform(|_| ());
+
+    html! {
+        <div class="hidden lg:flex flex-col w-96 bg-white border-l border-gray-200">
+            <div class="flex items-center px-4 py-3 border-b border-gray-200">
+                <h2 class="font-semibold text-gray-700">{"Moderation log"}</h2>
+                <button
+                    class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                    title="Close moderation log"
+                    onclick={on_close}
+                >
+                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                    </svg>
+                </button>
+            </div>
+            <div class="flex-1 overflow-y-auto">
+                if props.entries.is_empty() {
+                    <p class="p-4 text-sm text-gray-500">{"Nobody has been moderated yet"}</p>
+                }
+                {
+                    props.entries.iter().rev().map(|entry| {
+                        let date = js_sys::Date::new(&entry.timestamp.into())
+                            .to_locale_string("default", &JsValue::UNDEFINED);
+                        html! {
+                            <div key={entry.id} class="p-4 border-b border-gray-100">
+                                <div class="flex items-center text-sm">
+                                    <span class="font-semibold text-gray-800">{entry.by.clone()}</span>
+                                    <span class="ml-1 text-gray-700">{entry.moderation.action.describe()}</span>
+                                </div>
+                                if !entry.moderation.reason.is_empty() {
+                                    <p class="mt-1 text-sm text-gray-500 italic">{entry.moderation.reason.clone()}</p>
+                                }
+                                <div class="mt-1 text-xs text-gray-400">{String::from(date)}</div>
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,268 @@
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{HtmlInputElement, HtmlTextAreaElement};
+use yew::functional::*;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::services::avatar;
+use crate::services::profiles::{self, avatar_url};
+use crate::services::protocol::{AvatarStyle, Profile};
+use crate::Route;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub username: String,
+    pub profile: Profile,
+    /// "Owner", "Moderator" or `None` for members.
+    pub role: Option<&'static str>,
+    pub online: bool,
+    /// Whether it's the user's own, which they can change.
+    pub editable: bool,
+    pub on_save: Callback<Profile>,
+}
+
+/// Someone's profile in full, in place of the messages.
+#[function_component(ProfilePage)]
+pub fn profile_page(props: &Props) -> Html {
+    let profile = &props.profile;
+    let name = profiles::display_name(&props.username, Some(profile));
+    let local_time = profiles::local_time(&profile.time_zone);
+    // `html!` turns the props of nested components into bare statements.
+    #[allow(clippy::unnecessary_operation)]
+    let back = html! {
+        <Link<Route> to={Route::Chat} classes="text-sm text-blue-600 hover:underline">
+            {"\u{2190} Back to the chat"}
+        </Link<Route>>
+    };
+    #[allow(clippy::unnecessary_operation)]
+    let form = match props.editable {
+        true => html! {
+            <ProfileForm username={props.username.clone()} profile={profile.clone()} on_save={props.on_save.clone()}/>
+        },
+        false => html! {},
+    };
+
+    html! {
+        <div class="flex-1 overflow-y-auto bg-gray-50">
+            <div class="max-w-2xl mx-auto p-6">
+                {back}
+                <div class="flex items-center mt-4 p-6 bg-white rounded-lg shadow-sm">
+                    <img class="w-24 h-24 rounded-full shadow" src={avatar_url(&props.username, Some(profile))} alt="avatar"/>
+                    <div class="ml-6 min-w-0">
+                        <h2 class="text-2xl font-bold text-gray-800 truncate">{name}</h2>
+                        <p class="text-sm text-gray-500">
+                            {props.username.clone()}
+                            if !profile.pronouns.is_empty() {
+                                {format!(" \u{b7} {}", profile.pronouns)}
+                            }
+                        </p>
+                        <p class="mt-2 text-xs text-gray-500">
+                            {if props.online { "Online" } else { "Offline" }}
+                            if let Some(role) = props.role {
+                                {format!(" \u{b7} {}", role)}
+                            }
+                            if let Some(time) = local_time {
+                                {format!(" \u{b7} {} local time", time)}
+                            }
+                        </p>
+                    </div>
+                </div>
+                if !profile.bio.is_empty() {
+                    <p class="mt-4 p-6 bg-white rounded-lg shadow-sm text-gray-700 whitespace-pre-line">{profile.bio.clone()}</p>
+                }
+                {form}
+            </div>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct FormProps {
+    username: String,
+    profile: Profile,
+    on_save: Callback<Profile>,
+}
+
+#[function_component(ProfileForm)]
+fn profile_form(props: &FormProps) -> Html {
+    let draft = use_state(|| props.profile.clone());
+    let error = use_state(|| None::<String>);
+    {
+        // Start over from what was saved whenever that changes.
+        let draft = draft.clone();
+        use_effect_with_deps(
+            move |profile: &Profile| {
+                draft.set(profile.clone());
+                || ()
+            },
+            props.profile.clone(),
+        );
+    }
+
+    let edit = |apply: fn(&mut Profile, String)| {
+        let draft = draft.clone();
+        Callback::from(move |e: InputEvent| {
+            let value = match e.target_dyn_into::<HtmlTextAreaElement>() {
+                Some(area) => area.value(),
+                None => e.target_unchecked_into::<HtmlInputElement>().value(),
+            };
+            let mut profile = (*draft).clone();
+            apply(&mut profile, value);
+            draft.set(profile);
+        })
+    };
+    let upload = {
+        let draft = draft.clone();
+        let error = error.clone();
+        Callback::from(move |e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let file = match input.files().and_then(|files| files.get(0)) {
+                Some(file) => file,
+                None => return,
+            };
+            input.set_value("");
+            let draft = draft.clone();
+            let error = error.clone();
+            spawn_local(async move {
+                match avatar::shrink(&file).await {
+                    Ok(image) => {
+                        let mut profile = (*draft).clone();
+                        profile.image = image;
+                        draft.set(profile);
+                        error.set(None);
+                    }
+                    Err(e) => error.set(Some(e)),
+                }
+            });
+        })
+    };
+    let use_device_zone = {
+        let draft = draft.clone();
+        Callback::from(move |_| {
+            let mut profile = (*draft).clone();
+            profile.time_zone = profiles::device_time_zone().unwrap_or_default();
+            draft.set(profile);
+        })
+    };
+    let save = {
+        let draft = draft.clone();
+        let error = error.clone();
+        let on_save = props.on_save.clone();
+        Callback::from(move |_| match draft.validate() {
+            Ok(()) => {
+                error.set(None);
+                on_save.emit((*draft).clone());
+            }
+            Err(e) => error.set(Some(e)),
+        })
+    };
+    let input = "w-full mt-1 px-3 py-2 text-sm border border-gray-300 rounded focus:outline-none focus:ring-2 focus:ring-blue-500";
+    let label = "block mt-4 text-sm font-medium text-gray-700";
+    let unknown_zone =
+        !draft.time_zone.is_empty() && profiles::local_time(&draft.time_zone).is_none();
+    let name = profiles::display_name(&props.username, Some(&draft));
+    let choice = |selected: bool| {
+        classes!(
+            "p-1",
+            "rounded-full",
+            "border-2",
+            "focus:outline-none",
+            if selected {
+                "border-blue-500"
+            } else {
+                "border-transparent"
+            }
+        )
+    };
+
+    html! {
+        <div class="mt-4 p-6 bg-white rounded-lg shadow-sm">
+            <h3 class="font-semibold text-gray-700">{"Edit your profile"}</h3>
+            <label class={label}>{"Display name"}</label>
+            <input
+                class={input}
+                maxlength={Profile::DISPLAY_NAME_MAX.to_string()}
+                value={draft.display_name.clone()}
+                oninput={edit(|p, v| p.display_name = v)}
+            />
+            <label class={label}>{"Avatar"}</label>
+            <div class="flex items-center mt-1 space-x-2">
+                {
+                    AvatarStyle::ALL.iter().map(|&style| {
+                        let selected = draft.image.is_empty() && draft.avatar == style;
+                        let onclick = {
+                            let draft = draft.clone();
+                            Callback::from(move |_| {
+                                let mut profile = (*draft).clone();
+                                profile.avatar = style;
+                                profile.image.clear();
+                                draft.set(profile);
+                            })
+                        };
+                        html! {
+                            <button class={choice(selected)} {onclick}>
+                                <img class="w-12 h-12 rounded-full" src={avatar::drawn(&props.username, &name, style)} alt={format!("{:?}", style)}/>
+                            </button>
+                        }
+                    }).collect::<Html>()
+                }
+                if !draft.image.is_empty() {
+                    <span class={choice(true)}>
+                        <img class="w-12 h-12 rounded-full object-cover" src={draft.image.clone()} alt="Uploaded"/>
+                    </span>
+                }
+                <label class="ml-2 px-3 py-2 text-sm text-gray-700 border border-gray-300 rounded cursor-pointer hover:bg-gray-50">
+                    {"Upload\u{2026}"}
+                    <input class="hidden" type="file" accept="image/*" onchange={upload}/>
+                </label>
+            </div>
+            <label class={label}>{"Pronouns"}</label>
+            <input
+                class={input}
+                maxlength={Profile::PRONOUNS_MAX.to_string()}
+                placeholder="they/them"
+                value={draft.pronouns.clone()}
+                oninput={edit(|p, v| p.pronouns = v)}
+            />
+            <label class={label}>{"Bio"}</label>
+            <textarea
+                class={input}
+                rows="4"
+                maxlength={Profile::BIO_MAX.to_string()}
+                value={draft.bio.clone()}
+                oninput={edit(|p, v| p.bio = v)}
+            />
+            <label class={label}>{"Time zone"}</label>
+            <div class="flex items-center">
+                <input
+                    class={input}
+                    maxlength={Profile::TIME_ZONE_MAX.to_string()}
+                    placeholder="Asia/Jakarta"
+                    value={draft.time_zone.clone()}
+                    oninput={edit(|p, v| p.time_zone = v.trim().to_string())}
+                />
+                <button
+                    class="flex-shrink-0 mt-1 ml-2 px-3 py-2 text-sm text-gray-700 border border-gray-300 rounded hover:bg-gray-50 focus:outline-none"
+                    onclick={use_device_zone}
+                >
+                    {"Use this device's"}
+                </button>
+            </div>
+            if unknown_zone {
+                <p class="mt-1 text-xs text-yellow-700">{"This browser doesn't know that time zone, so nobody will see your local time."}</p>
+            }
+            if let Some(e) = &*error {
+                <p class="mt-4 text-sm text-red-600">{e.clone()}</p>
+            }
+            <div class="flex justify-end mt-6">
+                <button
+                    class="px-4 py-2 text-sm text-white bg-blue-600 rounded hover:bg-blue-700 focus:outline-none disabled:opacity-50"
+                    disabled={*draft == props.profile}
+                    onclick={save}
+                >
+                    {"Save"}
+                </button>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,77 @@
+use yew::functional::*;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::services::profiles::{self, avatar_url};
+use crate::services::protocol::Profile;
+use crate::Route;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub username: String,
+    pub profile: Profile,
+    pub online: bool,
+    /// Where it was opened, in client coordinates.
+    pub x: i32,
+    pub y: i32,
+    pub on_close: Callback<()>,
+}
+
+/// A glance at someone's profile, opened from their name or avatar in the
+/// message list.
+#[function_component(ProfileCard)]
+pub fn profile_card(props: &Props) -> Html {
+    let history = use_history();
+    let profile = &props.profile;
+    let on_view = {
+        let username = props.username.clone();
+        let on_close = props.on_close.clone();
+        Callback::from(move |_| {
+            on_close.emit(());
+            if let Some(history) = &history {
+                history.push(Route::Profile {
+                    username: username.clone(),
+                });
+            }
+        })
+    };
+    let close = props.on_close.reform(|_| ());
+
+    html! {
+        <>
+            <div class="fixed inset-0 z-40" onclick={close}></div>
+            <div
+                class="fixed z-50 w-72 p-4 bg-white border border-gray-200 rounded-lg shadow-lg text-sm"
+                style={format!("left: {}px; top: {}px", props.x, props.y)}
+            >
+                <div class="flex items-center">
+                    <img class="w-12 h-12 rounded-full shadow" src={avatar_url(&props.username, Some(profile))} alt="avatar"/>
+                    <div class="ml-3 min-w-0">
+                        <p class="font-semibold text-gray-800 truncate">{profiles::display_name(&props.username, Some(profile))}</p>
+                        <p class="text-xs text-gray-500 truncate">
+                            {props.username.clone()}
+                            if !profile.pronouns.is_empty() {
+                                {format!(" \u{b7} {}", profile.pronouns)}
+                            }
+                        </p>
+                    </div>
+                    if props.online {
+                        <span class="ml-auto w-2 h-2 bg-green-500 rounded-full flex-shrink-0" title="Online"></span>
+                    }
+                </div>
+                if !profile.bio.is_empty() {
+                    <p class="mt-3 text-gray-700 whitespace-pre-line line-clamp-3">{profile.bio.clone()}</p>
+                }
+                if let Some(time) = profiles::local_time(&profile.time_zone) {
+                    <p class="mt-2 text-xs text-gray-500">{format!("{} local time", time)}</p>
+                }
+                <button
+                    class="w-full mt-3 px-3 py-1 text-blue-600 border border-blue-200 rounded hover:bg-blue-50 focus:outline-none"
+                    onclick={on_view}
+                >
+                    {"View profile"}
+                </button>
+            </div>
+        </>
+    }
+}
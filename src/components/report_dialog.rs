@@ -0,0 +1,99 @@
+use web_sys::HtmlInputElement;
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::protocol::{MessageData, ReportCategory, ReportRequest};
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub message: MessageData,
+    pub on_submit: Callback<ReportRequest>,
+    pub on_close: Callback<()>,
+}
+
+/// Asks why a message is being reported before it goes to the moderators.
+#[function_component(ReportDialog)]
+pub fn report_dialog(props: &Props) -> Html {
+    let category = use_state(|| None::<ReportCategory>);
+    let note_input = use_node_ref();
+
+    let onsubmit = {
+        let category = category.clone();
+        let note_input = note_input.clone();
+        let on_submit = props.on_submit.clone();
+        let message_id = props.message.id;
+        Callback::from(move |_| {
+            let category = match *category {
+                Some(category) => category,
+                None => return,
+            };
+            let note = note_input
+                .cast::<HtmlInputElement>()
+                .map(|input| input.value())
+                .unwrap_or_default();
+            on_submit.emit(ReportRequest {
+                message_id,
+                category,
+                note,
+            });
+        })
+    };
+    let on_close = props.on_close.reform(|_| ());
+
+    html! {
+        <div class="fixed inset-0 z-30 flex items-center justify-center bg-black bg-opacity-30">
+            <div class="w-full max-w-md bg-white rounded-lg shadow-xl">
+                <div class="px-6 py-4 border-b border-gray-200">
+                    <h2 class="text-lg font-semibold text-gray-800">{"Report message"}</h2>
+                    <p class="mt-2 p-2 text-sm text-gray-600 bg-gray-50 rounded truncate">
+                        <span class="font-semibold">{format!("{}: ", props.message.from)}</span>
+                        {props.message.message.clone()}
+                    </p>
+                </div>
+                <div class="px-6 py-4">
+                    <p class="text-sm font-medium text-gray-700">{"What's wrong with it?"}</p>
+                    <div class="grid grid-cols-2 gap-2 mt-2">
+                        {
+                            ReportCategory::ALL.iter().map(|&option| {
+                                let selected = *category == Some(option);
+                                let onclick = {
+                                    let category = category.clone();
+                                    Callback::from(move |_| category.set(Some(option)))
+                                };
+                                html! {
+                                    <button
+                                        class={classes!("px-3", "py-2", "text-sm", "border", "rounded", "focus:outline-none", if selected { "border-blue-500 bg-blue-50 text-blue-800" } else { "border-gray-300 text-gray-700 hover:bg-gray-50" })}
+                                        {onclick}
+                                    >
+                                        {option.label()}
+                                    </button>
+                                }
+                            }).collect::<Html>()
+                        }
+                    </div>
+                    <input
+                        ref={note_input}
+                        type="text"
+                        placeholder="Anything the moderators should know (optional)"
+                        class="w-full mt-3 px-3 py-2 text-sm border border-gray-300 rounded focus:outline-none focus:ring-2 focus:ring-blue-500"
+                    />
+                </div>
+                <div class="flex justify-end px-6 py-4 border-t border-gray-200">
+                    <button
+                        class="px-4 py-2 text-sm text-gray-600 rounded hover:bg-gray-100 focus:outline-none"
+                        onclick={on_close}
+                    >
+                        {"Cancel"}
+                    </button>
+                    <button
+                        class="ml-2 px-4 py-2 text-sm text-white bg-red-600 rounded hover:bg-red-700 focus:outline-none disabled:opacity-50"
+                        disabled={category.is_none()}
+                        onclick={onsubmit}
+                    >
+                        {"Report"}
+                    </button>
+                </div>
+            </div>
+        </div>
+    }
+}
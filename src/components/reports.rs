@@ -0,0 +1,90 @@
+use wasm_bindgen::JsValue;
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::protocol::{MessageData, Report};
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub reports: Vec<Report>,
+    /// Called with a report's id and whether it was dismissed rather than
+    /// dealt with.
+    pub on_close_report: Callback<(u64, bool)>,
+    /// Called with a reported message.
+    pub on_remove: Callback<MessageData>,
+    pub on_close: Callback<()>,
+}
+
+/// The moderators' inbox: open reports, oldest first, each with the messages
+/// leading up to the reported one.
+#[function_component(Reports)]
+pub fn reports(props: &Props) -> Html {
+    let on_close = props.on_close.reform(|_| ());
+
+    html! {
+        <div class="hidden lg:flex flex-col w-96 bg-white border-l border-gray-200">
+            <div class="flex items-center px-4 py-3 border-b border-gray-200">
+                <h2 class="font-semibold text-gray-700">{"Reports"}</h2>
+                <span class="ml-2 text-xs text-gray-400">{props.reports.len().to_string()}</span>
+                <button
+                    class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                    title="Close reports"
+                    onclick={on_close}
+                >
+                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                    </svg>
+                </button>
+            </div>
+            <div class="flex-1 overflow-y-auto">
+                if props.reports.is_empty() {
+                    <p class="p-4 text-sm text-gray-500">{"No open reports"}</p>
+                }
+                {
+                    props.reports.iter().map(|report| {
+                        let date = js_sys::Date::new(&report.timestamp.into())
+                            .to_locale_string("default", &JsValue::UNDEFINED);
+                        let id = report.id;
+                        let resolve = props.on_close_report.reform(move |_| (id, false));
+                        let dismiss = props.on_close_report.reform(move |_| (id, true));
+                        let remove = {
+                            let message = report.message.clone();
+                            props.on_remove.reform(move |_| message.clone())
+                        };
+                        html! {
+                            <div key={report.id} class="p-4 border-b border-gray-100">
+                                <div class="flex items-center text-xs text-gray-500">
+                                    <span class="px-1.5 py-0.5 font-semibold text-red-700 bg-red-100 rounded">{report.category.label()}</span>
+                                    <span class="ml-2">{format!("by {}", report.reporter)}</span>
+                                    <span class="ml-auto">{String::from(date)}</span>
+                                </div>
+                                if !report.note.is_empty() {
+                                    <p class="mt-2 text-sm text-gray-600 italic">{report.note.clone()}</p>
+                                }
+                                <div class="mt-2 p-2 text-sm bg-gray-50 rounded">
+                                    <div class="mb-1 text-xs text-gray-400">{format!("# {}", report.message.room)}</div>
+                                    { report.context.iter().map(|m| view_line(m, false)).collect::<Html>() }
+                                    { view_line(&report.message, true) }
+                                </div>
+                                <div class="flex mt-2 space-x-3 text-xs">
+                                    <button class="text-red-600 hover:text-red-800 focus:outline-none" onclick={remove}>{"Remove message"}</button>
+                                    <button class="text-blue-600 hover:text-blue-800 focus:outline-none" onclick={resolve}>{"Resolve"}</button>
+                                    <button class="text-gray-500 hover:text-gray-700 focus:outline-none" onclick={dismiss}>{"Dismiss"}</button>
+                                </div>
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        </div>
+    }
+}
+
+fn view_line(m: &MessageData, reported: bool) -> Html {
+    html! {
+        <p class={classes!("break-words", if reported { "text-gray-800 bg-red-50 rounded px-1" } else { "text-gray-400" })}>
+            <span class="font-semibold">{format!("{}: ", m.from)}</span>
+            {m.message.clone()}
+        </p>
+    }
+}
@@ -0,0 +1,157 @@
+use web_sys::{HtmlInputElement, HtmlTextAreaElement};
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::content_filter::{ContentFilter, FilterLevel};
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    /// Ignored people, in name order.
+    pub ignored: Vec<String>,
+    pub on_ignore: Callback<String>,
+    pub on_unignore: Callback<String>,
+    /// The room being looked at, whose filter level is shown.
+    pub room: String,
+    pub content_filter: ContentFilter,
+    pub on_content_filter: Callback<ContentFilter>,
+    pub on_close: Callback<()>,
+}
+
+/// Settings that live on this device, over the chat.
+#[function_component(Settings)]
+pub fn settings(props: &Props) -> Html {
+    let name_input = use_node_ref();
+
+    let ignore = {
+        let name_input = name_input.clone();
+        let on_ignore = props.on_ignore.clone();
+        move || {
+            if let Some(input) = name_input.cast::<HtmlInputElement>() {
+                let name = input.value().trim().to_string();
+                if !name.is_empty() {
+                    on_ignore.emit(name);
+                    input.set_value("");
+                }
+            }
+        }
+    };
+    let onclick = {
+        let ignore = ignore.clone();
+        Callback::from(move |_| ignore())
+    };
+    let onkeypress = Callback::from(move |e: KeyboardEvent| {
+        if e.key() == "Enter" {
+            ignore()
+        }
+    });
+    let on_words = {
+        let filter = props.content_filter.clone();
+        props.on_content_filter.reform(move |e: Event| {
+            let mut filter = filter.clone();
+            let input: HtmlTextAreaElement = e.target_unchecked_into();
+            filter.set_blocked_words(&input.value());
+            filter
+        })
+    };
+    let level = props.content_filter.level(&props.room);
+    let on_close = props.on_close.reform(|_| ());
+
+    html! {
+        <div class="fixed inset-0 z-30 flex items-center justify-center bg-black bg-opacity-30">
+            <div class="flex flex-col w-full max-w-md max-h-full bg-white rounded-lg shadow-xl">
+                <div class="flex items-center px-6 py-4 border-b border-gray-200">
+                    <h2 class="text-lg font-semibold text-gray-800">{"Settings"}</h2>
+                    <button
+                        class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                        title="Close settings"
+                        onclick={on_close}
+                    >
+                        <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                            <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                        </svg>
+                    </button>
+                </div>
+                <div class="px-6 py-4 overflow-y-auto">
+                    <h3 class="font-semibold text-gray-700">{"Ignored people"}</h3>
+                    <p class="mt-1 text-xs text-gray-500">
+                        {"Their messages are hidden, and they don't notify you or show up as typing. They aren't told."}
+                    </p>
+                    <div class="flex mt-3">
+                        <input
+                            ref={name_input}
+                            type="text"
+                            placeholder="Username"
+                            class="flex-1 px-3 py-1 text-sm border border-gray-300 rounded focus:outline-none focus:ring-2 focus:ring-blue-500"
+                            {onkeypress}
+                        />
+                        <button
+                            class="ml-2 px-3 py-1 text-sm text-white bg-blue-600 rounded hover:bg-blue-700 focus:outline-none"
+                            {onclick}
+                        >
+                            {"Ignore"}
+                        </button>
+                    </div>
+                    if props.ignored.is_empty() {
+                        <p class="mt-3 text-sm text-gray-500">{"You're not ignoring anyone"}</p>
+                    }
+                    <ul class="mt-2">
+                        {
+                            props.ignored.iter().map(|name| {
+                                let onclick = {
+                                    let name = name.clone();
+                                    props.on_unignore.reform(move |_| name.clone())
+                                };
+                                html! {
+                                    <li key={name.clone()} class="flex items-center py-2 border-b border-gray-100 text-sm">
+                                        <span class="text-gray-800">{name.clone()}</span>
+                                        <button
+                                            class="ml-auto text-xs text-blue-600 hover:text-blue-800 focus:outline-none"
+                                            {onclick}
+                                        >
+                                            {"Stop ignoring"}
+                                        </button>
+                                    </li>
+                                }
+                            }).collect::<Html>()
+                        }
+                    </ul>
+                    <h3 class="mt-6 font-semibold text-gray-700">{"Content filter"}</h3>
+                    <p class="mt-1 text-xs text-gray-500">
+                        {"Blocked words are masked with asterisks, in rooms you turn the filter on for. Separate them with commas; end one with * to catch any ending."}
+                    </p>
+                    <textarea
+                        rows="3"
+                        placeholder="Blocked words"
+                        class="w-full mt-3 px-3 py-2 text-sm border border-gray-300 rounded focus:outline-none focus:ring-2 focus:ring-blue-500"
+                        value={props.content_filter.blocked_words.join(", ")}
+                        onchange={on_words}
+                    />
+                    <p class="mt-3 text-sm font-medium text-gray-700">{format!("In # {}", props.room)}</p>
+                    <div class="flex flex-col mt-2 space-y-1">
+                        {
+                            FilterLevel::ALL.iter().map(|&option| {
+                                let onclick = {
+                                    let filter = props.content_filter.clone();
+                                    let room = props.room.clone();
+                                    props.on_content_filter.reform(move |_| {
+                                        let mut filter = filter.clone();
+                                        filter.set_level(&room, option);
+                                        filter
+                                    })
+                                };
+                                html! {
+                                    <button
+                                        class={classes!("px-3", "py-2", "text-sm", "text-left", "border", "rounded", "focus:outline-none", if level == option { "border-blue-500 bg-blue-50 text-blue-800" } else { "border-gray-300 text-gray-700 hover:bg-gray-50" })}
+                                        {onclick}
+                                    >
+                                        {option.label()}
+                                    </button>
+                                }
+                            }).collect::<Html>()
+                        }
+                    </div>
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,126 @@
+use web_sys::HtmlInputElement;
+use yew::functional::*;
+use yew::prelude::*;
+
+use crate::services::protocol::{Webhook, WEBHOOK_PATH};
+use crate::services::transport::HTTP_URL;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub room: String,
+    /// The room's webhooks, `None` until the server has listed them.
+    pub hooks: Option<Vec<Webhook>>,
+    /// Called with the name for a new webhook.
+    pub on_create: Callback<String>,
+    /// Called with the id of a webhook to revoke.
+    pub on_revoke: Callback<String>,
+    pub on_close: Callback<()>,
+}
+
+/// Side panel listing a room's webhooks, with their URLs, and letting the
+/// user create and revoke them.
+#[function_component(Webhooks)]
+pub fn webhooks(props: &Props) -> Html {
+    let name_input = use_node_ref();
+
+    let create = {
+        let name_input = name_input.clone();
+        let on_create = props.on_create.clone();
+        move || {
+            if let Some(input) = name_input.cast::<HtmlInputElement>() {
+                let name = input.value().trim().to_string();
+                if !name.is_empty() {
+                    on_create.emit(name);
+                    input.set_value("");
+                }
+            }
+        }
+    };
+    let onclick = {
+        let create = create.clone();
+        Callback::from(move |_| create())
+    };
+    let onkeypress = Callback::from(move |e: KeyboardEvent| {
+        if e.key() == "Enter" {
+            create()
+        }
+    });
+    let on_close = props.on_close.reform(|_| ());
+
+    html! {
+        <div class="hidden lg:flex flex-col w-96 bg-white border-l border-gray-200">
+            <div class="flex items-center px-4 py-3 border-b border-gray-200">
+                <h2 class="font-semibold text-gray-700">{format!("Webhooks for # {}", props.room)}</h2>
+                <button
+                    class="ml-auto p-1 rounded text-gray-400 hover:text-gray-600 focus:outline-none"
+                    title="Close webhooks"
+                    onclick={on_close}
+                >
+                    <svg xmlns="http://www.w3.org/2000/svg" class="h-5 w-5" fill="none" viewBox="0 0 24 24" stroke="currentColor">
+                        <path stroke-linecap="round" stroke-linejoin="round" stroke-width="2" d="M6 18L18 6M6 6l12 12" />
+                    </svg>
+                </button>
+            </div>
+            <p class="px-4 pt-3 text-xs text-gray-500">
+                {"Anyone with a webhook's URL can post into this room by sending it JSON like "}
+                <code class="text-gray-700">{r#"{"text": "Build passed"}"#}</code>
+                {". Revoke it if it leaks."}
+            </p>
+            <div class="flex px-4 py-3 border-b border-gray-200">
+                <input
+                    ref={name_input}
+                    type="text"
+                    placeholder="Name, like CI or Deploys"
+                    class="flex-1 px-3 py-1 text-sm border border-gray-300 rounded focus:outline-none focus:ring-2 focus:ring-blue-500"
+                    {onkeypress}
+                />
+                <button
+                    class="ml-2 px-3 py-1 text-sm text-white bg-blue-600 rounded hover:bg-blue-700 focus:outline-none"
+                    {onclick}
+                >
+                    {"Create"}
+                </button>
+            </div>
+            <div class="flex-1 overflow-y-auto">
+                {
+                    match &props.hooks {
+                        None => html! { <p class="p-4 text-sm text-gray-500">{"Loading\u{2026}"}</p> },
+                        Some(hooks) if hooks.is_empty() => html! {
+                            <p class="p-4 text-sm text-gray-500">{"This room has no webhooks"}</p>
+                        },
+                        Some(hooks) => hooks.iter().map(|hook| {
+                            let url = format!("{}{}{}", HTTP_URL, WEBHOOK_PATH, hook.token);
+                            let onrevoke = {
+                                let id = hook.id.clone();
+                                props.on_revoke.reform(move |_| id.clone())
+                            };
+                            let onfocus = Callback::from(|e: FocusEvent| {
+                                e.target_unchecked_into::<HtmlInputElement>().select();
+                            });
+                            html! {
+                                <div key={hook.id.clone()} class="p-4 border-b border-gray-100">
+                                    <div class="flex items-center">
+                                        <span class="font-medium text-gray-800">{hook.name.clone()}</span>
+                                        <span class="ml-2 text-xs text-gray-400">{format!("by {}", hook.created_by)}</span>
+                                        <button
+                                            class="ml-auto text-xs text-red-600 hover:text-red-800 focus:outline-none"
+                                            onclick={onrevoke}
+                                        >
+                                            {"Revoke"}
+                                        </button>
+                                    </div>
+                                    <input
+                                        readonly=true
+                                        value={url}
+                                        class="w-full mt-2 px-2 py-1 font-mono text-xs text-gray-600 bg-gray-50 border border-gray-200 rounded"
+                                        {onfocus}
+                                    />
+                                </div>
+                            }
+                        }).collect::<Html>(),
+                    }
+                }
+            </div>
+        </div>
+    }
+}
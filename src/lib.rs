@@ -22,12 +22,14 @@ use components::login::Login;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Debug, Clone, Copy, PartialEq, Routable)]
+#[derive(Debug, Clone, PartialEq, Routable)]
 pub enum Route {
     #[at("/")]
     Login,
     #[at("/chat")]
     Chat,
+    #[at("/profile/:username")]
+    Profile { username: String },
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -38,18 +40,28 @@ pub type User = Rc<UserInner>;
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
     pub username: RefCell<String>,
+    /// Why the chat sent the user back to the login screen, like being
+    /// kicked; shown there until they start chatting again.
+    pub signed_out: RefCell<Option<String>>,
 }
 
 #[function_component(Main)]
-fn main() -> Html {
+pub fn main() -> Html {
     let ctx = use_state(|| {
         Rc::new(UserInner {
             username: RefCell::new("initial".into()),
+            signed_out: RefCell::new(None),
         })
     });
 
+    app((*ctx).clone())
+}
+
+// `html!` turns the props of nested components into bare statements.
+#[allow(clippy::unnecessary_operation)]
+fn app(user: User) -> Html {
     html! {
-        <ContextProvider<User> context={(*ctx).clone()}>
+        <ContextProvider<User> context={user}>
             <BrowserRouter>
                 <div class="flex w-screen h-screen">
                     <Switch<Route> render={Switch::render(switch)}/>
@@ -59,10 +71,15 @@ fn main() -> Html {
     }
 }
 
+// `html!` turns components without props into unit bindings and bare
+// statements.
+#[allow(clippy::unnecessary_operation, clippy::let_unit_value)]
 fn switch(selected_route: &Route) -> Html {
     match selected_route {
         Route::Login => html! {<Login />},
         Route::Chat => html! {<Chat/>},
+        // The same component as the chat, so it stays connected.
+        Route::Profile { username } => html! {<Chat profile={Some(username.clone())}/>},
         Route::NotFound => html! {<h1>{"404 baby"}</h1>},
     }
 }
@@ -73,3 +90,11 @@ pub fn run_app() -> Result<(), JsValue> {
     yew::start_app::<Main>();
     Ok(())
 }
+
+/// Starts the shared worker that holds the server connection; see
+/// `worker.js`.
+#[wasm_bindgen]
+pub fn run_connection_worker(early_ports: js_sys::Array) {
+    wasm_logger::init(wasm_logger::Config::default());
+    services::connection_worker::run(early_ports);
+}
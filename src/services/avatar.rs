@@ -0,0 +1,222 @@
+use std::fmt::Write;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{CanvasRenderingContext2d, File, HtmlCanvasElement, HtmlImageElement, Url};
+
+use crate::services::protocol::{AvatarStyle, Profile};
+
+/// Cells along each side of an identicon.
+const GRID: u64 = 5;
+/// Width and height uploads are scaled down to, in pixels.
+const UPLOAD_SIZE: u32 = 96;
+const JPEG_QUALITY: f64 = 0.85;
+
+/// FNV-1a, so every browser draws the same avatar for a name.
+fn hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// The colour a name is drawn in.
+fn hue(name: &str) -> u64 {
+    hash(name) % 360
+}
+
+/// A symmetric five by five pattern in a colour picked from `username`.
+pub fn identicon(username: &str) -> String {
+    let hash = hash(username);
+    let mut cells = String::new();
+    let mut cell = |x, y| {
+        let _ = write!(cells, r#"<rect x="{}" y="{}" width="1" height="1"/>"#, x, y);
+    };
+    // The low bits went into the colour; the pattern comes from the rest.
+    let mut bits = hash >> 9;
+    for x in 0..GRID.div_ceil(2) {
+        for y in 0..GRID {
+            if bits & 1 == 1 {
+                cell(x, y);
+                if GRID - 1 - x != x {
+                    cell(GRID - 1 - x, y);
+                }
+            }
+            bits >>= 1;
+        }
+    }
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-1 -1 7 7" shape-rendering="crispEdges">"#,
+            r#"<rect x="-1" y="-1" width="7" height="7" fill="hsl(0,0%,94%)"/>"#,
+            r#"<g fill="hsl({},60%,50%)">{}</g></svg>"#
+        ),
+        hue(username),
+        cells
+    )
+}
+
+/// Up to two letters of `name` on a background picked from `username`.
+pub fn initials(username: &str, name: &str) -> String {
+    let letters: String = name
+        .split_whitespace()
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+    let letters = if letters.is_empty() {
+        "?".to_string()
+    } else {
+        letters
+    };
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 64 64">"#,
+            r#"<rect width="64" height="64" fill="hsl({},55%,45%)"/>"#,
+            r#"<text x="32" y="32" dy=".35em" text-anchor="middle" fill="white" "#,
+            r#"font-family="sans-serif" font-size="26" font-weight="600">{}</text></svg>"#
+        ),
+        hue(username),
+        escape(&letters)
+    )
+}
+
+/// The avatar to show for `username`, who goes by `name`: their upload if
+/// they have one, otherwise one drawn in the style they picked.
+pub fn avatar(username: &str, name: &str, profile: Option<&Profile>) -> String {
+    match profile {
+        Some(profile) if !profile.image.is_empty() => profile.image.clone(),
+        _ => drawn(
+            username,
+            name,
+            profile.map(|p| p.avatar).unwrap_or_default(),
+        ),
+    }
+}
+
+/// The avatar drawn for `username` in `style`, as an image URL.
+pub fn drawn(username: &str, name: &str, style: AvatarStyle) -> String {
+    let svg = match style {
+        AvatarStyle::Identicon => identicon(username),
+        AvatarStyle::Initials => initials(username, name),
+    };
+    data_url(&svg)
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn data_url(svg: &str) -> String {
+    let mut url = "data:image/svg+xml,".to_string();
+    for b in svg.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.!~*'()/=:;,".contains(&b) {
+            url.push(b as char);
+        } else {
+            let _ = write!(url, "%{:02X}", b);
+        }
+    }
+    url
+}
+
+/// Crops `file` to a square and scales it down to an avatar small enough
+/// to go in a profile.
+pub async fn shrink(file: &File) -> Result<String, String> {
+    let url = Url::create_object_url_with_blob(file).map_err(js_error)?;
+    let image = HtmlImageElement::new().map_err(js_error)?;
+    image.set_src(&url);
+    let decoded = JsFuture::from(image.decode()).await;
+    let _ = Url::revoke_object_url(&url);
+    decoded.map_err(|_| "That isn't an image this browser can read.".to_string())?;
+
+    let canvas: HtmlCanvasElement = gloo::utils::document()
+        .create_element("canvas")
+        .map_err(js_error)?
+        .unchecked_into();
+    canvas.set_width(UPLOAD_SIZE);
+    canvas.set_height(UPLOAD_SIZE);
+    let context: CanvasRenderingContext2d = canvas
+        .get_context("2d")
+        .map_err(js_error)?
+        .ok_or("no 2d canvas")?
+        .unchecked_into();
+    let (width, height) = (image.natural_width() as f64, image.natural_height() as f64);
+    let side = width.min(height);
+    let size = UPLOAD_SIZE as f64;
+    // JPEG has no transparency; without this it'd come out black.
+    context.set_fill_style_str("white");
+    context.fill_rect(0.0, 0.0, size, size);
+    context
+        .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+            &image,
+            (width - side) / 2.0,
+            (height - side) / 2.0,
+            side,
+            side,
+            0.0,
+            0.0,
+            size,
+            size,
+        )
+        .map_err(js_error)?;
+    let url = canvas
+        .to_data_url_with_type_and_encoder_options("image/jpeg", &JPEG_QUALITY.into())
+        .map_err(js_error)?;
+    if url.len() > Profile::IMAGE_MAX {
+        return Err("That image is too detailed to use as an avatar.".to_string());
+    }
+    Ok(url)
+}
+
+fn js_error(e: JsValue) -> String {
+    format!("{:?}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avatars_are_the_same_everywhere() {
+        assert_eq!(identicon("alice"), identicon("alice"));
+        assert_ne!(identicon("alice"), identicon("bob"));
+        assert_eq!(hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn identicons_are_mirrored() {
+        let svg = identicon("carol");
+        for y in 0..GRID {
+            for x in 0..2 {
+                let cell = |x| format!(r#"x="{}" y="{}""#, x, y);
+                assert_eq!(svg.contains(&cell(x)), svg.contains(&cell(GRID - 1 - x)));
+            }
+        }
+    }
+
+    #[test]
+    fn initials_come_from_the_display_name() {
+        assert!(initials("al", "alice liddell").contains(">AL</text>"));
+        assert!(initials("al", "Al").contains(">A</text>"));
+        assert!(initials("al", "  ").contains(">?</text>"));
+        assert!(initials("x", "<b> &c").contains(">&lt;&amp;</text>"));
+    }
+
+    #[test]
+    fn uploads_win_over_drawn_avatars() {
+        let mut profile = Profile::default();
+        assert!(avatar("bob", "bob", Some(&profile)).starts_with("data:image/svg+xml,"));
+        profile.image = "data:image/png;base64,AAAA".into();
+        assert_eq!(avatar("bob", "bob", Some(&profile)), profile.image);
+        assert!(!drawn("bob", "bob", AvatarStyle::Initials).contains(['<', '"', '#', ' ']));
+    }
+}
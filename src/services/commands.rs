@@ -0,0 +1,335 @@
+use std::fmt;
+
+/// What a line typed into the composer asks the chat to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Post the text to the current room as it is.
+    Say(String),
+    /// Post an action in the third person, like "* alice waves".
+    Emote(String),
+    /// Reconnect under a different username.
+    Rename(String),
+    Join(String),
+    /// Leave the named room, or the current one.
+    Leave(Option<String>),
+    /// A direct message to one user.
+    Whisper {
+        to: String,
+        text: String,
+    },
+    SetTopic(String),
+    /// Forget the current room's messages on this device.
+    Clear,
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    Unknown(String),
+    /// The command exists but was given the wrong arguments; holds its usage.
+    Usage(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(
+                f,
+                "There's no /{} command. Type /help to see what there is.",
+                name
+            ),
+            CommandError::Usage(usage) => write!(f, "Usage: {}", usage),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&str) -> Option<Action>>;
+
+/// A slash command: its name without the slash, a hint of the arguments it
+/// takes and a line of help.
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub help: &'static str,
+    handler: Handler,
+}
+
+impl Command {
+    /// How to call it, like `/msg <user> <text>`.
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.args)
+        }
+    }
+}
+
+/// The slash commands the composer understands.
+///
+/// Lines starting with `/` run the command they name; anything else, and
+/// lines starting with `//` to get a literal slash, are said as they are.
+/// Handlers get everything after the command name, trimmed, and return
+/// `None` when that isn't what they take.
+pub struct Commands {
+    /// Kept sorted by name.
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    pub fn empty() -> Self {
+        Self { commands: vec![] }
+    }
+
+    /// Adds a command, replacing any already registered under `name`.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        args: &'static str,
+        help: &'static str,
+        handler: impl Fn(&str) -> Option<Action> + 'static,
+    ) {
+        let command = Command {
+            name,
+            args,
+            help,
+            handler: Box::new(handler),
+        };
+        match self.commands.binary_search_by_key(&name, |c| c.name) {
+            Ok(i) => self.commands[i] = command,
+            Err(i) => self.commands.insert(i, command),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands
+            .binary_search_by_key(&name, |c| c.name)
+            .ok()
+            .map(|i| &self.commands[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter()
+    }
+
+    /// Works out what a line from the composer asks for.
+    pub fn parse(&self, input: &str) -> Result<Action, CommandError> {
+        let (name, args) = match split(input) {
+            Some(command) => command,
+            None => return Ok(Action::Say(unescape(input).to_string())),
+        };
+        let command = self
+            .get(&name.to_lowercase())
+            .ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+        (command.handler)(args).ok_or_else(|| CommandError::Usage(command.usage()))
+    }
+
+    /// Commands to offer while `input` is being typed: those starting with
+    /// what's there so far, or, once arguments have started, the one named.
+    pub fn suggest(&self, input: &str) -> Vec<&Command> {
+        let rest = match input.strip_prefix('/') {
+            Some(rest) if !rest.starts_with('/') => rest,
+            _ => return vec![],
+        };
+        match rest.split_once(char::is_whitespace) {
+            Some((name, _)) => self.get(&name.to_lowercase()).into_iter().collect(),
+            None => {
+                let prefix = rest.to_lowercase();
+                self.commands
+                    .iter()
+                    .filter(|c| c.name.starts_with(&prefix))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Default for Commands {
+    /// The commands every chat has.
+    fn default() -> Self {
+        let mut commands = Self::empty();
+        commands.register(
+            "me",
+            "<action>",
+            "Say what you're doing, like \"/me waves\"",
+            |args| (!args.is_empty()).then(|| Action::Emote(args.to_string())),
+        );
+        commands.register("nick", "<name>", "Change your username", |args| {
+            one_word(args).map(|name| Action::Rename(name.to_string()))
+        });
+        commands.register(
+            "join",
+            "<room>",
+            "Join a room, creating it if needed",
+            |args| one_word(args).map(|room| Action::Join(room_name(room))),
+        );
+        commands.register(
+            "leave",
+            "[room]",
+            "Leave this room, or the one named",
+            |args| match args {
+                "" => Some(Action::Leave(None)),
+                _ => one_word(args).map(|room| Action::Leave(Some(room_name(room)))),
+            },
+        );
+        commands.register(
+            "msg",
+            "<user> <text>",
+            "Send someone a direct message",
+            |args| {
+                let (to, text) = args.split_once(char::is_whitespace)?;
+                Some(Action::Whisper {
+                    to: to.trim_start_matches('@').to_string(),
+                    text: text.trim().to_string(),
+                })
+            },
+        );
+        commands.register("topic", "<text>", "Set the topic of this room", |args| {
+            (!args.is_empty()).then(|| Action::SetTopic(args.to_string()))
+        });
+        commands.register(
+            "clear",
+            "",
+            "Clear this room's messages on this device",
+            |_| Some(Action::Clear),
+        );
+        commands.register("help", "", "List the commands", |_| Some(Action::Help));
+        commands
+    }
+}
+
+/// The command name and trimmed arguments of `input`, if it's a command.
+fn split(input: &str) -> Option<(&str, &str)> {
+    let rest = input.trim_start().strip_prefix('/')?;
+    if rest.starts_with('/') {
+        return None;
+    }
+    match rest.split_once(char::is_whitespace) {
+        Some((name, args)) => Some((name, args.trim())),
+        None => Some((rest.trim_end(), "")),
+    }
+}
+
+/// `//text` says `/text`.
+fn unescape(input: &str) -> &str {
+    match input.trim_start().strip_prefix("//") {
+        Some(_) => &input.trim_start()[1..],
+        None => input,
+    }
+}
+
+fn one_word(args: &str) -> Option<&str> {
+    (!args.is_empty() && !args.contains(char::is_whitespace)).then_some(args)
+}
+
+fn room_name(room: &str) -> String {
+    room.trim_start_matches('#').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_is_said_as_it_is() {
+        let commands = Commands::default();
+        assert_eq!(commands.parse("hello"), Ok(Action::Say("hello".into())));
+        assert_eq!(commands.parse("//shrug"), Ok(Action::Say("/shrug".into())));
+    }
+
+    #[test]
+    fn parses_the_builtin_commands() {
+        let commands = Commands::default();
+        assert_eq!(
+            commands.parse("/me waves"),
+            Ok(Action::Emote("waves".into()))
+        );
+        assert_eq!(
+            commands.parse("/NICK bob"),
+            Ok(Action::Rename("bob".into()))
+        );
+        assert_eq!(
+            commands.parse("/join #random"),
+            Ok(Action::Join("random".into()))
+        );
+        assert_eq!(commands.parse("/leave"), Ok(Action::Leave(None)));
+        assert_eq!(
+            commands.parse("/leave random"),
+            Ok(Action::Leave(Some("random".into())))
+        );
+        assert_eq!(
+            commands.parse("/msg @carol  see you at   five "),
+            Ok(Action::Whisper {
+                to: "carol".into(),
+                text: "see you at   five".into()
+            })
+        );
+        assert_eq!(
+            commands.parse("/topic Friday demo"),
+            Ok(Action::SetTopic("Friday demo".into()))
+        );
+        assert_eq!(commands.parse("/clear"), Ok(Action::Clear));
+        assert_eq!(commands.parse("/help"), Ok(Action::Help));
+    }
+
+    #[test]
+    fn bad_arguments_get_the_usage() {
+        let commands = Commands::default();
+        assert_eq!(
+            commands.parse("/msg carol"),
+            Err(CommandError::Usage("/msg <user> <text>".into()))
+        );
+        assert_eq!(
+            commands.parse("/nick two words"),
+            Err(CommandError::Usage("/nick <name>".into()))
+        );
+        assert_eq!(
+            commands.parse("/me"),
+            Err(CommandError::Usage("/me <action>".into()))
+        );
+        assert_eq!(
+            commands.parse("/dance"),
+            Err(CommandError::Unknown("dance".into()))
+        );
+    }
+
+    #[test]
+    fn new_commands_can_be_registered() {
+        let mut commands = Commands::default();
+        commands.register("shrug", "[text]", "Append a shrug", |args| {
+            Some(Action::Say(
+                format!("{} \u{af}\\_(\u{30c4})_/\u{af}", args)
+                    .trim()
+                    .to_string(),
+            ))
+        });
+        assert_eq!(
+            commands.parse("/shrug ok"),
+            Ok(Action::Say("ok \u{af}\\_(\u{30c4})_/\u{af}".into()))
+        );
+        // Registering a name again replaces the handler.
+        commands.register("help", "", "", |_| Some(Action::Clear));
+        assert_eq!(commands.parse("/help"), Ok(Action::Clear));
+    }
+
+    #[test]
+    fn suggests_commands_while_typing() {
+        let commands = Commands::default();
+        let names = |input| {
+            commands
+                .suggest(input)
+                .iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("/"),
+            vec!["clear", "help", "join", "leave", "me", "msg", "nick", "topic"]
+        );
+        assert_eq!(names("/m"), vec!["me", "msg"]);
+        assert_eq!(names("/msg bo"), vec!["msg"]);
+        assert!(names("/nope x").is_empty());
+        assert!(names("hello").is_empty());
+        assert!(names("//me").is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+use std::cell::{Cell, RefCell};
+
+use futures::channel::{
+    mpsc::{self, TrySendError},
+    oneshot,
+};
+use futures::{select, StreamExt};
+use gloo::timers::callback::Interval;
+
+use wasm_bindgen_futures::spawn_local;
+use yew::Callback;
+
+use crate::services::event_bus::{ConnectionState, Event};
+use crate::services::protocol::{Encoding, MsgTypes, WebSocketMessage};
+use crate::services::transport::{
+    Payload, Transport, TransportEvent, CLOSE_HEARTBEAT_TIMEOUT, CLOSE_LEAVING, CLOSE_NORMAL,
+};
+
+/// Frames that may wait to go out before [`Connection::send`] refuses more.
+const OUTGOING_QUEUE: usize = 64;
+
+/// How often to ping the server, and how many unanswered pings mean the
+/// connection is dead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeartbeatConfig {
+    pub interval_ms: u32,
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 10_000,
+            max_missed: 2,
+        }
+    }
+}
+
+/// The connection currently open in this page, if any.
+struct Active {
+    id: u64,
+    close: oneshot::Sender<(u16, String)>,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Active>> = const { RefCell::new(None) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A handle on the page's connection to the chat server, over whichever
+/// [`Transport`] it was given.
+///
+/// There is only ever one: connecting closes whatever connection was open
+/// before. If the transport carries binary frames, a binary encoding is
+/// offered when registering and used from then on if the server takes it
+/// up; frames are JSON otherwise. Decoded frames and connection state
+/// changes are handed to `on_event`; it's up to the owner to publish them.
+/// Dropping the handle closes the connection with [`CLOSE_LEAVING`].
+pub struct Connection {
+    id: u64,
+    tx: mpsc::Sender<String>,
+}
+
+enum Step {
+    Beat,
+    Send(Option<String>),
+    Receive(Option<TransportEvent>),
+    Close(Option<(u16, String)>),
+}
+
+impl Connection {
+    pub fn connect(transport: Box<dyn Transport>, on_event: Callback<Event>) -> Self {
+        Self::connect_with(transport, HeartbeatConfig::default(), on_event)
+    }
+
+    pub fn connect_with(
+        mut transport: Box<dyn Transport>,
+        heartbeat: HeartbeatConfig,
+        on_event: Callback<Event>,
+    ) -> Self {
+        let id = NEXT_ID.with(|next| {
+            next.set(next.get() + 1);
+            next.get()
+        });
+        let (tx, mut out_rx) = mpsc::channel::<String>(OUTGOING_QUEUE);
+        let (close_tx, mut close_rx) = oneshot::channel();
+        let previous = ACTIVE.with(|active| {
+            active.borrow_mut().replace(Active {
+                id,
+                close: close_tx,
+            })
+        });
+        if let Some(previous) = previous {
+            let _ = previous
+                .close
+                .send((CLOSE_NORMAL, "replaced by a new connection".to_string()));
+        }
+
+        let mut incoming = transport.connect().fuse();
+        spawn_local(async move {
+            let (beat_tx, mut beats) = mpsc::unbounded();
+            let _heartbeat = Interval::new(heartbeat.interval_ms, move || {
+                let _ = beat_tx.unbounded_send(());
+            });
+            let mut missed = 0;
+            // Servers that never answered a ping don't do heartbeats; don't
+            // hold their silence against them.
+            let mut answers_pings = false;
+            let offer_binary = transport.supports_binary();
+            let mut encoding = Encoding::Json;
+
+            let requested = loop {
+                let step = select! {
+                    _ = beats.next() => Step::Beat,
+                    frame = out_rx.next() => Step::Send(frame),
+                    event = incoming.next() => Step::Receive(event),
+                    close = close_rx => Step::Close(close.ok()),
+                };
+                match step {
+                    Step::Beat => {
+                        if answers_pings && missed >= heartbeat.max_missed {
+                            log::debug!("no pong for {} pings", missed);
+                            on_event.emit(Event::Error("the server stopped responding".into()));
+                            on_event.emit(Event::Connection(ConnectionState::Disconnected));
+                            break Some((CLOSE_HEARTBEAT_TIMEOUT, "heartbeat timeout".into()));
+                        }
+                        let ping = WebSocketMessage {
+                            message_type: MsgTypes::Ping,
+                            data: Some(js_sys::Date::now().to_string()),
+                            data_array: None,
+                        };
+                        if transport.send(encode(encoding, &ping)).is_ok() {
+                            missed += 1;
+                        }
+                    }
+                    Step::Send(Some(frame)) => {
+                        log::debug!("got event from channel! {}", frame);
+                        if let Err(e) = transport.send(outgoing(encoding, offer_binary, frame)) {
+                            log::error!("transport: {}", e);
+                            on_event.emit(Event::Error(format!("failed to send: {}", e)));
+                        }
+                    }
+                    // Every sender is gone, so nobody can use the connection.
+                    Step::Send(None) => break Some((CLOSE_NORMAL, String::new())),
+                    Step::Receive(Some(TransportEvent::Open)) => {
+                        on_event.emit(Event::Connection(ConnectionState::Connected));
+                    }
+                    Step::Receive(Some(TransportEvent::Frame(frame))) => {
+                        let msg = match decode(encoding, frame) {
+                            Ok(msg) => msg,
+                            Err(e) => {
+                                on_event.emit(Event::Error(e));
+                                continue;
+                            }
+                        };
+                        log::debug!("from server: {:?}", msg);
+                        if msg.message_type == MsgTypes::Encoding {
+                            match msg.data.as_deref().and_then(Encoding::from_name) {
+                                Some(agreed) => encoding = agreed,
+                                None => on_event.emit(Event::Error(format!(
+                                    "the server picked an unknown encoding: {:?}",
+                                    msg.data
+                                ))),
+                            }
+                            continue;
+                        }
+                        match Event::from_message(msg) {
+                            Some(Event::Pong(sent_at)) => {
+                                missed = 0;
+                                answers_pings = true;
+                                let rtt = js_sys::Date::now() - sent_at;
+                                on_event.emit(Event::Latency(rtt.max(0.0)));
+                            }
+                            Some(event) => on_event.emit(event),
+                            None => {}
+                        }
+                    }
+                    Step::Receive(Some(TransportEvent::Error(e))) => {
+                        log::error!("transport: {}", e);
+                        on_event.emit(Event::Error(e));
+                    }
+                    Step::Receive(Some(TransportEvent::Closed {
+                        code,
+                        reason,
+                        clean,
+                    })) => {
+                        if !clean {
+                            on_event.emit(Event::Error(format!(
+                                "connection lost (code {}{}{})",
+                                code,
+                                if reason.is_empty() { "" } else { ": " },
+                                reason
+                            )));
+                        }
+                        break None;
+                    }
+                    Step::Receive(None) => break None,
+                    // The handle went away without a word; treat it as a drop.
+                    Step::Close(close) => {
+                        break close.or_else(|| Some((CLOSE_LEAVING, String::new())))
+                    }
+                }
+            };
+
+            match requested {
+                Some((code, reason)) => {
+                    log::debug!("closing connection: {} {}", code, reason);
+                    transport.close(code, &reason);
+                }
+                // Only closes nobody asked for are news to the owner.
+                None => {
+                    log::debug!("connection closed");
+                    on_event.emit(Event::Connection(ConnectionState::Disconnected));
+                }
+            }
+            Self::forget(id);
+        });
+
+        Self { id, tx }
+    }
+
+    /// Queues a frame for the server. Fails if the queue is full, which
+    /// happens when frames are sent faster than the transport takes them.
+    pub fn send(&self, frame: String) -> Result<(), TrySendError<String>> {
+        self.tx.clone().try_send(frame)
+    }
+
+    /// Closes the connection with the given close code and reason, unless a
+    /// newer connection has replaced it already.
+    pub fn disconnect(self, code: u16, reason: &str) {
+        self.close(code, reason);
+    }
+
+    fn close(&self, code: u16, reason: &str) {
+        let active = ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            match active.as_ref() {
+                Some(a) if a.id == self.id => active.take(),
+                _ => None,
+            }
+        });
+        if let Some(active) = active {
+            let _ = active.close.send((code, reason.to_string()));
+        }
+    }
+
+    fn forget(id: u64) {
+        ACTIVE.with(|active| {
+            let mut active = active.borrow_mut();
+            if active.as_ref().is_some_and(|a| a.id == id) {
+                *active = None;
+            }
+        });
+    }
+}
+
+/// Writes a frame in the agreed encoding. Frames are always text until then.
+fn encode(encoding: Encoding, msg: &WebSocketMessage) -> Payload {
+    match encoding.encode(msg) {
+        Ok(bytes) if encoding.is_binary() => Payload::Binary(bytes),
+        _ => Payload::Text(serde_json::to_string(msg).unwrap()),
+    }
+}
+
+/// Re-encodes a JSON frame from the app for the wire, offering the binary
+/// encodings if it's a `Register` and the transport can carry them.
+fn outgoing(encoding: Encoding, offer_binary: bool, frame: String) -> Payload {
+    if !encoding.is_binary() && !offer_binary {
+        return Payload::Text(frame);
+    }
+    let mut msg: WebSocketMessage = match serde_json::from_str(&frame) {
+        Ok(msg) => msg,
+        Err(_) => return Payload::Text(frame),
+    };
+    if msg.message_type == MsgTypes::Register && offer_binary {
+        let offered = Encoding::BINARY
+            .iter()
+            .map(|e| e.name().to_string())
+            .collect();
+        msg.data_array = Some(offered);
+    }
+    encode(encoding, &msg)
+}
+
+/// Reads a frame: text is always JSON, binary is in the agreed encoding.
+fn decode(encoding: Encoding, frame: Payload) -> Result<WebSocketMessage, String> {
+    match frame {
+        Payload::Text(text) => {
+            serde_json::from_str(&text).map_err(|e| format!("malformed frame: {}", e))
+        }
+        Payload::Binary(_) if !encoding.is_binary() => {
+            Err("binary frame before agreeing on an encoding".to_string())
+        }
+        Payload::Binary(bytes) => encoding.decode(&bytes).map_err(|e| e.to_string()),
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.close(CLOSE_LEAVING, "");
+    }
+}
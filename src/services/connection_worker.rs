@@ -0,0 +1,399 @@
+//! The shared worker that holds the server connection for every open tab of
+//! a user.
+//!
+//! Tabs start it through
+//! [`SharedConnection`](crate::services::shared_connection::SharedConnection),
+//! one worker per username, and talk to it over message ports: they hand it
+//! frames for the server, and it hands each of them everything the server
+//! says. Browsers without shared workers run the same [`Relay`] in the page.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+use futures::channel::mpsc::UnboundedSender;
+use gloo::events::EventListener;
+use gloo::timers::callback::Interval;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::{EventTarget, MessageEvent, MessagePort};
+use yew::Callback;
+
+use crate::services::connection::Connection;
+use crate::services::event_bus::{ConnectionState, Event, SyncEvent};
+use crate::services::protocol::WebSocketMessage;
+use crate::services::transport::{TransportKind, CLOSE_LEAVING};
+
+/// How often the relay checks whether it's time to reconnect.
+const HEARTBEAT_MS: u32 = 1000;
+/// Longest wait between attempts to reconnect a lost connection.
+const MAX_RETRY_MS: f64 = 30_000.0;
+/// Events kept around so tabs opened later can catch up.
+const REPLAY_LEN: usize = 200;
+
+/// What a tab says to the relay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TabMessage {
+    /// The first thing a tab says: who it is and how to reach the server.
+    Join {
+        username: String,
+        transport: TransportKind,
+        /// The session token kept for `username`, if there is one.
+        token: Option<String>,
+    },
+    /// A frame for the server.
+    Send(String),
+    /// A local state change for the user's other tabs.
+    Sync(SyncEvent),
+    /// The tab is closing, or done with this user.
+    Leave,
+}
+
+/// What the relay says to a tab.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WorkerMessage {
+    Event(Event),
+    /// What a tab that just joined missed.
+    Replay(Vec<Event>),
+}
+
+/// How the relay reaches a tab.
+pub enum Port {
+    /// A tab of the shared worker.
+    Worker {
+        port: MessagePort,
+        _listener: EventListener,
+    },
+    /// The page the relay runs in.
+    Page(UnboundedSender<WorkerMessage>),
+}
+
+impl Port {
+    fn post(&self, message: &WorkerMessage) {
+        match self {
+            Port::Worker { port, .. } => {
+                let message = serde_json::to_string(message).unwrap();
+                if let Err(e) = port.post_message(&message.into()) {
+                    log::error!("failed to reach a tab: {:?}", e);
+                }
+            }
+            Port::Page(tx) => {
+                let _ = tx.unbounded_send(message.clone());
+            }
+        }
+    }
+}
+
+struct Inner {
+    /// The tabs that have joined, by port.
+    ports: BTreeMap<u64, Port>,
+    username: String,
+    transport: TransportKind,
+    token: Option<String>,
+    socket: Option<Connection>,
+    recent: VecDeque<Event>,
+    last_state: Option<Event>,
+    last_users: Option<Event>,
+    last_roles: Option<Event>,
+    last_profiles: Option<Event>,
+    /// Whether we're muted, as the last `Muted` or `Unmuted` said.
+    last_mute: Option<Event>,
+    last_reports: Option<Event>,
+    /// The server kicked us; reconnecting would only annoy it.
+    kicked: bool,
+    last_id: u64,
+    /// Reconnects attempted since the server last answered.
+    retries: u32,
+    /// Don't reconnect before this time, in milliseconds since the epoch.
+    retry_at: f64,
+}
+
+/// One connection to the server and the tabs using it.
+///
+/// The relay connects once the first tab joins and registers under that
+/// tab's name, reconnects with backoff while any tab is left, and
+/// disconnects when the last one leaves. Everything the server says goes to
+/// every tab; tabs that join later are replayed what they missed.
+pub struct Relay {
+    inner: Rc<RefCell<Inner>>,
+    _heartbeat: Interval,
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        let inner = Rc::new(RefCell::new(Inner {
+            ports: BTreeMap::new(),
+            username: String::new(),
+            transport: TransportKind::WebSocket,
+            token: None,
+            socket: None,
+            recent: VecDeque::new(),
+            last_state: None,
+            last_users: None,
+            last_roles: None,
+            last_profiles: None,
+            last_mute: None,
+            last_reports: None,
+            kicked: false,
+            last_id: 0,
+            retries: 0,
+            retry_at: 0.0,
+        }));
+        let heartbeat = {
+            let inner = inner.clone();
+            Interval::new(HEARTBEAT_MS, move || Inner::heartbeat(&inner))
+        };
+        Self {
+            inner,
+            _heartbeat: heartbeat,
+        }
+    }
+}
+
+impl Relay {
+    /// Adds a tab, which is heard from once it joins.
+    pub fn attach(&self, id: u64, port: Port) {
+        self.inner.borrow_mut().ports.insert(id, port);
+    }
+
+    pub fn receive(&self, id: u64, message: TabMessage) {
+        Inner::receive(&self.inner, id, message);
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        if let Some(socket) = self.inner.borrow_mut().socket.take() {
+            socket.disconnect(CLOSE_LEAVING, "tab closed");
+        }
+    }
+}
+
+/// Runs the shared worker: every tab that connects gets a port on the one
+/// relay. `early` holds the ports of tabs that connected while the worker
+/// was still loading.
+pub fn run(early: js_sys::Array) {
+    let relay = Rc::new(Relay::default());
+    let mut next_id = 0;
+    let mut attach = move |port: MessagePort| {
+        next_id += 1;
+        let id = next_id;
+        let on_message = {
+            let relay = relay.clone();
+            EventListener::new(&port, "message", move |e| {
+                let data = e.unchecked_ref::<MessageEvent>().data();
+                match data.as_string().map(|s| serde_json::from_str(&s)) {
+                    Some(Ok(message)) => relay.receive(id, message),
+                    _ => log::error!("unreadable message from a tab"),
+                }
+            })
+        };
+        port.start();
+        relay.attach(
+            id,
+            Port::Worker {
+                port,
+                _listener: on_message,
+            },
+        );
+    };
+    for port in early.iter() {
+        attach(port.unchecked_into());
+    }
+    let scope: EventTarget = js_sys::global().unchecked_into();
+    EventListener::new(&scope, "connect", move |e| {
+        attach(
+            e.unchecked_ref::<MessageEvent>()
+                .ports()
+                .get(0)
+                .unchecked_into(),
+        )
+    })
+    .forget();
+}
+
+impl Inner {
+    fn receive(this: &Rc<RefCell<Inner>>, id: u64, message: TabMessage) {
+        let mut inner = this.borrow_mut();
+        match message {
+            TabMessage::Join {
+                username,
+                transport,
+                token,
+            } => {
+                let connect = inner.socket.is_none();
+                if connect {
+                    inner.username = username;
+                    inner.transport = transport;
+                    // Signing in again is worth a try, even after a kick.
+                    inner.kicked = false;
+                    inner.retry_at = 0.0;
+                }
+                if inner.token.is_none() {
+                    inner.token = token;
+                }
+                let replay = WorkerMessage::Replay(inner.replay());
+                if let Some(port) = inner.ports.get(&id) {
+                    port.post(&replay);
+                }
+                drop(inner);
+                if connect {
+                    Inner::connect(this);
+                }
+            }
+            TabMessage::Send(frame) => {
+                let error = match &inner.socket {
+                    Some(socket) => match socket.send(frame) {
+                        Ok(()) => return,
+                        Err(e) if e.is_full() => "too many frames are waiting to go out",
+                        Err(_) => "the connection is closed",
+                    },
+                    None => "not connected",
+                };
+                if let Some(port) = inner.ports.get(&id) {
+                    port.post(&WorkerMessage::Event(Event::Error(format!(
+                        "failed to send: {}",
+                        error
+                    ))));
+                }
+            }
+            TabMessage::Sync(sync) => {
+                let message = WorkerMessage::Event(Event::Sync(sync));
+                for (_, port) in inner.ports.iter().filter(|(other, _)| **other != id) {
+                    port.post(&message);
+                }
+            }
+            TabMessage::Leave => {
+                inner.ports.remove(&id);
+                if inner.ports.is_empty() {
+                    if let Some(socket) = inner.socket.take() {
+                        socket.disconnect(CLOSE_LEAVING, "every tab closed");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reconnects a lost connection once it's time to try again.
+    fn heartbeat(this: &Rc<RefCell<Inner>>) {
+        let due = {
+            let inner = this.borrow();
+            inner.socket.is_none()
+                && !inner.kicked
+                && !inner.ports.is_empty()
+                && !inner.username.is_empty()
+                && js_sys::Date::now() >= inner.retry_at
+        };
+        if due {
+            Inner::connect(this);
+        }
+    }
+
+    fn connect(this: &Rc<RefCell<Inner>>) {
+        let on_event = {
+            let inner = Rc::downgrade(this);
+            Callback::from(move |event| {
+                if let Some(inner) = inner.upgrade() {
+                    inner.borrow_mut().handle(event);
+                }
+            })
+        };
+        let transport = this.borrow().transport.open();
+        let socket = Connection::connect(transport, on_event);
+
+        let mut inner = this.borrow_mut();
+        let register = WebSocketMessage::register(&inner.username, inner.token.as_deref());
+        if let Err(e) = socket.send(serde_json::to_string(&register).unwrap()) {
+            log::debug!("error sending to channel: {:?}", e);
+        }
+        inner.socket = Some(socket);
+    }
+
+    /// Passes on something from the server to every tab.
+    fn handle(&mut self, event: Event) {
+        let event = self.stamp(event);
+        match &event {
+            Event::Connection(ConnectionState::Disconnected) => self.lost_connection(),
+            Event::Users(..) | Event::Latency(_) => self.retries = 0,
+            Event::Kicked(_) | Event::Refused(_) => self.kicked = true,
+            // Tabs keep it, since workers can't use local storage; this is
+            // for reconnecting.
+            Event::Session(token) => self.token = Some(token.clone()),
+            _ => {}
+        }
+        self.remember(&event);
+        let message = WorkerMessage::Event(event);
+        for port in self.ports.values() {
+            port.post(&message);
+        }
+    }
+
+    /// Drops a connection that died and schedules the next attempt, backing
+    /// off while the server stays unreachable.
+    fn lost_connection(&mut self) {
+        self.socket = None;
+        if self.kicked {
+            self.retry_at = f64::INFINITY;
+            return;
+        }
+        self.retries += 1;
+        let delay = (HEARTBEAT_MS as f64 * 2f64.powi(self.retries as i32 - 1)).min(MAX_RETRY_MS);
+        self.retry_at = js_sys::Date::now() + delay;
+        log::debug!("connection lost, reconnecting in {} ms", delay);
+    }
+
+    /// Numbers messages from servers that don't. Nothing else makes ids up,
+    /// so every tab agrees on them and none collide.
+    fn stamp(&mut self, event: Event) -> Event {
+        match event {
+            Event::Message(mut m) => {
+                if m.id == 0 {
+                    m.id = self.last_id + 1;
+                }
+                if m.timestamp == 0.0 {
+                    m.timestamp = js_sys::Date::now();
+                }
+                Event::Message(m)
+            }
+            event => event,
+        }
+    }
+
+    fn remember(&mut self, event: &Event) {
+        match event {
+            Event::Connection(_) => self.last_state = Some(event.clone()),
+            Event::Users(..) => self.last_users = Some(event.clone()),
+            Event::Roles(_) => self.last_roles = Some(event.clone()),
+            Event::Profiles(_) => self.last_profiles = Some(event.clone()),
+            Event::Muted(_) | Event::Unmuted => self.last_mute = Some(event.clone()),
+            Event::Reports(_) => self.last_reports = Some(event.clone()),
+            Event::Message(m) => {
+                self.last_id = self.last_id.max(m.id);
+                self.recent.push_back(event.clone());
+            }
+            Event::Removed(id) => self
+                .recent
+                .retain(|e| !matches!(e, Event::Message(m) if m.id == *id)),
+            Event::Read(_) | Event::Topic(_) | Event::ModerationLog(_) => {
+                self.recent.push_back(event.clone())
+            }
+            _ => {}
+        }
+        while self.recent.len() > REPLAY_LEN {
+            self.recent.pop_front();
+        }
+    }
+
+    fn replay(&self) -> Vec<Event> {
+        self.last_state
+            .iter()
+            .chain(self.last_users.iter())
+            .chain(self.last_roles.iter())
+            .chain(self.last_profiles.iter())
+            .chain(self.last_mute.iter())
+            .chain(self.last_reports.iter())
+            .chain(self.recent.iter())
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use crate::services::protocol::MessageData;
+use crate::services::search;
+
+const SETTINGS_KEY: &str = "yewchat.content_filter";
+
+/// How much filtering a room gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FilterLevel {
+    #[default]
+    Off,
+    /// Blocked words are masked.
+    Words,
+    /// Blocked words are masked and images aren't embedded.
+    Strict,
+}
+
+impl FilterLevel {
+    pub const ALL: [FilterLevel; 3] = [FilterLevel::Off, FilterLevel::Words, FilterLevel::Strict];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FilterLevel::Off => "Off",
+            FilterLevel::Words => "Mask words",
+            FilterLevel::Strict => "Mask words and hide images",
+        }
+    }
+}
+
+/// A message as the filter lets it be shown.
+#[derive(Debug, PartialEq)]
+pub struct Filtered<'a> {
+    pub message: Cow<'a, MessageData>,
+    /// Show a placeholder instead of embedding the message if it's an image.
+    pub hide_images: bool,
+}
+
+/// What to keep off the screen, and where; applied to incoming messages
+/// just before they're rendered, so search and reports still see the
+/// originals. Kept in local storage, for this device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContentFilter {
+    /// Matched as whole words, ignoring case; `*` at the end matches any
+    /// ending, so `darn*` catches "darned" too.
+    pub blocked_words: Vec<String>,
+    /// Levels by room; rooms not listed are [`FilterLevel::Off`].
+    pub levels: HashMap<String, FilterLevel>,
+}
+
+impl ContentFilter {
+    pub fn load() -> Self {
+        LocalStorage::get(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = LocalStorage::set(SETTINGS_KEY, self) {
+            log::error!("failed to save the content filter: {:?}", e);
+        }
+    }
+
+    pub fn level(&self, room: &str) -> FilterLevel {
+        self.levels.get(room).copied().unwrap_or_default()
+    }
+
+    pub fn set_level(&mut self, room: &str, level: FilterLevel) {
+        match level {
+            FilterLevel::Off => self.levels.remove(room),
+            _ => self.levels.insert(room.to_string(), level),
+        };
+    }
+
+    /// Replaces the blocked words with those in `list`, separated by commas
+    /// or new lines.
+    pub fn set_blocked_words(&mut self, list: &str) {
+        self.blocked_words = list
+            .split([',', '\n'])
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty() && word != "*")
+            .collect();
+    }
+
+    /// `m` as it should be shown in its room.
+    pub fn apply<'a>(&self, m: &'a MessageData) -> Filtered<'a> {
+        let level = self.level(&m.room);
+        let hide_images = level == FilterLevel::Strict && search::is_image(&m.message);
+        if level == FilterLevel::Off || self.blocked_words.is_empty() {
+            return Filtered {
+                message: Cow::Borrowed(m),
+                hide_images,
+            };
+        }
+
+        let mut masked = Cow::Borrowed(m);
+        if let Cow::Owned(text) = self.mask(&m.message) {
+            masked.to_mut().message = text;
+        }
+        for (i, a) in m.attachments.iter().enumerate() {
+            if let Cow::Owned(title) = self.mask(&a.title) {
+                masked.to_mut().attachments[i].title = title;
+            }
+            if let Cow::Owned(text) = self.mask(&a.text) {
+                masked.to_mut().attachments[i].text = text;
+            }
+            for (j, f) in a.fields.iter().enumerate() {
+                if let Cow::Owned(value) = self.mask(&f.value) {
+                    masked.to_mut().attachments[i].fields[j].value = value;
+                }
+            }
+        }
+        Filtered {
+            message: masked,
+            hide_images,
+        }
+    }
+
+    /// `text` with every blocked word replaced by as many asterisks.
+    pub fn mask<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        let mut masked = String::new();
+        let mut changed = false;
+        let mut rest = text;
+        while let Some(start) = rest.find(is_word_char) {
+            let len = rest[start..]
+                .find(|c| !is_word_char(c))
+                .unwrap_or(rest.len() - start);
+            let word = &rest[start..start + len];
+            masked.push_str(&rest[..start]);
+            if self.is_blocked(word) {
+                masked.extend(word.chars().map(|_| '*'));
+                changed = true;
+            } else {
+                masked.push_str(word);
+            }
+            rest = &rest[start + len..];
+        }
+        if !changed {
+            return Cow::Borrowed(text);
+        }
+        masked.push_str(rest);
+        Cow::Owned(masked)
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.blocked_words
+            .iter()
+            .any(|blocked| match blocked.strip_suffix('*') {
+                Some(prefix) => word.starts_with(prefix),
+                None => word == *blocked,
+            })
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '\'' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::protocol::{Attachment, AttachmentField, DEFAULT_ROOM};
+
+    fn filter(words: &str, level: FilterLevel) -> ContentFilter {
+        let mut filter = ContentFilter::default();
+        filter.set_blocked_words(words);
+        filter.set_level(DEFAULT_ROOM, level);
+        filter
+    }
+
+    fn message(text: &str) -> MessageData {
+        MessageData {
+            id: 1,
+            room: DEFAULT_ROOM.to_string(),
+            from: "bob".to_string(),
+            to: None,
+            message: text.to_string(),
+            timestamp: 0.0,
+            bot: None,
+            attachments: vec![],
+            sealed: None,
+        }
+    }
+
+    #[test]
+    fn masks_whole_words_ignoring_case() {
+        let filter = filter("heck, darn*", FilterLevel::Words);
+        assert_eq!(
+            filter.mask("Oh HECK, that's darned good"),
+            "Oh ****, that's ****** good"
+        );
+        // Inside other words they're left alone.
+        assert_eq!(filter.mask("check the heckler"), "check the heckler");
+        assert!(matches!(filter.mask("nothing to see"), Cow::Borrowed(_)));
+        assert_eq!(filter.mask("h\u{e9}ck heck\u{e9}"), "h\u{e9}ck heck\u{e9}");
+    }
+
+    #[test]
+    fn parses_word_lists() {
+        let filter = filter(" Heck ,\n,*, darn* \n", FilterLevel::Words);
+        assert_eq!(filter.blocked_words, vec!["heck", "darn*"]);
+    }
+
+    #[test]
+    fn rooms_get_their_own_levels() {
+        let mut filter = filter("heck", FilterLevel::Words);
+        let mut elsewhere = message("heck");
+        elsewhere.room = "random".to_string();
+        assert_eq!(filter.apply(&message("heck")).message.message, "****");
+        assert_eq!(filter.apply(&elsewhere).message.message, "heck");
+
+        filter.set_level(DEFAULT_ROOM, FilterLevel::Off);
+        assert!(filter.levels.is_empty());
+        assert!(matches!(
+            filter.apply(&message("heck")).message,
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn strict_rooms_hide_images() {
+        let gif = message("https://example.com/cat.gif");
+        assert!(!filter("", FilterLevel::Words).apply(&gif).hide_images);
+        assert!(filter("", FilterLevel::Strict).apply(&gif).hide_images);
+        assert!(
+            !filter("", FilterLevel::Strict)
+                .apply(&message("cat"))
+                .hide_images
+        );
+    }
+
+    #[test]
+    fn masks_attachments_too() {
+        let mut m = message("deploy");
+        m.attachments.push(Attachment {
+            title: "heck".into(),
+            text: "what the heck".into(),
+            fields: vec![AttachmentField {
+                name: "status".into(),
+                value: "heck no".into(),
+                short: true,
+            }],
+            ..Attachment::default()
+        });
+        let shown = filter("heck", FilterLevel::Words)
+            .apply(&m)
+            .message
+            .into_owned();
+        assert_eq!(shown.message, "deploy");
+        let card = &shown.attachments[0];
+        assert_eq!(
+            (card.title.as_str(), card.text.as_str()),
+            ("****", "what the ****")
+        );
+        assert_eq!(card.fields[0].value, "**** no");
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+
+use gloo::storage::{LocalStorage, Storage};
+use js_sys::{Array, Object, Promise, Reflect, Uint8Array, JSON};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{CryptoKey, CryptoKeyPair, SubtleCrypto};
+
+use crate::services::protocol::{PublicKeys, Sealed};
+
+/// AES-GCM nonce length, in bytes.
+const IV_BYTES: usize = 12;
+/// How much of a key's SHA-256 is shown as its fingerprint, in bytes.
+const FINGERPRINT_BYTES: usize = 16;
+
+/// The conversation of encrypted direct messages with `peer`. It's kept
+/// apart from the rooms, under a name no room can have.
+pub fn direct_room(peer: &str) -> String {
+    format!("@{}", peer)
+}
+
+/// Who a conversation from [`direct_room`] is with.
+pub fn peer_of(room: &str) -> Option<&str> {
+    room.strip_prefix('@')
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// A key's SHA-256 as people read it out to each other: the first
+/// [`FINGERPRINT_BYTES`] in groups of four hex digits.
+pub fn format_fingerprint(digest: &[u8]) -> String {
+    let hex = to_hex(&digest[..digest.len().min(FINGERPRINT_BYTES)]);
+    hex.as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// What the user has to go on that a key really is the other person's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    /// First seen, or the same as before, but never checked.
+    Unverified,
+    /// The user compared fingerprints with them.
+    Verified,
+    /// Not the key they had before, so someone may be in the middle.
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PinnedKey {
+    key: String,
+    verified: bool,
+}
+
+/// The keys of the people the user talks to privately, pinned the first time
+/// they're used so a different one later stands out. Kept in local storage
+/// per account.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyRing {
+    pinned: BTreeMap<String, PinnedKey>,
+}
+
+impl KeyRing {
+    fn storage_key(account: &str) -> String {
+        format!("yewchat.keyring.{}", account)
+    }
+
+    pub fn load(account: &str) -> Self {
+        LocalStorage::get(Self::storage_key(account)).unwrap_or_default()
+    }
+
+    pub fn save(&self, account: &str) {
+        if let Err(e) = LocalStorage::set(Self::storage_key(account), self) {
+            log::error!("failed to save the key ring: {:?}", e);
+        }
+    }
+
+    pub fn trust(&self, user: &str, key: &str) -> Trust {
+        match self.pinned.get(user) {
+            Some(pinned) if pinned.key != key => Trust::Changed,
+            Some(pinned) if pinned.verified => Trust::Verified,
+            _ => Trust::Unverified,
+        }
+    }
+
+    /// Pins `key` for `user` unless they already have one; returns `false`
+    /// if that's a different key.
+    pub fn pin(&mut self, user: &str, key: &str) -> bool {
+        let pinned = self
+            .pinned
+            .entry(user.to_string())
+            .or_insert_with(|| PinnedKey {
+                key: key.to_string(),
+                verified: false,
+            });
+        pinned.key == key
+    }
+
+    /// The people in `published` whose key isn't the one pinned for them.
+    pub fn changed<'a>(&'a self, published: &'a PublicKeys) -> impl Iterator<Item = &'a str> {
+        published
+            .iter()
+            .filter(|(user, key)| self.trust(user, key) == Trust::Changed)
+            .map(|(user, _)| user.as_str())
+    }
+
+    /// Pins `key` for `user` as checked, replacing any other.
+    pub fn verify(&mut self, user: &str, key: &str) {
+        let pinned = PinnedKey {
+            key: key.to_string(),
+            verified: true,
+        };
+        self.pinned.insert(user.to_string(), pinned);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    /// The private key as a JWK.
+    private_key: String,
+    public_key: String,
+}
+
+/// The user's ECDH key pair, made the first time they sign in under a name
+/// and kept in local storage from then on, so only this browser can read
+/// what's sent to them.
+pub struct Identity {
+    private_key: CryptoKey,
+    /// Raw, in hex, as published to the server.
+    pub public_key: String,
+}
+
+impl Identity {
+    fn storage_key(account: &str) -> String {
+        format!("yewchat.identity.{}", account)
+    }
+
+    pub async fn load_or_create(account: &str) -> Result<Identity, String> {
+        if let Ok(stored) = LocalStorage::get::<StoredIdentity>(Self::storage_key(account)) {
+            let jwk = JSON::parse(&stored.private_key).map_err(js_error)?;
+            let private_key = call(subtle()?.import_key_with_object(
+                "jwk",
+                jwk.unchecked_ref(),
+                &ecdh(),
+                false,
+                &usages(&["deriveKey"]),
+            ))
+            .await?;
+            return Ok(Identity {
+                private_key: private_key.unchecked_into(),
+                public_key: stored.public_key,
+            });
+        }
+
+        let pair: CryptoKeyPair =
+            call(subtle()?.generate_key_with_object(&ecdh(), true, &usages(&["deriveKey"])))
+                .await?
+                .unchecked_into();
+        let raw = call(subtle()?.export_key("raw", &pair.get_public_key())).await?;
+        let jwk = call(subtle()?.export_key("jwk", &pair.get_private_key())).await?;
+        let stored = StoredIdentity {
+            private_key: JSON::stringify(&jwk).map_err(js_error)?.into(),
+            public_key: to_hex(&Uint8Array::new(&raw).to_vec()),
+        };
+        LocalStorage::set(Self::storage_key(account), &stored).map_err(|e| e.to_string())?;
+        Ok(Identity {
+            private_key: pair.get_private_key(),
+            public_key: stored.public_key,
+        })
+    }
+
+    /// Encrypts `text` from `from` to `to`, whose public key is `their_key`.
+    pub async fn seal(
+        &self,
+        text: &str,
+        from: &str,
+        to: &str,
+        their_key: &str,
+    ) -> Result<Sealed, String> {
+        let key = self.shared_key(their_key).await?;
+        let mut iv = [0u8; IV_BYTES];
+        gloo::utils::window()
+            .crypto()
+            .and_then(|crypto| crypto.get_random_values_with_u8_array(&mut iv))
+            .map_err(js_error)?;
+        let ciphertext = call(subtle()?.encrypt_with_object_and_u8_array(
+            &aes_gcm(&iv, from, to),
+            &key,
+            text.as_bytes(),
+        ))
+        .await?;
+        Ok(Sealed {
+            sender_key: self.public_key.clone(),
+            recipient_key: their_key.to_string(),
+            iv: to_hex(&iv),
+            ciphertext: to_hex(&Uint8Array::new(&ciphertext).to_vec()),
+        })
+    }
+
+    /// Decrypts a message the server says went from `from` to `to`; fails if
+    /// it didn't, or was changed on the way.
+    pub async fn open(&self, sealed: &Sealed, from: &str, to: &str) -> Result<String, String> {
+        let their_key = if sealed.sender_key == self.public_key {
+            &sealed.recipient_key
+        } else if sealed.recipient_key == self.public_key {
+            &sealed.sender_key
+        } else {
+            return Err("sealed for a key this browser doesn't have".to_string());
+        };
+        let key = self.shared_key(their_key).await?;
+        let iv = from_hex(&sealed.iv).ok_or("malformed iv")?;
+        let ciphertext = from_hex(&sealed.ciphertext).ok_or("malformed ciphertext")?;
+        let plaintext = call(subtle()?.decrypt_with_object_and_u8_array(
+            &aes_gcm(&iv, from, to),
+            &key,
+            &ciphertext,
+        ))
+        .await?;
+        String::from_utf8(Uint8Array::new(&plaintext).to_vec()).map_err(|e| e.to_string())
+    }
+
+    /// The AES-GCM key this user and the owner of `their_key` agree on.
+    async fn shared_key(&self, their_key: &str) -> Result<CryptoKey, String> {
+        let raw = from_hex(their_key).ok_or("malformed public key")?;
+        let public_key = call(subtle()?.import_key_with_object(
+            "raw",
+            &Uint8Array::from(raw.as_slice()),
+            &ecdh(),
+            true,
+            &Array::new(),
+        ))
+        .await?;
+        let algorithm = object(&[("name", "ECDH".into()), ("public", public_key)]);
+        let aes = object(&[("name", "AES-GCM".into()), ("length", 256.into())]);
+        let key = call(subtle()?.derive_key_with_object_and_object(
+            &algorithm,
+            &self.private_key,
+            &aes,
+            false,
+            &usages(&["encrypt", "decrypt"]),
+        ))
+        .await?;
+        Ok(key.unchecked_into())
+    }
+}
+
+/// The fingerprint of a public key in hex, for people to compare.
+pub async fn fingerprint(key: &str) -> Result<String, String> {
+    let raw = from_hex(key).ok_or("malformed public key")?;
+    let digest = call(subtle()?.digest_with_str_and_u8_array("SHA-256", &raw)).await?;
+    Ok(format_fingerprint(&Uint8Array::new(&digest).to_vec()))
+}
+
+fn subtle() -> Result<SubtleCrypto, String> {
+    let crypto = gloo::utils::window().crypto().map_err(js_error)?;
+    Ok(crypto.subtle())
+}
+
+async fn call(promise: Result<Promise, JsValue>) -> Result<JsValue, String> {
+    JsFuture::from(promise.map_err(js_error)?)
+        .await
+        .map_err(js_error)
+}
+
+fn js_error(e: JsValue) -> String {
+    format!("{:?}", e)
+}
+
+fn object(fields: &[(&str, JsValue)]) -> Object {
+    let object = Object::new();
+    for (name, value) in fields {
+        Reflect::set(&object, &JsValue::from_str(name), value).unwrap();
+    }
+    object
+}
+
+fn usages(names: &[&str]) -> Array {
+    names.iter().map(|name| JsValue::from_str(name)).collect()
+}
+
+fn ecdh() -> Object {
+    object(&[("name", "ECDH".into()), ("namedCurve", "P-256".into())])
+}
+
+/// Binding who a message is from and to into it, so the server can't pass
+/// it off as someone else's.
+fn aes_gcm(iv: &[u8], from: &str, to: &str) -> Object {
+    let additional_data = format!("{}\n{}", from, to);
+    object(&[
+        ("name", "AES-GCM".into()),
+        ("iv", Uint8Array::from(iv).into()),
+        (
+            "additionalData",
+            Uint8Array::from(additional_data.as_bytes()).into(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0x04, 0xab, 0x00]), "04ab00");
+        assert_eq!(from_hex("04AB00"), Some(vec![0x04, 0xab, 0x00]));
+        assert_eq!(from_hex("04a"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("\u{e9}a"), None);
+    }
+
+    #[test]
+    fn fingerprints_are_grouped() {
+        let digest: Vec<u8> = (0..32).collect();
+        assert_eq!(
+            format_fingerprint(&digest),
+            "0001 0203 0405 0607 0809 0a0b 0c0d 0e0f"
+        );
+    }
+
+    #[test]
+    fn direct_conversations_are_named_after_the_peer() {
+        assert_eq!(direct_room("bob"), "@bob");
+        assert_eq!(peer_of("@bob"), Some("bob"));
+        assert_eq!(peer_of("general"), None);
+    }
+
+    #[test]
+    fn keys_are_pinned_on_first_use() {
+        let mut ring = KeyRing::default();
+        assert_eq!(ring.trust("bob", "04aa"), Trust::Unverified);
+        assert!(ring.pin("bob", "04aa"));
+        assert!(ring.pin("bob", "04aa"));
+        assert!(!ring.pin("bob", "04bb"));
+        assert_eq!(ring.trust("bob", "04bb"), Trust::Changed);
+
+        let published = PublicKeys::from([
+            ("bob".to_string(), "04bb".to_string()),
+            ("carol".to_string(), "04cc".to_string()),
+        ]);
+        assert_eq!(ring.changed(&published).collect::<Vec<_>>(), vec!["bob"]);
+
+        ring.verify("bob", "04bb");
+        assert_eq!(ring.trust("bob", "04bb"), Trust::Verified);
+        assert_eq!(ring.trust("bob", "04aa"), Trust::Changed);
+    }
+}
@@ -1,47 +1,304 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
+use crate::services::protocol::{
+    MessageData, ModerationEntry, MsgTypes, Profile, PublicKeys, ReadData, Report, Role, Sanction,
+    SearchResultsData, TopicData, TypingData, WebSocketMessage, WebhookList,
+};
+
+/// Channels subscribers can listen on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Users,
+    Messages,
+    Rooms,
+    ReadReceipts,
+    Typing,
+    Search,
+    Connection,
+    Errors,
+    Sync,
+    Moderation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+/// State other tabs of the same user keep in step with, without involving
+/// the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SyncEvent {
+    Read(ReadData),
+    Draft { room: String, text: String },
+}
+
+/// Everything published on the bus: decoded server frames as well as events
+/// raised inside the app.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Everyone online, and the keys of those who can get encrypted direct
+    /// messages.
+    Users(Vec<String>, PublicKeys),
+    /// Everyone who isn't a plain member.
+    Roles(BTreeMap<String, Role>),
+    /// Everyone who has told the server about themselves.
+    Profiles(BTreeMap<String, Profile>),
+    Message(MessageData),
+    /// A moderator took down the message with this id.
+    Removed(u64),
+    Read(ReadData),
+    Typing(TypingData),
+    Topic(TopicData),
+    Webhooks(WebhookList),
+    SearchResults(SearchResultsData),
+    Connection(ConnectionState),
+    /// Heartbeat reply carrying the send time of its ping. The socket turns
+    /// it into [`Event::Latency`] before anyone else sees it.
+    Pong(f64),
+    /// Round-trip time to the server in milliseconds.
+    Latency(f64),
+    /// The server dropped a message for going over the rate limit, and
+    /// wants no more for this many milliseconds.
+    SlowDown(f64),
+    Error(String),
+    Sync(SyncEvent),
+    Muted(Sanction),
+    Unmuted,
+    /// The server is about to close the connection on us.
+    Kicked(Sanction),
+    /// The server turned our name down, saying why, and is closing the
+    /// connection.
+    Refused(String),
+    /// The token that proves our name is ours, the first time we use it.
+    /// [`SharedConnection`](crate::services::shared_connection::SharedConnection)
+    /// keeps it rather than passing it on.
+    Session(String),
+    ModerationLog(Vec<ModerationEntry>),
+    /// Every open report; only moderators get these.
+    Reports(Vec<Report>),
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Users(..) | Event::Roles(_) | Event::Profiles(_) => Topic::Users,
+            Event::Message(_) | Event::Removed(_) => Topic::Messages,
+            Event::Read(_) => Topic::ReadReceipts,
+            Event::Typing(_) => Topic::Typing,
+            Event::Topic(_) | Event::Webhooks(_) => Topic::Rooms,
+            Event::SearchResults(_) => Topic::Search,
+            Event::Connection(_)
+            | Event::Pong(_)
+            | Event::Latency(_)
+            | Event::SlowDown(_)
+            | Event::Session(_) => Topic::Connection,
+            Event::Error(_) => Topic::Errors,
+            Event::Sync(_) => Topic::Sync,
+            Event::Muted(_)
+            | Event::Unmuted
+            | Event::Kicked(_)
+            | Event::Refused(_)
+            | Event::ModerationLog(_)
+            | Event::Reports(_) => Topic::Moderation,
+        }
+    }
+
+    /// Decodes a frame received from the server. Frames whose data fails to
+    /// parse become an [`Event::Error`]; frame types the client has no use
+    /// for yield `None`.
+    pub fn from_message(msg: WebSocketMessage) -> Option<Event> {
+        let decoded = match msg.message_type {
+            MsgTypes::Users => {
+                // Servers from before encrypted messages send no keys.
+                let keys = match msg.data {
+                    None => Ok(PublicKeys::new()),
+                    data => decode_data(data),
+                };
+                keys.map(|keys| Event::Users(msg.data_array.unwrap_or_default(), keys))
+            }
+            MsgTypes::Message => decode_data(msg.data).map(Event::Message),
+            MsgTypes::Read => decode_data(msg.data).map(Event::Read),
+            MsgTypes::Typing => decode_data(msg.data).map(Event::Typing),
+            MsgTypes::Topic => decode_data(msg.data).map(Event::Topic),
+            MsgTypes::Webhooks => decode_data(msg.data).map(Event::Webhooks),
+            MsgTypes::SearchResults => decode_data(msg.data).map(Event::SearchResults),
+            MsgTypes::Pong => msg
+                .data
+                .and_then(|d| d.parse().ok())
+                .map(Event::Pong)
+                .ok_or_else(|| "malformed pong".to_string()),
+            MsgTypes::SlowDown => msg
+                .data
+                .and_then(|d| d.parse().ok())
+                .map(Event::SlowDown)
+                .ok_or_else(|| "malformed slow down".to_string()),
+            MsgTypes::Roles => decode_data(msg.data).map(Event::Roles),
+            MsgTypes::Profiles => decode_data(msg.data).map(Event::Profiles),
+            MsgTypes::Removed => msg
+                .data
+                .and_then(|d| d.parse().ok())
+                .map(Event::Removed)
+                .ok_or_else(|| "malformed removal".to_string()),
+            MsgTypes::Muted => decode_data(msg.data).map(Event::Muted),
+            MsgTypes::Unmuted => Ok(Event::Unmuted),
+            MsgTypes::Kicked => decode_data(msg.data).map(Event::Kicked),
+            MsgTypes::Refused => Ok(Event::Refused(msg.data.unwrap_or_default())),
+            MsgTypes::Session => msg
+                .data
+                .map(Event::Session)
+                .ok_or_else(|| "session without a token".to_string()),
+            MsgTypes::ModerationLog => decode_data(msg.data).map(Event::ModerationLog),
+            MsgTypes::Reports => decode_data(msg.data).map(Event::Reports),
+            MsgTypes::Register
+            | MsgTypes::RegisterBot
+            | MsgTypes::BotMessage
+            | MsgTypes::Interaction
+            | MsgTypes::CreateWebhook
+            | MsgTypes::RevokeWebhook
+            | MsgTypes::ListWebhooks
+            | MsgTypes::Moderate
+            | MsgTypes::Report
+            | MsgTypes::PublicKey
+            | MsgTypes::Sealed
+            | MsgTypes::Profile
+            | MsgTypes::Search
+            | MsgTypes::Ping
+            | MsgTypes::Encoding => return None,
+        };
+        Some(decoded.unwrap_or_else(Event::Error))
+    }
+}
+
+fn decode_data<T: serde::de::DeserializeOwned>(data: Option<String>) -> Result<T, String> {
+    let data = data.ok_or_else(|| "frame is missing its data".to_string())?;
+    serde_json::from_str(&data).map_err(|e| format!("malformed frame data: {}", e))
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Request {
-    EventBusMsg(String),
+    /// Start receiving events on these topics, in addition to any already
+    /// subscribed to.
+    Subscribe(Vec<Topic>),
+    Unsubscribe(Vec<Topic>),
+    Publish(Event),
 }
 
 pub struct EventBus {
     link: AgentLink<EventBus>,
-    subscribers: HashSet<HandlerId>,
+    subscribers: HashMap<HandlerId, HashSet<Topic>>,
 }
 
 impl Agent for EventBus {
     type Reach = Context<Self>;
     type Message = ();
     type Input = Request;
-    type Output = String;
+    type Output = Event;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
             link,
-            subscribers: HashSet::new(),
+            subscribers: HashMap::new(),
         }
     }
 
     fn update(&mut self, _msg: Self::Message) {}
 
-    fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
+    fn handle_input(&mut self, msg: Self::Input, id: HandlerId) {
         match msg {
-            Request::EventBusMsg(s) => {
-                for sub in self.subscribers.iter() {
-                    self.link.respond(*sub, s.clone())
+            Request::Subscribe(topics) => {
+                self.subscribers.entry(id).or_default().extend(topics);
+            }
+            Request::Unsubscribe(topics) => {
+                if let Some(subscribed) = self.subscribers.get_mut(&id) {
+                    for topic in topics {
+                        subscribed.remove(&topic);
+                    }
+                }
+            }
+            Request::Publish(event) => {
+                let topic = event.topic();
+                for (sub, topics) in self.subscribers.iter() {
+                    if sub.is_respondable() && topics.contains(&topic) {
+                        self.link.respond(*sub, event.clone())
+                    }
                 }
             }
         }
     }
 
     fn connected(&mut self, id: HandlerId) {
-        self.subscribers.insert(id);
+        self.subscribers.insert(id, HashSet::new());
     }
 
     fn disconnected(&mut self, id: HandlerId) {
         self.subscribers.remove(&id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Event {
+        fn from_frame(frame: &str) -> Option<Event> {
+            Event::from_message(serde_json::from_str(frame).unwrap())
+        }
+    }
+
+    #[test]
+    fn decodes_server_frames() {
+        let users = r#"{"messageType":"users","dataArray":["alice","bob"]}"#;
+        assert_eq!(
+            Event::from_frame(users),
+            Some(Event::Users(vec!["alice".into(), "bob".into()], PublicKeys::new()))
+        );
+        let users = r#"{"messageType":"users","dataArray":["alice"],"data":"{\"alice\":\"04ab\"}"}"#;
+        let keys = PublicKeys::from([("alice".to_string(), "04ab".to_string())]);
+        assert_eq!(Event::from_frame(users), Some(Event::Users(vec!["alice".into()], keys)));
+
+        let message = r#"{"messageType":"message","data":"{\"from\":\"bob\",\"message\":\"hi\"}"}"#;
+        match Event::from_frame(message) {
+            Some(Event::Message(m)) => assert_eq!((m.from.as_str(), m.message.as_str()), ("bob", "hi")),
+            other => panic!("expected a message, got {:?}", other),
+        }
+
+        let pong = r#"{"messageType":"pong","data":"1234.5"}"#;
+        assert_eq!(Event::from_frame(pong), Some(Event::Pong(1234.5)));
+        assert_eq!(Event::from_frame(pong).unwrap().topic(), Topic::Connection);
+
+        let slow_down = r#"{"messageType":"slowdown","data":"800"}"#;
+        assert_eq!(Event::from_frame(slow_down), Some(Event::SlowDown(800.0)));
+
+        let roles = r#"{"messageType":"roles","data":"{\"alice\":\"owner\"}"}"#;
+        let owner = BTreeMap::from([("alice".to_string(), Role::Owner)]);
+        assert_eq!(Event::from_frame(roles), Some(Event::Roles(owner)));
+        let profiles = r#"{"messageType":"profiles","data":"{\"alice\":{\"pronouns\":\"she/her\"}}"}"#;
+        match Event::from_frame(profiles) {
+            Some(Event::Profiles(profiles)) => assert_eq!(profiles["alice"].pronouns, "she/her"),
+            other => panic!("expected profiles, got {:?}", other),
+        }
+        let removed = r#"{"messageType":"removed","data":"42"}"#;
+        assert_eq!(Event::from_frame(removed).unwrap().topic(), Topic::Messages);
+        let kicked = r#"{"messageType":"kicked","data":"{\"by\":\"alice\",\"banned\":true}"}"#;
+        match Event::from_frame(kicked) {
+            Some(Event::Kicked(sanction)) => assert!(sanction.banned && sanction.reason.is_empty()),
+            other => panic!("expected a kick, got {:?}", other),
+        }
+        let refused = r#"{"messageType":"refused","data":"That name is taken."}"#;
+        assert_eq!(Event::from_frame(refused).unwrap().topic(), Topic::Moderation);
+        let session = r#"{"messageType":"session","data":"9f2c"}"#;
+        assert_eq!(Event::from_frame(session), Some(Event::Session("9f2c".into())));
+    }
+
+    #[test]
+    fn bad_frames_become_errors() {
+        let no_data = r#"{"messageType":"message"}"#;
+        assert!(matches!(Event::from_frame(no_data), Some(Event::Error(_))));
+        // Frames only the server acts on are dropped.
+        assert_eq!(Event::from_frame(r#"{"messageType":"ping","data":"1"}"#), None);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeSet;
+
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+/// People the user doesn't want to hear from: their messages are hidden,
+/// and they raise no notifications or typing indicators. Kept in local
+/// storage per account, and never told to the server or the people on it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IgnoreList {
+    users: BTreeSet<String>,
+}
+
+impl IgnoreList {
+    fn key(account: &str) -> String {
+        format!("yewchat.ignored.{}", account)
+    }
+
+    pub fn load(account: &str) -> Self {
+        LocalStorage::get(Self::key(account)).unwrap_or_default()
+    }
+
+    pub fn save(&self, account: &str) {
+        if let Err(e) = LocalStorage::set(Self::key(account), self) {
+            log::error!("failed to save the ignore list: {:?}", e);
+        }
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains(user)
+    }
+
+    /// Starts ignoring `user`; returns `false` if they already were.
+    pub fn ignore(&mut self, user: &str) -> bool {
+        let user = user.trim().trim_start_matches('@');
+        !user.is_empty() && self.users.insert(user.to_string())
+    }
+
+    /// Returns `false` if `user` wasn't ignored.
+    pub fn unignore(&mut self, user: &str) -> bool {
+        self.users.remove(user)
+    }
+
+    /// In name order.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.users.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_each_name_once() {
+        let mut list = IgnoreList::default();
+        assert!(list.ignore("@mallory "));
+        assert!(!list.ignore("mallory"));
+        assert!(!list.ignore("  "));
+        assert!(list.ignore("eve"));
+        assert!(list.contains("mallory"));
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec!["eve", "mallory"]);
+
+        assert!(list.unignore("mallory"));
+        assert!(!list.unignore("mallory"));
+        assert!(!list.contains("mallory"));
+    }
+}
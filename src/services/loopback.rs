@@ -0,0 +1,168 @@
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::{LocalBoxStream, StreamExt};
+
+use crate::services::protocol::{Credentials, MessageData, MsgTypes, TopicData, WebSocketMessage};
+use crate::services::transport::{Payload, Transport, TransportEvent};
+
+/// Just enough of a server to chat with yourself: registering lists you as
+/// the only user, messages come straight back and pings are answered.
+#[derive(Default)]
+pub struct Loopback {
+    username: Option<String>,
+    last_id: u64,
+}
+
+impl Loopback {
+    /// Replies to a frame the client sent at time `now`.
+    pub fn reply(&mut self, frame: &str, now: f64) -> Vec<WebSocketMessage> {
+        let msg: WebSocketMessage = match serde_json::from_str(frame) {
+            Ok(msg) => msg,
+            Err(_) => return vec![],
+        };
+        match msg.message_type {
+            MsgTypes::Register => {
+                let credentials = msg.data.and_then(|d| serde_json::from_str(&d).ok());
+                self.username = credentials.map(|c: Credentials| c.name);
+                vec![WebSocketMessage {
+                    message_type: MsgTypes::Users,
+                    data: None,
+                    data_array: Some(self.username.iter().cloned().collect()),
+                }]
+            }
+            MsgTypes::Message => {
+                let from = match &self.username {
+                    Some(username) => username.clone(),
+                    None => return vec![],
+                };
+                let (room, to) = msg.chat_target();
+                self.last_id += 1;
+                let data = MessageData {
+                    id: self.last_id,
+                    room,
+                    from,
+                    to,
+                    message: msg.data.unwrap_or_default(),
+                    timestamp: now,
+                    bot: None,
+                    attachments: vec![],
+                    sealed: None,
+                };
+                vec![WebSocketMessage {
+                    message_type: MsgTypes::Message,
+                    data: Some(serde_json::to_string(&data).unwrap()),
+                    data_array: None,
+                }]
+            }
+            MsgTypes::Ping => vec![WebSocketMessage {
+                message_type: MsgTypes::Pong,
+                ..msg
+            }],
+            MsgTypes::Read => vec![msg],
+            MsgTypes::Topic => {
+                let topic = msg
+                    .data
+                    .and_then(|d| serde_json::from_str::<TopicData>(&d).ok());
+                match (topic, &self.username) {
+                    (Some(topic), Some(username)) => {
+                        let topic = TopicData {
+                            set_by: username.clone(),
+                            ..topic
+                        };
+                        vec![WebSocketMessage {
+                            message_type: MsgTypes::Topic,
+                            data: Some(serde_json::to_string(&topic).unwrap()),
+                            data_array: None,
+                        }]
+                    }
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Talks to a [`Loopback`] in the page instead of a server, for tests and
+/// for trying the app out offline.
+#[derive(Default)]
+pub struct LoopbackTransport {
+    server: Loopback,
+    events: Option<UnboundedSender<TransportEvent>>,
+}
+
+impl Transport for LoopbackTransport {
+    fn connect(&mut self) -> LocalBoxStream<'static, TransportEvent> {
+        let (events, incoming) = mpsc::unbounded();
+        let _ = events.unbounded_send(TransportEvent::Open);
+        self.events = Some(events);
+        incoming.boxed_local()
+    }
+
+    fn send(&mut self, frame: Payload) -> Result<(), String> {
+        let frame = match frame {
+            Payload::Text(text) => text,
+            Payload::Binary(_) => return Err("loopback only speaks JSON".to_string()),
+        };
+        let events = self.events.as_ref().ok_or("not connected")?;
+        for reply in self.server.reply(&frame, js_sys::Date::now()) {
+            let reply = serde_json::to_string(&reply).unwrap();
+            let _ = events.unbounded_send(TransportEvent::Frame(Payload::Text(reply)));
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, code: u16, reason: &str) {
+        if let Some(events) = self.events.take() {
+            let _ = events.unbounded_send(TransportEvent::Closed {
+                code,
+                reason: reason.to_string(),
+                clean: true,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message_type: MsgTypes, data: &str) -> String {
+        serde_json::to_string(&WebSocketMessage {
+            message_type,
+            data: Some(data.to_string()),
+            data_array: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn echoes_messages_from_the_registered_user() {
+        let mut server = Loopback::default();
+        assert!(server
+            .reply(&frame(MsgTypes::Message, "too early"), 1.0)
+            .is_empty());
+
+        let register = serde_json::to_string(&WebSocketMessage::register("alice", None)).unwrap();
+        let users = server.reply(&register, 1.0);
+        assert_eq!(users[0].data_array, Some(vec!["alice".to_string()]));
+
+        let echoed: Vec<MessageData> = ["one", "two"]
+            .iter()
+            .flat_map(|text| server.reply(&frame(MsgTypes::Message, text), 42.0))
+            .map(|m| serde_json::from_str(&m.data.unwrap()).unwrap())
+            .collect();
+        assert_eq!((echoed[0].id, echoed[1].id), (1, 2));
+        assert_eq!(echoed[1].from, "alice");
+        assert_eq!(echoed[1].message, "two");
+        assert_eq!(echoed[1].timestamp, 42.0);
+    }
+
+    #[test]
+    fn answers_pings() {
+        let mut server = Loopback::default();
+        let pong = server.reply(&frame(MsgTypes::Ping, "12"), 0.0);
+        assert_eq!(pong[0].message_type, MsgTypes::Pong);
+        assert_eq!(pong[0].data.as_deref(), Some("12"));
+        assert!(server.reply("garbage", 0.0).is_empty());
+    }
+}
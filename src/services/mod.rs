@@ -1,2 +1,19 @@
-pub mod websocket;
+pub mod avatar;
+pub mod commands;
+pub mod connection;
+pub mod connection_worker;
+pub mod content_filter;
+pub mod crypto;
 pub mod event_bus;
+pub mod ignore_list;
+pub mod loopback;
+pub mod notifications;
+pub mod profiles;
+pub mod protocol;
+pub mod read_receipts;
+pub mod search;
+pub mod shared_connection;
+pub mod sse;
+pub mod transport;
+pub mod virtual_list;
+pub mod websocket;
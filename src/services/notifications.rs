@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{AudioContext, Notification, NotificationOptions, NotificationPermission};
+use yew::Callback;
+
+const SETTINGS_KEY: &str = "yewchat.notifications";
+/// Title of the page when there is nothing unread, matches `static/index.html`.
+pub const APP_TITLE: &str = "Yewchat!";
+
+/// User preferences for background notifications, kept in local storage.
+#[derive(Default, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    pub sound: bool,
+    pub muted_rooms: HashSet<String>,
+}
+
+impl NotificationSettings {
+    pub fn load() -> Self {
+        LocalStorage::get(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = LocalStorage::set(SETTINGS_KEY, self) {
+            log::error!("failed to save notification settings: {:?}", e);
+        }
+    }
+
+    pub fn is_muted(&self, room: &str) -> bool {
+        self.muted_rooms.contains(room)
+    }
+
+    pub fn toggle_mute(&mut self, room: &str) {
+        if !self.muted_rooms.remove(room) {
+            self.muted_rooms.insert(room.to_string());
+        }
+    }
+}
+
+/// Whether `text` mentions `username` as `@username`.
+pub fn is_mention(text: &str, username: &str) -> bool {
+    let needle = format!("@{}", username.to_lowercase());
+    let text = text.to_lowercase();
+    text.match_indices(&needle).any(|(i, _)| {
+        let rest = &text[i + needle.len()..];
+        !rest
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+thread_local! {
+    /// Made on the first chime and kept, as browsers only allow a few.
+    static AUDIO: RefCell<Option<AudioContext>> = const { RefCell::new(None) };
+}
+
+/// Whether this browser has notifications at all; some mobile ones don't,
+/// and `Notification.permission` throws there.
+fn supported() -> bool {
+    js_sys::Reflect::has(&gloo::utils::window(), &"Notification".into()).unwrap_or(false)
+}
+
+/// The permission to notify, which is never granted where notifications
+/// aren't supported.
+pub fn permission() -> NotificationPermission {
+    if !supported() {
+        return NotificationPermission::Denied;
+    }
+    Notification::permission()
+}
+
+/// Asks the browser for permission to show notifications and reports the
+/// outcome once the user has answered the prompt.
+pub fn request_permission(callback: Callback<NotificationPermission>) {
+    if !supported() {
+        callback.emit(NotificationPermission::Denied);
+        return;
+    }
+    let promise = match Notification::request_permission() {
+        Ok(promise) => promise,
+        Err(e) => {
+            log::error!("notifications unavailable: {:?}", e);
+            return;
+        }
+    };
+    spawn_local(async move {
+        if let Err(e) = JsFuture::from(promise).await {
+            log::error!("notification permission request failed: {:?}", e);
+        }
+        callback.emit(Notification::permission());
+    });
+}
+
+/// Shows a notification that brings the chat tab to the front when clicked.
+pub fn notify(title: &str, body: &str, icon: &str) {
+    if permission() != NotificationPermission::Granted {
+        return;
+    }
+    let options = NotificationOptions::new();
+    options.set_body(body);
+    options.set_icon(icon);
+    match Notification::new_with_options(title, &options) {
+        Ok(notification) => {
+            let onclick = Closure::once_into_js(|| {
+                if let Err(e) = gloo::utils::window().focus() {
+                    log::error!("failed to focus window: {:?}", e);
+                }
+            });
+            notification.set_onclick(Some(onclick.unchecked_ref()));
+        }
+        Err(e) => log::error!("failed to show notification: {:?}", e),
+    }
+}
+
+/// Plays a short chime; no audio file needed.
+pub fn play_sound() {
+    if let Err(e) = chime() {
+        log::error!("failed to play notification sound: {:?}", e);
+    }
+}
+
+fn chime() -> Result<(), JsValue> {
+    let ctx = AUDIO.with(|audio| {
+        let mut audio = audio.borrow_mut();
+        match &*audio {
+            Some(ctx) => Ok::<_, JsValue>(ctx.clone()),
+            None => Ok(audio.insert(AudioContext::new()?).clone()),
+        }
+    })?;
+    let oscillator = ctx.create_oscillator()?;
+    let gain = ctx.create_gain()?;
+    let now = ctx.current_time();
+
+    oscillator.frequency().set_value(880.0);
+    gain.gain().set_value(0.2);
+    gain.gain()
+        .exponential_ramp_to_value_at_time(0.001, now + 0.3)?;
+    oscillator.connect_with_audio_node(&gain)?;
+    gain.connect_with_audio_node(&ctx.destination())?;
+    oscillator.start()?;
+    oscillator.stop_with_when(now + 0.3)?;
+    Ok(())
+}
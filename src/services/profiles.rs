@@ -0,0 +1,91 @@
+use gloo::storage::{LocalStorage, Storage};
+use js_sys::{Array, Date, Function, Object, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::services::avatar;
+use crate::services::protocol::Profile;
+
+/// The image to show for `username`, drawn here rather than fetched so
+/// nobody else learns who's chatting.
+pub fn avatar_url(username: &str, profile: Option<&Profile>) -> String {
+    avatar::avatar(username, &display_name(username, profile), profile)
+}
+
+/// What to call `username`: their display name if they've set one.
+pub fn display_name(username: &str, profile: Option<&Profile>) -> String {
+    match profile.map(|p| p.display_name.trim()) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => username.to_string(),
+    }
+}
+
+fn storage_key(account: &str) -> String {
+    format!("yewchat.profile.{}", account)
+}
+
+/// The profile the user last saved under `account`, to tell the server
+/// again whenever it has forgotten.
+pub fn load_own(account: &str) -> Profile {
+    LocalStorage::get(storage_key(account)).unwrap_or_default()
+}
+
+pub fn save_own(account: &str, profile: &Profile) {
+    if let Err(e) = LocalStorage::set(storage_key(account), profile) {
+        log::error!("failed to save the profile: {:?}", e);
+    }
+}
+
+/// The time zone this browser is set to, like `Asia/Jakarta`.
+pub fn device_time_zone() -> Option<String> {
+    let options = date_time_format(&Object::new())?.resolved_options();
+    Reflect::get(&options, &"timeZone".into()).ok()?.as_string()
+}
+
+/// The time right now in `time_zone`, as `HH:MM`; `None` if the browser
+/// doesn't know the zone.
+pub fn local_time(time_zone: &str) -> Option<String> {
+    if time_zone.is_empty() {
+        return None;
+    }
+    let options = Object::new();
+    let set = |name: &str, value: &str| Reflect::set(&options, &name.into(), &value.into());
+    set("timeZone", time_zone).ok()?;
+    set("hour", "2-digit").ok()?;
+    set("minute", "2-digit").ok()?;
+    set("hourCycle", "h23").ok()?;
+    let format: Function = date_time_format(&options)?.format();
+    format
+        .call1(&JsValue::NULL, &Date::new_0())
+        .ok()?
+        .as_string()
+}
+
+/// An `Intl.DateTimeFormat`, or `None` if `options` are ones it throws on,
+/// such as an unknown time zone.
+fn date_time_format(options: &Object) -> Option<js_sys::Intl::DateTimeFormat> {
+    let intl = Reflect::get(&js_sys::global(), &"Intl".into()).ok()?;
+    let constructor: Function = Reflect::get(&intl, &"DateTimeFormat".into())
+        .ok()?
+        .dyn_into()
+        .ok()?;
+    let args = Array::of2(&Array::new(), options);
+    Reflect::construct(&constructor, &args)
+        .ok()
+        .map(JsCast::unchecked_into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names_fall_back_to_the_username() {
+        let mut profile = Profile::default();
+        assert_eq!(display_name("alice", None), "alice");
+        assert_eq!(display_name("alice", Some(&profile)), "alice");
+        profile.display_name = "  ".into();
+        assert_eq!(display_name("alice", Some(&profile)), "alice");
+        profile.display_name = " Alice L. ".into();
+        assert_eq!(display_name("alice", Some(&profile)), "Alice L.");
+    }
+}
@@ -0,0 +1,4 @@
+//! Frames exchanged with the chat server, shared with it through the
+//! `yewchat-protocol` crate.
+
+pub use yewchat_protocol::*;
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+/// Tracks the last message id each user has read, per room.
+#[derive(Default)]
+pub struct ReadReceipts {
+    rooms: HashMap<String, HashMap<String, u64>>,
+}
+
+impl ReadReceipts {
+    /// Records that `user` has read up to `message_id` in `room`.
+    ///
+    /// Read positions only ever move forward; returns `true` if the stored
+    /// position changed.
+    pub fn mark_read(&mut self, room: &str, user: &str, message_id: u64) -> bool {
+        let last = self
+            .rooms
+            .entry(room.to_string())
+            .or_default()
+            .entry(user.to_string())
+            .or_default();
+        if message_id > *last {
+            *last = message_id;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Users whose read position in `room` is exactly `message_id`.
+    pub fn readers_of(&self, room: &str, message_id: u64) -> Vec<&str> {
+        let mut readers: Vec<&str> = self
+            .rooms
+            .get(room)
+            .map(|users| {
+                users
+                    .iter()
+                    .filter(|(_, last)| **last == message_id)
+                    .map(|(user, _)| user.as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        readers.sort_unstable();
+        readers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_positions_only_move_forward() {
+        let mut receipts = ReadReceipts::default();
+        assert!(receipts.mark_read("general", "bob", 5));
+        assert!(!receipts.mark_read("general", "bob", 3));
+        assert!(!receipts.mark_read("general", "bob", 5));
+        assert_eq!(receipts.readers_of("general", 5), vec!["bob"]);
+        assert!(receipts.readers_of("general", 3).is_empty());
+    }
+
+    #[test]
+    fn readers_are_per_room_and_sorted() {
+        let mut receipts = ReadReceipts::default();
+        receipts.mark_read("general", "carol", 2);
+        receipts.mark_read("general", "alice", 2);
+        receipts.mark_read("random", "bob", 2);
+        assert_eq!(receipts.readers_of("general", 2), vec!["alice", "carol"]);
+        assert_eq!(receipts.readers_of("random", 2), vec!["bob"]);
+        assert!(receipts.readers_of("nowhere", 2).is_empty());
+    }
+}
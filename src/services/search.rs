@@ -0,0 +1,59 @@
+//! Message search. Queries are parsed and matched in the protocol crate, so
+//! the server filters its history the same way.
+
+pub use crate::services::protocol::{is_image, SearchQuery};
+
+/// Splits `text` into `(is_match, segment)` pieces so every occurrence of
+/// one of `terms` can be highlighted.
+pub fn highlight<'a>(text: &'a str, terms: &[String]) -> Vec<(bool, &'a str)> {
+    let lower = text.to_lowercase();
+    // Lowercasing can change byte lengths outside ASCII; don't risk slicing
+    // mid-character in that case.
+    if lower.len() != text.len() {
+        return vec![(false, text)];
+    }
+
+    let mut ranges: Vec<(usize, usize)> = terms
+        .iter()
+        .filter(|t| !t.is_empty())
+        .flat_map(|t| {
+            lower
+                .match_indices(t.as_str())
+                .map(|(i, m)| (i, i + m.len()))
+        })
+        .collect();
+    ranges.sort_unstable();
+
+    let mut segments = vec![];
+    let mut pos = 0;
+    for (start, end) in ranges {
+        if end <= pos {
+            continue;
+        }
+        let start = start.max(pos);
+        if start > pos {
+            segments.push((false, &text[pos..start]));
+        }
+        segments.push((true, &text[start..end]));
+        pos = end;
+    }
+    if pos < text.len() {
+        segments.push((false, &text[pos..]));
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_overlapping_terms_once() {
+        let terms = vec!["cat".to_string(), "at".to_string()];
+        assert_eq!(
+            highlight("A Cat sat", &terms),
+            vec![(false, "A "), (true, "Cat"), (false, " s"), (true, "at")]
+        );
+        assert_eq!(highlight("dog", &terms), vec![(false, "dog")]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use gloo::events::EventListener;
+use gloo::storage::{LocalStorage, Storage};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{MessageEvent, MessagePort, SharedWorker};
+use yew_agent::Dispatched;
+
+use crate::services::connection_worker::{Port, Relay, TabMessage, WorkerMessage};
+use crate::services::event_bus::{ConnectionState, Event, EventBus, Request, SyncEvent};
+use crate::services::transport::TransportKind;
+
+/// The worker script, built from `worker.js`.
+const WORKER_URL: &str = "connection-worker.js";
+
+/// Why a frame wasn't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotConnected;
+
+/// One server connection shared by every open tab of the same user.
+///
+/// The connection lives in a shared worker, one per username, which
+/// registers with the server once however many tabs are open; see
+/// [`Relay`]. Where shared workers aren't available, or the script fails to
+/// load, the tab runs a relay of its own instead. Either way, everything
+/// the server says is published on the [`EventBus`].
+pub struct SharedConnection {
+    tab: Rc<Tab>,
+    _page_listeners: Vec<EventListener>,
+}
+
+struct Tab {
+    username: String,
+    transport: TransportKind,
+    /// Whether the server connection is up, as the relay last said.
+    connected: Rc<Cell<bool>>,
+    /// Where the relay's messages go to be published.
+    tx: UnboundedSender<WorkerMessage>,
+    link: RefCell<Option<Link>>,
+}
+
+/// How a tab reaches its relay.
+enum Link {
+    Worker {
+        port: MessagePort,
+        _worker: SharedWorker,
+        _listeners: Vec<EventListener>,
+    },
+    Page(Relay),
+}
+
+impl SharedConnection {
+    pub fn new(username: &str, transport: TransportKind) -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        let connected = Rc::new(Cell::new(false));
+        spawn_local(deliver(username.to_string(), connected.clone(), rx));
+        let tab = Rc::new(Tab {
+            username: username.to_string(),
+            transport,
+            connected,
+            tx,
+            link: RefCell::new(None),
+        });
+        Tab::open(&tab);
+
+        // Pages kept in the back/forward cache are hidden without being
+        // closed, and can come back.
+        let window = gloo::utils::window();
+        let on_hide = {
+            let tab = tab.clone();
+            EventListener::new(&window, "pagehide", move |_| tab.leave())
+        };
+        let on_show = {
+            let tab = tab.clone();
+            EventListener::new(&window, "pageshow", move |_| {
+                if tab.link.borrow().is_none() {
+                    Tab::open(&tab);
+                }
+            })
+        };
+
+        Self {
+            tab,
+            _page_listeners: vec![on_hide, on_show],
+        }
+    }
+
+    /// Sends a frame to the server. Fails if the connection isn't up, so
+    /// nobody thinks it went.
+    pub fn send(&self, frame: String) -> Result<(), NotConnected> {
+        if !self.tab.connected.get() {
+            return Err(NotConnected);
+        }
+        self.tab.post(TabMessage::Send(frame));
+        Ok(())
+    }
+
+    /// Tells this user's other tabs about a local state change.
+    pub fn share(&self, sync: SyncEvent) {
+        self.tab.post(TabMessage::Sync(sync));
+    }
+}
+
+impl Drop for SharedConnection {
+    fn drop(&mut self) {
+        self.tab.leave();
+    }
+}
+
+/// Where the token for our name is kept. Losing it loses the name, and any
+/// role that went with it, until the server restarts.
+fn session_key(username: &str) -> String {
+    format!("yewchat.session.{}", username)
+}
+
+impl Tab {
+    /// Starts or joins the user's connection worker, or relays from this
+    /// page if there's no such thing.
+    fn open(this: &Rc<Tab>) {
+        let name = format!("yewchat:{}", this.username);
+        let link = match SharedWorker::new_with_str(WORKER_URL, &name) {
+            Ok(worker) => Tab::worker_link(this, worker),
+            Err(e) => {
+                log::debug!("no shared worker, connecting from this tab: {:?}", e);
+                this.page_link()
+            }
+        };
+        *this.link.borrow_mut() = Some(link);
+        this.post(this.join());
+    }
+
+    fn worker_link(this: &Rc<Tab>, worker: SharedWorker) -> Link {
+        let port = worker.port();
+        let on_message = {
+            let tx = this.tx.clone();
+            EventListener::new(&port, "message", move |e| {
+                let data = e.unchecked_ref::<MessageEvent>().data();
+                match data.as_string().map(|s| serde_json::from_str(&s)) {
+                    Some(Ok(message)) => {
+                        let _ = tx.unbounded_send(message);
+                    }
+                    _ => log::error!("unreadable message from the connection worker"),
+                }
+            })
+        };
+        // The script didn't load, or the worker died: carry on without it.
+        let on_error = {
+            let tab = Rc::downgrade(this);
+            EventListener::new(&worker, "error", move |_| {
+                let tab = tab.clone();
+                // Not while this listener is running; replacing the link
+                // drops it.
+                spawn_local(async move {
+                    if let Some(tab) = tab.upgrade() {
+                        log::error!("the connection worker failed, connecting from this tab");
+                        *tab.link.borrow_mut() = Some(tab.page_link());
+                        tab.post(tab.join());
+                    }
+                });
+            })
+        };
+        port.start();
+        Link::Worker {
+            port,
+            _worker: worker,
+            _listeners: vec![on_message, on_error],
+        }
+    }
+
+    fn page_link(&self) -> Link {
+        let relay = Relay::default();
+        relay.attach(0, Port::Page(self.tx.clone()));
+        Link::Page(relay)
+    }
+
+    fn join(&self) -> TabMessage {
+        TabMessage::Join {
+            username: self.username.clone(),
+            transport: self.transport,
+            token: LocalStorage::get(session_key(&self.username)).ok(),
+        }
+    }
+
+    fn post(&self, message: TabMessage) {
+        match &*self.link.borrow() {
+            Some(Link::Worker { port, .. }) => {
+                let message = serde_json::to_string(&message).unwrap();
+                if let Err(e) = port.post_message(&message.into()) {
+                    log::error!("failed to reach the connection worker: {:?}", e);
+                }
+            }
+            Some(Link::Page(relay)) => relay.receive(0, message),
+            None => {}
+        }
+    }
+
+    /// Lets go of the connection, which closes if no other tab is using it.
+    fn leave(&self) {
+        self.post(TabMessage::Leave);
+        self.connected.set(false);
+        if let Some(Link::Worker { port, .. }) = self.link.borrow_mut().take() {
+            port.close();
+        }
+    }
+}
+
+/// Publishes what the relay says, keeping track of whether the connection
+/// is up and keeping session tokens rather than passing them on.
+async fn deliver(
+    username: String,
+    connected: Rc<Cell<bool>>,
+    mut messages: UnboundedReceiver<WorkerMessage>,
+) {
+    let mut event_bus = EventBus::dispatcher();
+    while let Some(message) = messages.next().await {
+        let events = match message {
+            WorkerMessage::Event(event) => vec![event],
+            WorkerMessage::Replay(events) => events,
+        };
+        for event in events {
+            match &event {
+                Event::Connection(state) => connected.set(*state == ConnectionState::Connected),
+                Event::Session(token) => {
+                    if let Err(e) = LocalStorage::set(session_key(&username), token) {
+                        log::error!("failed to keep the session token: {:?}", e);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            event_bus.send(Request::Publish(event));
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::channel::oneshot;
+use futures::stream::{LocalBoxStream, StreamExt};
+use gloo::events::EventListener;
+use js_sys::{Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{Crypto, EventSource, MessageEvent, Request, RequestInit, Response};
+
+use crate::services::transport::{Payload, Transport, TransportEvent, CLOSE_ABNORMAL};
+
+/// Frames from the server as Server-Sent Events on `{base}/events`, frames
+/// to it as HTTP POSTs to `{base}/send`.
+///
+/// Both carry a `client` query parameter so the server can tell which event
+/// stream a POST belongs to; anyone who knew it could send as us, so it's
+/// random. Plain HTTP gets through proxies and firewalls that block
+/// WebSockets.
+pub struct SseTransport {
+    base: String,
+    client: String,
+    source: Option<EventSource>,
+    /// Feeds the task that POSTs frames one at a time, so they arrive in
+    /// order. It holds them until the event stream is open, since the server
+    /// has nowhere to send the answers before that.
+    outgoing: Option<UnboundedSender<String>>,
+    _listeners: Vec<EventListener>,
+}
+
+impl SseTransport {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+            client: client_key(),
+            source: None,
+            outgoing: None,
+            _listeners: vec![],
+        }
+    }
+}
+
+// This runs in the connection worker as often as in a page, so it can't
+// go through `window`.
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = fetch)]
+    fn fetch_with_request(request: &Request) -> Promise;
+}
+
+/// 128 random bits in hex.
+fn client_key() -> String {
+    let mut key = [0u8; 16];
+    if let Err(e) = Reflect::get(&js_sys::global(), &"crypto".into()).and_then(|crypto| {
+        crypto
+            .unchecked_into::<Crypto>()
+            .get_random_values_with_u8_array(&mut key)
+    }) {
+        log::error!("sse: no secure randomness: {:?}", e);
+        key.iter_mut()
+            .for_each(|b| *b = (js_sys::Math::random() * 256.0) as u8);
+    }
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Transport for SseTransport {
+    fn connect(&mut self) -> LocalBoxStream<'static, TransportEvent> {
+        let (events, incoming) = mpsc::unbounded();
+        let url = format!("{}/events?client={}", self.base, self.client);
+        let source = match EventSource::new(&url) {
+            Ok(source) => source,
+            Err(e) => {
+                let _ = events.unbounded_send(TransportEvent::Closed {
+                    code: CLOSE_ABNORMAL,
+                    reason: format!("can't connect: {:?}", e),
+                    clean: false,
+                });
+                return incoming.boxed_local();
+            }
+        };
+
+        let (opened, open) = oneshot::channel();
+        let on_open = {
+            let events = events.clone();
+            let opened = RefCell::new(Some(opened));
+            EventListener::new(&source, "open", move |_| {
+                if let Some(opened) = opened.borrow_mut().take() {
+                    let _ = opened.send(());
+                }
+                let _ = events.unbounded_send(TransportEvent::Open);
+            })
+        };
+        let on_message = {
+            let events = events.clone();
+            EventListener::new(&source, "message", move |e| {
+                if let Some(data) = e.unchecked_ref::<MessageEvent>().data().as_string() {
+                    let _ = events.unbounded_send(TransportEvent::Frame(Payload::Text(data)));
+                }
+            })
+        };
+        // The browser retries a dropped event stream by itself; it's only
+        // over once the source says it's closed.
+        let on_error = {
+            let events = events.clone();
+            let source = source.clone();
+            EventListener::new(&source.clone(), "error", move |_| {
+                let event = if source.ready_state() == EventSource::CLOSED {
+                    TransportEvent::Closed {
+                        code: CLOSE_ABNORMAL,
+                        reason: "event stream closed".to_string(),
+                        clean: false,
+                    }
+                } else {
+                    TransportEvent::Error("event stream interrupted, retrying".to_string())
+                };
+                let _ = events.unbounded_send(event);
+            })
+        };
+
+        let (outgoing, mut frames) = mpsc::unbounded::<String>();
+        let send_url = format!("{}/send?client={}", self.base, self.client);
+        spawn_local(async move {
+            if open.await.is_err() {
+                return;
+            }
+            while let Some(frame) = frames.next().await {
+                let error = match post(&send_url, &frame).await {
+                    Ok(response) if response.ok() => continue,
+                    Ok(response) => format!("server said {}", response.status()),
+                    Err(e) => format!("{:?}", e),
+                };
+                let error = format!("failed to send: {}", error);
+                let _ = events.unbounded_send(TransportEvent::Error(error));
+            }
+        });
+
+        self._listeners = vec![on_open, on_message, on_error];
+        self.source = Some(source);
+        self.outgoing = Some(outgoing);
+        incoming.boxed_local()
+    }
+
+    fn send(&mut self, frame: Payload) -> Result<(), String> {
+        let frame = match frame {
+            Payload::Text(text) => text,
+            Payload::Binary(_) => return Err("event streams only carry text".to_string()),
+        };
+        let outgoing = self.outgoing.as_ref().ok_or("not connected")?;
+        outgoing
+            .unbounded_send(frame)
+            .map_err(|_| "the connection is closed".to_string())
+    }
+
+    /// Event streams have no close handshake, so `code` and `reason` only
+    /// make it to the log.
+    fn close(&mut self, code: u16, reason: &str) {
+        log::debug!("closing event stream: {} {}", code, reason);
+        self.outgoing = None;
+        if let Some(source) = self.source.take() {
+            source.close();
+        }
+    }
+}
+
+async fn post(url: &str, frame: &str) -> Result<Response, JsValue> {
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&frame.into());
+    let request = Request::new_with_str_and_init(url, &init)?;
+    request.headers().set("Content-Type", "application/json")?;
+    Ok(JsFuture::from(fetch_with_request(&request))
+        .await?
+        .unchecked_into())
+}
+
+impl Drop for SseTransport {
+    fn drop(&mut self) {
+        if let Some(source) = self.source.take() {
+            source.close();
+        }
+    }
+}
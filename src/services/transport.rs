@@ -0,0 +1,106 @@
+use futures::stream::LocalBoxStream;
+use serde::{Deserialize, Serialize};
+
+use crate::services::loopback::LoopbackTransport;
+use crate::services::sse::SseTransport;
+use crate::services::websocket::WebSocketTransport;
+
+/// Close code for a connection that is done with, see RFC 6455 §7.4.1.
+/// Browsers only let pages close with this one or 3000-4999.
+pub const CLOSE_NORMAL: u16 = 1000;
+/// Close code for a client that is leaving, like a closing tab. RFC 6455's
+/// 1001 says the same but browsers won't send it, so this is our own.
+pub const CLOSE_LEAVING: u16 = 4001;
+/// Close code for a connection that dropped without a close frame.
+pub const CLOSE_ABNORMAL: u16 = 1006;
+/// Close code for a connection the server stopped answering heartbeats on.
+pub const CLOSE_HEARTBEAT_TIMEOUT: u16 = 4000;
+
+const WEBSOCKET_URL: &str = "ws://127.0.0.1:8080";
+/// The chat server over plain HTTP, which is also where webhooks live.
+pub const HTTP_URL: &str = "http://127.0.0.1:8080";
+
+/// A frame as it travels on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// What a transport reports about its connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    Open,
+    /// A frame from the server, still encoded.
+    Frame(Payload),
+    /// Something went wrong but the connection may still be usable.
+    Error(String),
+    /// The connection is gone; nothing follows this.
+    Closed {
+        code: u16,
+        reason: String,
+        clean: bool,
+    },
+}
+
+/// A way of exchanging protocol frames with the server.
+///
+/// Transports only move frames; decoding them, heartbeats and reconnecting
+/// are left to [`Connection`](crate::services::connection::Connection).
+pub trait Transport {
+    /// Opens the connection, returning everything that happens on it.
+    fn connect(&mut self) -> LocalBoxStream<'static, TransportEvent>;
+    /// Queues a frame for the server. Frames sent while still connecting go
+    /// out once the connection opens.
+    fn send(&mut self, frame: Payload) -> Result<(), String>;
+    fn close(&mut self, code: u16, reason: &str);
+    /// Whether binary frames get through, so a binary encoding can be
+    /// offered to the server.
+    fn supports_binary(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransportKind {
+    WebSocket,
+    /// Server-Sent Events down, HTTP POST up, for networks that block
+    /// WebSockets.
+    Sse,
+    /// No server at all; see [`LoopbackTransport`].
+    Loopback,
+}
+
+impl TransportKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "websocket" | "ws" => Some(TransportKind::WebSocket),
+            "sse" => Some(TransportKind::Sse),
+            "loopback" | "offline" => Some(TransportKind::Loopback),
+            _ => None,
+        }
+    }
+
+    /// The transport named by `?transport=` in the page URL, WebSocket if
+    /// there is none.
+    pub fn from_query() -> Self {
+        let search = gloo::utils::window()
+            .location()
+            .search()
+            .unwrap_or_default();
+        search
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|pair| pair.strip_prefix("transport="))
+            .find_map(Self::parse)
+            .unwrap_or(TransportKind::WebSocket)
+    }
+
+    pub fn open(self) -> Box<dyn Transport> {
+        match self {
+            TransportKind::WebSocket => Box::new(WebSocketTransport::new(WEBSOCKET_URL)),
+            TransportKind::Sse => Box::new(SseTransport::new(HTTP_URL)),
+            TransportKind::Loopback => Box::new(LoopbackTransport::default()),
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+/// Slice of a list that should actually be in the DOM, with the space taken
+/// by the rows skipped on either side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub start: usize,
+    pub end: usize,
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Windowing for long lists with rows of varying height.
+///
+/// Rows are identified by a stable key so measured heights survive rows
+/// being inserted or filtered; rows that haven't been measured yet count as
+/// `estimate` pixels tall.
+pub struct VirtualList {
+    heights: HashMap<u64, f64>,
+    estimate: f64,
+    overscan: usize,
+}
+
+impl VirtualList {
+    pub fn new(estimate: f64, overscan: usize) -> Self {
+        Self {
+            heights: HashMap::new(),
+            estimate,
+            overscan,
+        }
+    }
+
+    fn height(&self, key: u64) -> f64 {
+        self.heights.get(&key).copied().unwrap_or(self.estimate)
+    }
+
+    /// Records the rendered height of a row; returns `true` if it differs
+    /// from what the layout assumed.
+    pub fn set_height(&mut self, key: u64, height: f64) -> bool {
+        let changed = (self.height(key) - height).abs() > 0.5;
+        self.heights.insert(key, height);
+        changed
+    }
+
+    /// Distance from the top of the list to the row with `key`.
+    pub fn offset_of(&self, keys: &[u64], key: u64) -> Option<f64> {
+        let index = keys.iter().position(|k| *k == key)?;
+        Some(keys[..index].iter().map(|k| self.height(*k)).sum())
+    }
+
+    /// Rows of `keys` visible in a viewport of `viewport` pixels scrolled to
+    /// `scroll_top`, plus `overscan` rows either side. Scroll offsets past the
+    /// end are clamped, so `f64::MAX` means "scrolled to the bottom".
+    pub fn window(&self, keys: &[u64], scroll_top: f64, viewport: f64) -> Window {
+        let heights: Vec<f64> = keys.iter().map(|k| self.height(*k)).collect();
+        let total: f64 = heights.iter().sum();
+        let top = scroll_top.min(total - viewport).max(0.0);
+        let bottom = top + viewport;
+
+        let mut offset = 0.0;
+        let mut first = keys.len();
+        let mut last = keys.len();
+        for (i, h) in heights.iter().enumerate() {
+            if first == keys.len() && offset + h > top {
+                first = i;
+            }
+            if offset >= bottom {
+                last = i;
+                break;
+            }
+            offset += h;
+        }
+
+        let start = first.min(last).saturating_sub(self.overscan);
+        let end = (last + self.overscan).min(keys.len());
+        Window {
+            start,
+            end,
+            before: heights[..start].iter().sum(),
+            after: heights[end..].iter().sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW: f64 = 80.0;
+    const VIEWPORT: f64 = 800.0;
+    const OVERSCAN: usize = 5;
+
+    fn keys(n: u64) -> Vec<u64> {
+        (1..=n).collect()
+    }
+
+    #[test]
+    fn window_covers_viewport_plus_overscan() {
+        let list = VirtualList::new(ROW, OVERSCAN);
+        let keys = keys(100);
+
+        let w = list.window(&keys, 0.0, VIEWPORT);
+        assert_eq!((w.start, w.end), (0, 10 + OVERSCAN));
+        assert_eq!(w.before, 0.0);
+        assert_eq!(w.after, (100 - w.end) as f64 * ROW);
+
+        let w = list.window(&keys, 40.0 * ROW, VIEWPORT);
+        assert_eq!((w.start, w.end), (40 - OVERSCAN, 50 + OVERSCAN));
+        assert_eq!(
+            w.before + w.after + (w.end - w.start) as f64 * ROW,
+            100.0 * ROW
+        );
+    }
+
+    #[test]
+    fn scrolled_past_end_shows_last_rows() {
+        let list = VirtualList::new(ROW, OVERSCAN);
+        let keys = keys(100);
+
+        let w = list.window(&keys, f64::MAX, VIEWPORT);
+        assert_eq!((w.start, w.end), (90 - OVERSCAN, 100));
+        assert_eq!(w.after, 0.0);
+    }
+
+    #[test]
+    fn short_lists_render_everything() {
+        let list = VirtualList::new(ROW, OVERSCAN);
+        let w = list.window(&keys(3), f64::MAX, VIEWPORT);
+        assert_eq!((w.start, w.end, w.before, w.after), (0, 3, 0.0, 0.0));
+
+        let w = list.window(&[], 0.0, VIEWPORT);
+        assert_eq!((w.start, w.end), (0, 0));
+    }
+
+    #[test]
+    fn measured_heights_shift_the_window() {
+        let mut list = VirtualList::new(ROW, 0);
+        let keys = keys(100);
+
+        // A tall image at the top pushes everything after it down.
+        assert!(list.set_height(1, 10.0 * ROW));
+        assert!(!list.set_height(1, 10.0 * ROW));
+
+        let w = list.window(&keys, 10.0 * ROW, VIEWPORT);
+        assert_eq!((w.start, w.end), (1, 11));
+        assert_eq!(w.before, 10.0 * ROW);
+    }
+
+    /// Rows whose pixels `top..top + VIEWPORT` overlap, worked out the
+    /// slow way.
+    fn visible(list: &VirtualList, keys: &[u64], top: f64) -> (usize, usize) {
+        let mut offset = 0.0;
+        let mut rows = vec![];
+        for (i, k) in keys.iter().enumerate() {
+            let height = list.height(*k);
+            if offset + height > top && offset < top + VIEWPORT {
+                rows.push(i);
+            }
+            offset += height;
+        }
+        (rows[0], rows[rows.len() - 1] + 1)
+    }
+
+    /// Rows rendered by one window and not the other: what a scroll step
+    /// adds to the DOM plus what it takes out.
+    fn churn(a: &Window, b: &Window) -> usize {
+        let overlap = a.end.min(b.end).saturating_sub(a.start.max(b.start));
+        (a.end - a.start - overlap) + (b.end - b.start - overlap)
+    }
+
+    /// Scrolling through 10k messages renders the rows in view and a few
+    /// either side, however long the history, and each scroll step only
+    /// swaps the rows that scrolled in or out.
+    #[test]
+    fn windows_stay_small_with_10k_messages() {
+        let mut list = VirtualList::new(ROW, OVERSCAN);
+        let keys = keys(10_000);
+        for k in keys.iter().step_by(7) {
+            list.set_height(*k, 3.0 * ROW);
+        }
+        let total: f64 = keys.iter().map(|k| list.height(*k)).sum();
+        let step = VIEWPORT / 4.0;
+
+        // A few consecutive scroll steps at the top, middle and end of history.
+        for region in [0.0, total / 2.0, total - VIEWPORT - 10.0 * step] {
+            for i in 0..=10 {
+                let top = region + i as f64 * step;
+                let w = list.window(&keys, top, VIEWPORT);
+                let (first, last) = visible(&list, &keys, top);
+                assert!(
+                    w.start <= first && last <= w.end,
+                    "{:?} misses {}..{}",
+                    w,
+                    first,
+                    last
+                );
+                assert!(
+                    first - w.start <= OVERSCAN && w.end - last <= OVERSCAN + 1,
+                    "{:?}",
+                    w
+                );
+                assert_eq!(
+                    w.before,
+                    keys[..w.start].iter().map(|k| list.height(*k)).sum::<f64>()
+                );
+            }
+        }
+
+        // Then 200 steps on from the middle. No row is shorter than `ROW`,
+        // so a window holds at most `max_rows`, which with a spacer either
+        // side makes `max_rows + 2` nodes, and a step brings at most half of
+        // `max_churn` in.
+        let step = VIEWPORT / 2.0;
+        let max_rows = (VIEWPORT / ROW) as usize + 1 + 2 * OVERSCAN;
+        let max_churn = 2 * ((step / ROW) as usize + 1);
+        let first = list.window(&keys, total / 2.0, VIEWPORT);
+        let mut previous = first;
+        let mut churned = 0;
+        for i in 1..=200 {
+            let top = total / 2.0 + i as f64 * step;
+            let w = list.window(&keys, top, VIEWPORT);
+            let nodes = w.end - w.start + 2;
+            assert!(nodes <= max_rows + 2, "{} nodes at {}", nodes, top);
+            assert!(
+                churn(&previous, &w) <= max_churn,
+                "{:?} -> {:?}",
+                previous,
+                w
+            );
+            churned += churn(&previous, &w);
+            previous = w;
+        }
+        // Every row scrolled past went in once and came out once.
+        let passed = previous.start - first.start;
+        assert!(
+            churned <= 2 * passed + 2 * max_rows,
+            "{} rows changed scrolling past {}",
+            churned,
+            passed
+        );
+    }
+}
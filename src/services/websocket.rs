@@ -1,53 +1,146 @@
-use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
-use reqwasm::websocket::{futures::WebSocket, Message};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-use wasm_bindgen_futures::spawn_local;
-use yew_agent::Dispatched;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::stream::{LocalBoxStream, StreamExt};
+use gloo::events::EventListener;
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
 
-use crate::services::event_bus::{EventBus, Request};
+use crate::services::transport::{Payload, Transport, TransportEvent, CLOSE_ABNORMAL};
 
-pub struct WebsocketService {
-    pub tx: Sender<String>,
+/// Frames over a browser WebSocket.
+pub struct WebSocketTransport {
+    url: String,
+    socket: Option<WebSocket>,
+    /// Frames sent before the socket opened.
+    pending: Rc<RefCell<Vec<Payload>>>,
+    _listeners: Vec<EventListener>,
 }
 
-impl WebsocketService {
-    pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
-
-        let (mut write, mut read) = ws.split();
+impl WebSocketTransport {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            socket: None,
+            pending: Rc::default(),
+            _listeners: vec![],
+        }
+    }
 
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
-        let mut event_bus = EventBus::dispatcher();
+    fn listen(
+        &self,
+        ws: &WebSocket,
+        events: &UnboundedSender<TransportEvent>,
+        event_type: &'static str,
+        to_event: impl Fn(&web_sys::Event) -> Option<TransportEvent> + 'static,
+    ) -> EventListener {
+        let events = events.clone();
+        EventListener::new(ws, event_type, move |e| {
+            if let Some(event) = to_event(e) {
+                let _ = events.unbounded_send(event);
+            }
+        })
+    }
+}
 
-        spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
-                log::debug!("got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+impl Transport for WebSocketTransport {
+    fn connect(&mut self) -> LocalBoxStream<'static, TransportEvent> {
+        let (events, incoming) = mpsc::unbounded();
+        let ws = match WebSocket::new(&self.url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                let _ = events.unbounded_send(TransportEvent::Closed {
+                    code: CLOSE_ABNORMAL,
+                    reason: format!("can't connect: {:?}", e),
+                    clean: false,
+                });
+                return incoming.boxed_local();
             }
-        });
+        };
+        ws.set_binary_type(BinaryType::Arraybuffer);
 
-        spawn_local(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(data)) => {
-                        log::debug!("from websocket: {}", data);
-                        event_bus.send(Request::EventBusMsg(data));
-                    }
-                    Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
-                            log::debug!("from websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("ws: {:?}", e)
+        let on_open = {
+            let ws = ws.clone();
+            let pending = self.pending.clone();
+            self.listen(&ws.clone(), &events, "open", move |_| {
+                for frame in pending.borrow_mut().drain(..) {
+                    if let Err(e) = send(&ws, &frame) {
+                        log::error!("ws: failed to send queued frame: {:?}", e);
                     }
                 }
-            }
-            log::debug!("WebSocket Closed");
+                Some(TransportEvent::Open)
+            })
+        };
+        let on_message = self.listen(&ws, &events, "message", |e| {
+            let data = e.unchecked_ref::<MessageEvent>().data();
+            let payload = match data.as_string() {
+                Some(text) => Some(Payload::Text(text)),
+                None => data
+                    .dyn_into::<js_sys::ArrayBuffer>()
+                    .ok()
+                    .map(|b| Payload::Binary(js_sys::Uint8Array::new(&b).to_vec())),
+            };
+            payload.map(TransportEvent::Frame)
+        });
+        // Browsers don't say what went wrong; a close event follows if the
+        // socket is done for.
+        let on_error = self.listen(&ws, &events, "error", |_| {
+            Some(TransportEvent::Error("WebSocket error".to_string()))
         });
+        let on_close = self.listen(&ws, &events, "close", |e| {
+            let e = e.unchecked_ref::<CloseEvent>();
+            Some(TransportEvent::Closed {
+                code: e.code(),
+                reason: e.reason(),
+                clean: e.was_clean(),
+            })
+        });
+
+        self._listeners = vec![on_open, on_message, on_error, on_close];
+        self.socket = Some(ws);
+        incoming.boxed_local()
+    }
+
+    fn send(&mut self, frame: Payload) -> Result<(), String> {
+        let ws = self.socket.as_ref().ok_or("not connected")?;
+        match ws.ready_state() {
+            WebSocket::CONNECTING => {
+                self.pending.borrow_mut().push(frame);
+                Ok(())
+            }
+            WebSocket::OPEN => send(ws, &frame).map_err(|e| format!("{:?}", e)),
+            _ => Err("the connection is closed".to_string()),
+        }
+    }
+
+    fn close(&mut self, code: u16, reason: &str) {
+        if let Some(ws) = self.socket.take() {
+            // Only fails for codes or reasons the browser won't send; the
+            // socket still has to go.
+            if let Err(e) = ws.close_with_code_and_reason(code, reason) {
+                log::error!("ws: failed to close with {}: {:?}", code, e);
+                let _ = ws.close();
+            }
+        }
+    }
+
+    fn supports_binary(&self) -> bool {
+        true
+    }
+}
+
+fn send(ws: &WebSocket, frame: &Payload) -> Result<(), wasm_bindgen::JsValue> {
+    match frame {
+        Payload::Text(text) => ws.send_with_str(text),
+        Payload::Binary(bytes) => ws.send_with_u8_array(bytes),
+    }
+}
 
-        Self { tx: in_tx }
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        if let Some(ws) = self.socket.take() {
+            let _ = ws.close();
+        }
     }
 }
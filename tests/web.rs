@@ -0,0 +1,88 @@
+//! Browser tests that mount the whole app against the mock server.
+//!
+//! They expect it to be replaying `tests/fixtures/chat.jsonl` where the client
+//! connects to:
+//!
+//! ```text
+//! cargo run -p yewchat-server --bin mock-server -- tests/fixtures/chat.jsonl &
+//! wasm-pack test --headless --firefox
+//! ```
+#![cfg(target_arch = "wasm32")]
+
+use gloo::utils::{body, document};
+use js_sys::Promise;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::*;
+use web_sys::{Event, EventInit, HtmlButtonElement, HtmlInputElement};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+async fn sleep(ms: i32) {
+    let promise = Promise::new(&mut |resolve, _| {
+        gloo::utils::window()
+            .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms)
+            .unwrap();
+    });
+    JsFuture::from(promise).await.unwrap();
+}
+
+/// Waits up to ten seconds for `text` to show up on the page. Connecting
+/// takes a moment: the test server has no connection worker, so the tab
+/// only connects by itself once starting one has failed.
+async fn wait_for_text(text: &str) {
+    for _ in 0..100 {
+        if body().text_content().unwrap_or_default().contains(text) {
+            return;
+        }
+        sleep(100).await;
+    }
+    panic!("{:?} never appeared", text);
+}
+
+fn input(placeholder: &str) -> HtmlInputElement {
+    document()
+        .query_selector(&format!("input[placeholder='{}']", placeholder))
+        .unwrap()
+        .unwrap_or_else(|| panic!("no input {:?}", placeholder))
+        .unchecked_into()
+}
+
+fn button(label: &str) -> HtmlButtonElement {
+    let buttons = document().query_selector_all("button").unwrap();
+    (0..buttons.length())
+        .filter_map(|i| buttons.item(i))
+        .find(|b| b.text_content().unwrap_or_default().contains(label))
+        .unwrap_or_else(|| panic!("no {:?} button", label))
+        .unchecked_into()
+}
+
+fn type_into(input: &HtmlInputElement, text: &str) {
+    input.set_value(text);
+    let init = EventInit::new();
+    init.set_bubbles(true);
+    let event = Event::new_with_event_init_dict("input", &init).unwrap();
+    input.dispatch_event(&event).unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn logs_in_and_chats() {
+    let root = document().create_element("div").unwrap();
+    body().append_child(&root).unwrap();
+    yew::start_app_in_element::<yewchat::Main>(root);
+    sleep(0).await;
+
+    type_into(&input("Enter your username"), "alice");
+    sleep(0).await;
+    button("Start Chatting!").click();
+
+    // The fixture greets us with the user list and a message from bob.
+    wait_for_text("hi alice").await;
+    assert!(body().text_content().unwrap().contains("bob"));
+
+    let composer = input("Type your message...");
+    type_into(&composer, "hello bob");
+    button("Send").click();
+    wait_for_text("hello bob").await;
+    assert_eq!(composer.value(), "");
+}
@@ -9,10 +9,14 @@ module.exports = {
     devServer: {
         port: 8000,
     },
-    entry: './bootstrap.js',
+    entry: {
+        yewchat: './bootstrap.js',
+        // Workers have no document to load chunks with.
+        'connection-worker': { import: './worker.js', chunkLoading: 'import-scripts' },
+    },
     output: {
         path: distPath,
-        filename: 'yewchat.js',
+        filename: '[name].js',
         webassemblyModuleFilename: 'yewchat_bg.wasm',
     },
     plugins: [
@@ -0,0 +1,10 @@
+// The shared worker holding the server connection for every tab of a user;
+// see `src/services/connection_worker.rs`. Tabs that connect while the
+// module is still loading are handed over once it's ready.
+const early = [];
+self.onconnect = (e) => early.push(e.ports[0]);
+
+import('./pkg').then((module) => {
+    self.onconnect = null;
+    module.run_connection_worker(early);
+});